@@ -0,0 +1,266 @@
+use crate::backends::HostBackend;
+use crate::net::{VTcpListener, VTcpStream};
+use crate::poll::watch_key;
+use crate::prelude::*;
+use crate::sockman_utils::*;
+use std::future::{poll_fn, Future};
+use std::io::{Read, Write};
+use std::net::SocketAddrV4;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+/* Async sockets:
+
+AsyncVTcpStream and AsyncVTcpListener sit on top of nonblocking VTcpStreams and VTcpListeners. An
+operation that would block registers the task's waker with the socket - the same watchers poll()
+uses, woken by the socket's buffers and by the listener's accept path - and returns Pending. Only
+std::task is involved, so any executor can drive them, and a task waiting on a socket holds no
+thread while it does. The waker goes in before the operation is tried a second time, so whatever
+makes the socket ready in between still wakes the task. Wakeups can be spurious - a read and a
+write waiting on the same socket both get woken when either side moves - and just lead to another try.
+
+Connecting works the same way, off the handshake's outcome instead of a buffer. The socket gives up
+on its own at the connection timeout, and dropping the future before then resets the connection.
+
+*/
+
+/// Runs an operation on a nonblocking socket, having the task woken up to try again if it would block
+fn poll_io<T>(
+    backend: &HostBackend,
+    sid: SocketId,
+    key: u64,
+    cx: &mut Context<'_>,
+    mut op: impl FnMut() -> Result<T>,
+) -> Poll<Result<T>> {
+    match op() {
+        Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+        outcome => return Poll::Ready(outcome),
+    }
+    let watched = backend.watch(sid, key, cx.waker());
+    match op() {
+        Err(e) if e.kind() == ErrorKind::WouldBlock => match watched {
+            Ok(()) => Poll::Pending,
+            Err(e) => Poll::Ready(Err(e)), //Socket went away - nothing will ever wake us up
+        },
+        outcome => Poll::Ready(outcome),
+    }
+}
+
+/// A connection for async code
+pub struct AsyncVTcpStream {
+    stream: VTcpStream,
+    read_key: u64, //Readers and writers can be different tasks, so each gets a waker of its own
+    write_key: u64,
+}
+
+impl AsyncVTcpStream {
+    /// Opens a connection without blocking on the handshake - see HostBackend::connect() for how it can fail
+    pub async fn connect(backend: &HostBackend, ip_addr: Ipv4Addr, port: u16) -> Result<AsyncVTcpStream> {
+        let (sid, handshake) = backend.begin_connect(ip_addr, port)?;
+        let connect = Connect {
+            backend: backend.clone(),
+            sid,
+            handshake,
+            key: watch_key(),
+            done: false,
+        };
+        connect.await
+    }
+    /// Makes a stream async, switching it to nonblocking
+    pub fn from_stream(stream: VTcpStream) -> Result<AsyncVTcpStream> {
+        stream.set_nonblocking(true)?;
+        Ok(AsyncVTcpStream {
+            stream,
+            read_key: watch_key(),
+            write_key: watch_key(),
+        })
+    }
+    pub fn sid(&self) -> SocketId {
+        self.stream.sid()
+    }
+    pub fn local_addr(&self) -> Result<SocketAddrV4> {
+        self.stream.local_addr()
+    }
+    pub fn peer_addr(&self) -> Result<SocketAddrV4> {
+        self.stream.peer_addr()
+    }
+    pub fn set_nodelay(&self, nodelay: bool) -> Result<()> {
+        self.stream.set_nodelay(nodelay)
+    }
+    /// Reads like Read::read() - 0 bytes means our partner closed its end
+    pub fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        let backend = self.stream.backend();
+        poll_io(backend, self.sid(), self.read_key, cx, || (&self.stream).read(buf))
+    }
+    /// Buffers as much of the input as fits, at least one byte
+    pub fn poll_write(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let backend = self.stream.backend();
+        poll_io(backend, self.sid(), self.write_key, cx, || (&self.stream).write(buf))
+    }
+    /// Shutting down writing finishes once everything buffered has been acknowledged and our FIN went out
+    pub fn poll_shutdown(&self, cx: &mut Context<'_>, how: How) -> Poll<Result<()>> {
+        let backend = self.stream.backend();
+        poll_io(backend, self.sid(), self.write_key, cx, || self.stream.shutdown(how))
+    }
+    pub async fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        poll_fn(|cx| self.poll_read(cx, buf)).await
+    }
+    pub async fn write(&self, buf: &[u8]) -> Result<usize> {
+        poll_fn(|cx| self.poll_write(cx, buf)).await
+    }
+    pub async fn write_all(&self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let written = self.write(buf).await?;
+            buf = &buf[written..];
+        }
+        Ok(())
+    }
+    /// Reads until our partner closes its end, returning how many bytes that took
+    pub async fn read_to_end(&self, out: &mut Vec<u8>) -> Result<usize> {
+        let mut buf = vec![0u8; 8192];
+        let start_len = out.len();
+        loop {
+            match self.read(&mut buf).await? {
+                0 => return Ok(out.len() - start_len),
+                read => out.extend_from_slice(&buf[..read]),
+            }
+        }
+    }
+    pub async fn shutdown(&self, how: How) -> Result<()> {
+        poll_fn(|cx| self.poll_shutdown(cx, how)).await
+    }
+    /// Closes the connection gracefully, finishing once our FIN went out
+    /// Dropping the stream closes it too, but leaves the waiting to a thread - see VTcpStream
+    pub async fn close(mut self) -> Result<()> {
+        self.shutdown(How::Both).await?;
+        self.stream.mark_closed();
+        Ok(())
+    }
+}
+
+impl Drop for AsyncVTcpStream {
+    fn drop(&mut self) {
+        let backend = self.stream.backend();
+        backend.unwatch(self.sid(), self.read_key);
+        backend.unwatch(self.sid(), self.write_key);
+    }
+}
+
+/// Waits on the handshake of a connection begun with HostBackend::begin_connect()
+struct Connect {
+    backend: HostBackend,
+    sid: SocketId,
+    handshake: Receiver<Result<()>>,
+    key: u64,
+    done: bool, //The handshake's outcome is in, so there's nothing to give up on anymore
+}
+
+impl Future for Connect {
+    type Output = Result<AsyncVTcpStream>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<AsyncVTcpStream>> {
+        let this = &mut *self;
+        let handshake = &this.handshake;
+        let outcome = ready!(poll_io(&this.backend, this.sid, this.key, cx, || {
+            match handshake.try_recv() {
+                Ok(outcome) => outcome,
+                Err(TryRecvError::Empty) => Err(Error::new(ErrorKind::WouldBlock, "Handshake still under way")),
+                Err(TryRecvError::Disconnected) => Err(Error::new(ErrorKind::TimedOut, "Connection timed out")),
+            }
+        }));
+        this.done = true;
+        this.backend.unwatch(this.sid, this.key);
+        Poll::Ready(outcome.and_then(|()| AsyncVTcpStream::from_stream(VTcpStream::from_sid(&this.backend, this.sid))))
+    }
+}
+
+impl Drop for Connect {
+    fn drop(&mut self) {
+        if !self.done {
+            self.backend.unwatch(self.sid, self.key);
+            let _ = self.backend.abort(self.sid); //Nobody's going to use the connection
+        }
+    }
+}
+
+/// A listening socket for async code
+pub struct AsyncVTcpListener {
+    listener: VTcpListener,
+    key: u64,
+}
+
+impl AsyncVTcpListener {
+    /// Starts listening on a port, with the default backlog - fails with AddrInUse if something is already listening there
+    pub fn bind(backend: &HostBackend, port: u16) -> Result<AsyncVTcpListener> {
+        let listener = VTcpListener::bind(backend, port)?;
+        listener.set_nonblocking(true)?;
+        Ok(AsyncVTcpListener {
+            listener,
+            key: watch_key(),
+        })
+    }
+    pub fn sid(&self) -> SocketId {
+        self.listener.sid()
+    }
+    pub fn local_addr(&self) -> Result<SocketAddrV4> {
+        self.listener.local_addr()
+    }
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<Result<(AsyncVTcpStream, SocketAddrV4)>> {
+        let backend = self.listener.backend();
+        let (stream, peer_addr) = ready!(poll_io(backend, self.sid(), self.key, cx, || self.listener.accept()))?;
+        Poll::Ready(AsyncVTcpStream::from_stream(stream).map(|stream| (stream, peer_addr)))
+    }
+    pub async fn accept(&self) -> Result<(AsyncVTcpStream, SocketAddrV4)> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+}
+
+impl Drop for AsyncVTcpListener {
+    fn drop(&mut self) {
+        self.listener.backend().unwatch(self.sid(), self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::task::{Wake, Waker};
+
+    /// Counts how many times it's been woken
+    #[derive(Default)]
+    struct CountingWaker {
+        wakes: AtomicUsize,
+    }
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.wakes.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_read_woken_by_data() {
+        let (host_a, sid_a, host_b, sid_b) = connected_pair("");
+        let stream = AsyncVTcpStream::from_stream(VTcpStream::from_sid(&host_a, sid_a)).unwrap();
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut cx = Context::from_waker(&waker);
+        let mut buf = [0; 16];
+        assert!(stream.poll_read(&mut cx, &mut buf).is_pending());
+        assert_eq!(counter.wakes.load(Ordering::SeqCst), 0);
+        host_b.tcp_send(sid_b, b"wake up".to_vec()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while counter.wakes.load(Ordering::SeqCst) == 0 {
+            assert!(Instant::now() < deadline, "Data came in without waking the task");
+            thread::sleep(Duration::from_millis(10));
+        }
+        match stream.poll_read(&mut cx, &mut buf) {
+            Poll::Ready(Ok(read)) => assert_eq!(&buf[..read], b"wake up"),
+            other => panic!("Retry after the wakeup didn't read anything: {other:?}"),
+        }
+    }
+}
@@ -1,11 +1,17 @@
+use crate::congestion::CongestionAlgorithm;
 use crate::conn_socket::ConnectionSocket;
+use crate::isn::IsnGenerator;
+use crate::poll::*;
 use crate::prelude::*;
 use crate::socket_manager::SocketManager;
 use crate::sockman_utils::*;
+use crate::tcp_config::{check_buffer_size, check_timer, Keepalive, TcpConfig};
 use crate::tcp_utils::*;
 use crate::utils::*;
 use crate::vnode_traits::*;
+use std::task::Waker;
 
+#[allow(clippy::large_enum_variant)] //Only ever one of these per node
 pub enum Backend {
     Host(HostBackend),
     Router(RouterBackend),
@@ -21,6 +27,8 @@ pub struct HostBackend {
     closed_sender: Arc<Sender<SocketId>>,
     ip_sender: Arc<Sender<PacketBasis>>,
     sid_assigner: Arc<SidAssigner>,
+    isn_generator: Arc<IsnGenerator>,
+    tcp_config: TcpConfig,
 }
 
 impl VnodeBackend for HostBackend {
@@ -47,6 +55,7 @@ impl HostBackend {
         forwarding_table: Arc<RwLock<ForwardingTable>>,
         socket_table: Arc<RwLock<SocketTable>>,
         ip_sender: Sender<PacketBasis>,
+        tcp_config: TcpConfig,
     ) -> HostBackend {
         let local_ip = interface_reps
             .read()
@@ -58,11 +67,16 @@ impl HostBackend {
         let (closed_send, closed_recv) = channel::<SocketId>();
         let closed_sender = Arc::new(closed_send);
         let sid_assigner: Arc<SidAssigner> = Arc::new(SidAssigner::new());
+        let isn_generator = Arc::new(IsnGenerator::new(tcp_config.isn_seed));
         let socket_manager = SocketManager::new(
+            Arc::clone(&interface_reps),
+            Arc::clone(&forwarding_table),
             Arc::clone(&socket_table),
             Arc::clone(&closed_sender),
             Arc::clone(&ip_sender),
             Arc::clone(&sid_assigner),
+            Arc::clone(&isn_generator),
+            tcp_config.clone(),
         );
         let socket_manager = Arc::new(Mutex::new(socket_manager));
         let socket_table_clone = Arc::clone(&socket_table);
@@ -76,6 +90,8 @@ impl HostBackend {
             closed_sender,
             ip_sender,
             sid_assigner,
+            isn_generator,
+            tcp_config,
         }
     }
     pub fn socket_table(&self) -> RwLockReadGuard<'_, SocketTable> {
@@ -84,148 +100,262 @@ impl HostBackend {
     fn socket_table_mut(&self) -> RwLockWriteGuard<'_, SocketTable> {
         self.socket_table.write().unwrap()
     }
-    fn sock_arc(&self, sid: &SocketId) -> Option<Arc<Mutex<ConnectionSocket>>> {
-        let s_table = self.socket_table();
-        match s_table.get(sid) {
-            Some(SocketEntry::Connection(s_ent)) => Some(Arc::clone(&s_ent.sock)),
-            Some(SocketEntry::Listener(_)) => None,
-            None => None,
+    /// Looks up the connection socket behind a socket ID, failing with InvalidInput if there isn't one
+    fn conn_sock(&self, sid: SocketId) -> Result<Arc<Mutex<ConnectionSocket>>> {
+        match self.socket_table().get(&sid) {
+            Some(SocketEntry::Connection(s_ent)) => Ok(Arc::clone(&s_ent.sock)),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Input socket ID does not match that of any connection sockets",
+            )),
         }
     }
-    pub fn listen(&self, port: u16) -> SocketId {
-        self.socket_manager.lock().unwrap().listen(port)
+    /// Listens on a port, answering with SYN cookies once backlog handshakes are under way
+    /// Fails with AddrInUse if something is already listening there
+    pub fn listen(&self, port: u16, backlog: usize) -> Result<SocketId> {
+        self.socket_manager.lock().unwrap().listen(port, backlog)
     }
     pub fn accept(&self, port: u16) {
         self.socket_manager.lock().unwrap().accept(port);
     }
-    pub fn accept1(&self, port: u16) -> Option<SocketId> {
+    /// Blocks until a connection comes in on <port>, unless the listener is nonblocking - then fails with WouldBlock if none has
+    pub fn accept1(&self, port: u16) -> Result<SocketId> {
         let conn_wait = {
             let mut sock_man = self.socket_manager.lock().unwrap();
-            sock_man.accept1(port) //Doesn't block
+            sock_man.accept1(port)? //Doesn't block
         };
-        if let Some(conn_wait) = conn_wait {
-            let sock_arc = conn_wait
-                .recv()
-                .expect("Error receiving arc of socket from sender");
-            let sid = ConnectionSocket::get_sid(sock_arc);
-            Some(sid)
-        } else {
-            None
-        }
+        let sock_arc = conn_wait
+            .recv()
+            .expect("Error receiving arc of socket from sender");
+        Ok(ConnectionSocket::get_sid(sock_arc))
+    }
+    /// Opens a connection, blocking until the handshake is done
+    /// Fails with HostUnreachable if there's no route, ConnectionRefused if we get reset and TimedOut if nobody answers
+    pub fn connect(&self, ip_addr: Ipv4Addr, port: u16) -> Result<SocketId> {
+        let (sid, handshake) = self.begin_connect(ip_addr, port)?;
+        // Wait for the handshake to finish - the socket retransmits our SYN in the meantime, and gives up at the connection timeout
+        handshake
+            .recv()
+            .unwrap_or_else(|_| Err(Error::new(ErrorKind::TimedOut, "Connection timed out")))
+            .map(|()| sid)
     }
-    pub fn connect(&self, ip_addr: Ipv4Addr, port: u16) -> SocketId {
+    /// Starts opening a connection without waiting on the handshake - its outcome comes through the returned receiver,
+    /// and anything watching the socket gets woken up once it's there
+    pub fn begin_connect(&self, ip_addr: Ipv4Addr, port: u16) -> Result<(SocketId, Receiver<Result<()>>)> {
+        // No point in sending SYNs we can't route
+        egress_interface(&self.interface_reps(), &self.forwarding_table(), &ip_addr)?;
         // Initialize connection
-        let (sid, sock) = self.init_new_conn(ip_addr, port);
-
-        // Create completion channel
-        let (tx, rx) = channel();
-
-        // Spawn monitoring thread
-        let sock_clone = Arc::clone(&sock);
-        thread::spawn(move || loop {
-            let state = {
-                let socket = sock_clone.lock().unwrap();
-                let state = socket.state.read().unwrap();
-                (*state).clone()
-            };
-
-            match state {
-                TcpState::Established => {
-                    tx.send(()).unwrap();
-                    break;
-                }
-                TcpState::Closed => {
-                    panic!("Connection failed - socket closed during handshake");
-                }
-                _ => {
-                    thread::sleep(Duration::from_micros(10));
-                    continue;
-                }
-            }
-        });
-
-        // Wait for completion
-        rx.recv().expect("Connection monitor thread died");
-
-        sid
+        self.init_new_conn(ip_addr, port)
     }
     fn init_new_conn(
         &self,
         dst_vip: Ipv4Addr,
         dst_port: u16,
-    ) -> (SocketId, Arc<Mutex<ConnectionSocket>>) {
-        let conn_src_addr = self.unused_tcp_addr();
-        let conn_dst_addr = TcpAddress::new(dst_vip, dst_port);
+    ) -> Result<(SocketId, Receiver<Result<()>>)> {
         let init_state = Arc::new(RwLock::new(TcpState::AwaitingRun));
+        let local_mss = mss_for_mtu(egress_mtu(
+            &self.interface_reps(),
+            &self.forwarding_table(),
+            &dst_vip,
+        ));
+        //The table stays locked from picking the port until the connection is in it, so nobody else gets the same one
+        let mut socket_table = self.socket_table_mut();
+        let conn_src_addr = self.unused_tcp_addr(&socket_table)?;
+        let conn_dst_addr = TcpAddress::new(dst_vip, dst_port);
+        let init_seq = self.isn_generator.generate(&conn_src_addr, &conn_dst_addr);
         let conn_sock = ConnectionSocket::new(
             init_state,
             conn_src_addr.clone(),
             conn_dst_addr.clone(),
+            init_seq,
             Arc::clone(&self.closed_sender),
             Arc::clone(&self.ip_sender),
+            local_mss,
+            self.tcp_config.clone(),
         );
         let pending_conn = PendingConn::new(conn_sock);
-        let mut socket_table = self.socket_table_mut();
         let sid = self.sid_assigner.assign_sid();
         let sock = pending_conn.start(&mut socket_table, sid);
-        ConnectionSocket::first_syn(sock.clone()); //Sends SYN message to start handshake
-        (sid, sock)
-    }
-    /// Generates a new unused TCP address on the local IP
-    fn unused_tcp_addr(&self) -> TcpAddress {
-        // Acquire local ip
-        let local_ip = self.local_ip;
-        let sock_table = self.socket_table.read().unwrap();
-        // Generate random ports until one is unused
-        let mut port = rand::thread_rng().gen_range(0..65535);
-        while sock_table.contains_key(&port) {
-            port = rand::thread_rng().gen_range(0..65535);
-        }
-        // Return a new TcpAddress with the local IP and a random port
-        TcpAddress::new(local_ip, port as u16)
-    }
-    pub fn find_conn_socket(
-        socket_table: RwLockReadGuard<SocketTable>,
-        dst_ip: &Ipv4Addr,
-        port: &u16,
-    ) -> Option<SocketId> {
-        for (sid, ent) in &*socket_table {
-            match ent {
-                SocketEntry::Connection(ent)
-                    if (ent.dst_addr.ip == *dst_ip) && (ent.dst_addr.port == *port) =>
-                {
-                    return Some(*sid);
-                }
-                SocketEntry::Connection(_) => {} //Not a matching socket
-                SocketEntry::Listener(_) => {}   //Don't care if it's a listener socket
-            }
-        }
-        None
+        let handshake = ConnectionSocket::first_syn(sock); //Sends SYN message to start handshake
+        Ok((sid, handshake))
+    }
+    /// Picks an ephemeral port on the local IP that no socket in the input table is using,
+    /// starting somewhere random and going up from there
+    fn unused_tcp_addr(&self, socket_table: &SocketTable) -> Result<TcpAddress> {
+        let (first, last) = (*EPHEMERAL_PORTS.start(), *EPHEMERAL_PORTS.end());
+        let offset = rand::thread_rng().gen_range(EPHEMERAL_PORTS) - first;
+        let range_len = last - first + 1;
+        (0..range_len)
+            .map(|i| first + (offset + i) % range_len)
+            .find(|&port| !socket_table.port_in_use(port))
+            .map(|port| TcpAddress::new(self.local_ip, port))
+            .ok_or_else(|| Error::new(ErrorKind::AddrNotAvailable, "No ephemeral ports left"))
     }
     //More to come
     pub fn tcp_send(&self, sid: SocketId, data: Vec<u8>) -> Result<u32> {
-        let sock = match self.sock_arc(&sid) {
-            Some(sock) => sock,
-            None => {
-                return Err(Error::new(
-                    ErrorKind::InvalidInput,
-                    "Input socket ID does not match that of any connection sockets",
-                ))
-            }
-        };
+        let sock = self.conn_sock(sid)?;
         ConnectionSocket::send(sock, data)
     }
     pub fn tcp_recieve(&self, sid: SocketId, bytes: u16) -> Result<Vec<u8>> {
-        let sock = match self.sock_arc(&sid) {
-            Some(sock) => sock,
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::receive(sock, bytes)
+    }
+    /// Switches the congestion control algorithm used by a connection socket
+    pub fn set_congestion_control(&self, sid: SocketId, algo: CongestionAlgorithm) -> Result<()> {
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::set_congestion_control(sock, algo);
+        Ok(())
+    }
+    /// Turns Nagle's algorithm off (nodelay = true) or back on for a connection socket
+    pub fn set_nodelay(&self, sid: SocketId, nodelay: bool) -> Result<()> {
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::set_nodelay(sock, nodelay);
+        Ok(())
+    }
+    /// Sets how long a connection socket's sent data may go unacknowledged before the connection gets aborted
+    pub fn set_user_timeout(&self, sid: SocketId, user_timeout: Duration) -> Result<()> {
+        let user_timeout = check_timer("User timeout", user_timeout)?;
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::set_user_timeout(sock, user_timeout);
+        Ok(())
+    }
+    /// Turns keepalive probing of an idle connection socket on or off
+    pub fn set_keepalive(&self, sid: SocketId, keepalive: bool) -> Result<()> {
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::set_keepalive(sock, keepalive);
+        Ok(())
+    }
+    /// Sets a connection socket's keepalive idle time, probe interval and probe count
+    pub fn set_keepalive_params(&self, sid: SocketId, params: Keepalive) -> Result<()> {
+        check_timer("Keepalive idle time", params.idle)?;
+        check_timer("Keepalive interval", params.interval)?;
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::set_keepalive_params(sock, params);
+        Ok(())
+    }
+    /// Returns congestion control and timer information about a connection socket
+    pub fn socket_stats(&self, sid: SocketId) -> Result<SocketStats> {
+        let sock = self.conn_sock(sid)?;
+        Ok(ConnectionSocket::stats(sock))
+    }
+    /// Shuts down reading, writing or both directions of a connection socket
+    /// Shutting down writing blocks until everything buffered has been sent and acknowledged, and our FIN has gone out
+    pub fn shutdown(&self, sid: SocketId, how: How) -> Result<()> {
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::shutdown(sock, how)
+    }
+    /// Drops whatever a connection socket still has buffered and resets the connection, without waiting on our partner
+    pub fn abort(&self, sid: SocketId) -> Result<()> {
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::reset(sock);
+        Ok(())
+    }
+    /// Sets how long closing a connection socket may wait on buffered data before resetting the connection
+    /// None waits for as long as it takes, zero resets right away
+    pub fn set_linger(&self, sid: SocketId, linger: Option<Duration>) -> Result<()> {
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::set_linger(sock, linger);
+        Ok(())
+    }
+    /// Sets how many bytes a connection socket buffers for sending
+    pub fn set_send_buffer(&self, sid: SocketId, size: usize) -> Result<()> {
+        let size = check_buffer_size(size)?;
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::set_send_buffer(sock, size);
+        Ok(())
+    }
+    /// Sets how many bytes a connection socket buffers for receiving, and so the largest window it advertises
+    pub fn set_recv_buffer(&self, sid: SocketId, size: usize) -> Result<()> {
+        let size = check_buffer_size(size)?;
+        let sock = self.conn_sock(sid)?;
+        ConnectionSocket::set_recv_buffer(sock, size);
+        Ok(())
+    }
+    /// Makes sends, receives and accepts on a socket fail with WouldBlock instead of blocking
+    pub fn set_nonblocking(&self, sid: SocketId, nonblocking: bool) -> Result<()> {
+        match self.socket_table().get(&sid) {
+            Some(SocketEntry::Connection(ent)) => ConnectionSocket::set_nonblocking(Arc::clone(&ent.sock), nonblocking),
+            Some(SocketEntry::Listener(ent)) => {
+                let mut sock_man = self.socket_manager.lock().unwrap();
+                sock_man.set_listener_nonblocking(ent.port, nonblocking);
+            }
             None => {
                 return Err(Error::new(
                     ErrorKind::InvalidInput,
-                    "Input socket ID does not match that of any connection sockets",
+                    "Input socket ID does not match that of any sockets",
                 ))
             }
+        }
+        Ok(())
+    }
+    /// Waits until at least one of the input sockets is ready for what it's asked about, or until the timeout passes
+    /// Returns the sockets that are ready and what for - nothing if the timeout passed first, and None waits forever
+    pub fn poll(&self, interests: &[(SocketId, Interest)], timeout: Option<Duration>) -> Result<Vec<(SocketId, Readiness)>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut targets = Vec::new();
+        for (sid, interest) in interests {
+            targets.push((*sid, *interest, self.poll_target(*sid)?));
+        }
+        let waiter = Arc::new(PollWaiter::new());
+        let waker = Waker::from(Arc::clone(&waiter));
+        let key = watch_key();
+        for (_, _, target) in &targets {
+            self.watch_target(target, key, Some(&waker));
+        }
+        let ready = loop {
+            waiter.reset(); //Anything that happens from here on wakes us back up
+            let ready: Vec<(SocketId, Readiness)> = targets
+                .iter()
+                .map(|(sid, interest, target)| (*sid, self.target_readiness(target).filter(*interest)))
+                .filter(|(_, readiness)| readiness.any())
+                .collect();
+            if !ready.is_empty() || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break ready;
+            }
+            waiter.wait(deadline);
         };
-        ConnectionSocket::receive(sock, bytes)
+        for (_, _, target) in &targets {
+            self.watch_target(target, key, None);
+        }
+        Ok(ready)
+    }
+    /// Has the waker woken whenever the socket might have become ready for something - see poll()
+    /// A waker already registered under the input key gets replaced
+    pub fn watch(&self, sid: SocketId, key: u64, waker: &Waker) -> Result<()> {
+        let target = self.poll_target(sid)?;
+        self.watch_target(&target, key, Some(waker));
+        Ok(())
+    }
+    /// Unregisters the waker under the input key - does nothing if the socket is already gone
+    pub fn unwatch(&self, sid: SocketId, key: u64) {
+        if let Ok(target) = self.poll_target(sid) {
+            self.watch_target(&target, key, None);
+        }
+    }
+    fn poll_target(&self, sid: SocketId) -> Result<PollTarget> {
+        match self.socket_table().get(&sid) {
+            Some(SocketEntry::Connection(ent)) => Ok(PollTarget::Connection(Arc::clone(&ent.sock))),
+            Some(SocketEntry::Listener(ent)) => Ok(PollTarget::Listener(ent.port)),
+            None => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Input socket ID does not match that of any sockets",
+            )),
+        }
+    }
+    /// Registers the waker with the target under the input key, or unregisters whatever is there if there's no waker
+    fn watch_target(&self, target: &PollTarget, key: u64, waker: Option<&Waker>) {
+        match (target, waker) {
+            (PollTarget::Connection(sock), Some(waker)) => ConnectionSocket::watch(Arc::clone(sock), key, waker),
+            (PollTarget::Connection(sock), None) => ConnectionSocket::unwatch(Arc::clone(sock), key),
+            (PollTarget::Listener(port), Some(waker)) => self.socket_manager.lock().unwrap().watch_listener(*port, key, waker),
+            (PollTarget::Listener(port), None) => self.socket_manager.lock().unwrap().unwatch_listener(*port, key),
+        }
+    }
+    fn target_readiness(&self, target: &PollTarget) -> Readiness {
+        match target {
+            PollTarget::Connection(sock) => ConnectionSocket::readiness(Arc::clone(sock)),
+            PollTarget::Listener(port) => self.socket_manager.lock().unwrap().listener_readiness(*port),
+        }
     }
     pub fn close(&self, sid: SocketId) -> Result<()> {
         let sock_ent = {
@@ -242,7 +372,7 @@ impl HostBackend {
         match sock_ent {
             SocketEntry::Connection(ent) => {
                 let sock = Arc::clone(&ent.sock);
-                ConnectionSocket::close(sock);
+                ConnectionSocket::close(sock)?;
             }
             SocketEntry::Listener(ent) => {
                 {
@@ -270,6 +400,12 @@ impl HostBackend {
     }
 }
 
+/// A socket poll() waits on
+enum PollTarget {
+    Connection(Arc<Mutex<ConnectionSocket>>),
+    Listener(u16), //Listeners live in the socket manager, by port
+}
+
 pub struct RouterBackend {
     interface_reps: Arc<RwLock<InterfaceTable>>,
     forwarding_table: Arc<RwLock<ForwardingTable>>,
@@ -308,3 +444,186 @@ impl RouterBackend {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::HostBackend;
+    use crate::vnode_traits::VnodeBackend;
+    use crate::net::VTcpStream;
+    use crate::poll::Interest;
+    use crate::prelude::*;
+    use crate::sockman_utils::{How, SocketEntry, SocketId, DEFAULT_BACKLOG, EPHEMERAL_PORTS};
+    use crate::tcp_utils::mss_for_mtu;
+    use crate::test_utils::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_connect_refused() {
+        let (host_a, _host_b) = host_pair("");
+        let err = host_a.connect(HOST_B, 4444).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn test_connect_unreachable() {
+        let (host_a, _host_b) = host_pair("");
+        let unreachable = Ipv4Addr::new(10, 7, 0, 1);
+        let err = host_a.connect(unreachable, 4444).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::HostUnreachable);
+        assert!(!host_a
+            .socket_table()
+            .values()
+            .any(|ent| matches!(ent, SocketEntry::Connection(ent) if ent.dst_addr.ip == unreachable)));
+    }
+
+    #[test]
+    fn test_connect_picks_ephemeral_port() {
+        let (host_a, sid_a, _host_b, _sid_b) = connected_pair("");
+        let socket_table = host_a.socket_table();
+        let Some(SocketEntry::Connection(ent)) = socket_table.get(&sid_a) else {
+            panic!("Expected a connection");
+        };
+        assert!(EPHEMERAL_PORTS.contains(&ent.src_addr.port));
+        assert!(socket_table.port_in_use(ent.src_addr.port));
+    }
+
+    #[test]
+    fn test_connect_timed_out() {
+        let (host_a, _host_b) = host_pair("tcp connect-timeout 300");
+        let start = Instant::now();
+        // Routed through our link, but nobody's there to answer
+        let err = host_a.connect(Ipv4Addr::new(10, 0, 0, 77), 4444).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_mss_follows_mtu() {
+        let (host_a, host_b) = host_pair_with_mtu(Some(576), "");
+        let (sid_a, sid_b) = connect_hosts(&host_a, &host_b);
+        // 536 bytes fit after the IP and TCP headers, less the timestamp and SACK options on every data segment
+        assert_eq!(mss_for_mtu(576), 536);
+        assert_eq!(host_a.socket_stats(sid_a).unwrap().mss, 536 - 12 - 28);
+        assert_eq!(host_b.socket_stats(sid_b).unwrap().mss, 536 - 12 - 28);
+    }
+
+    #[test]
+    fn test_write_after_shutdown() {
+        let (host_a, sid_a, _host_b, _sid_b) = connected_pair("");
+        let stream = VTcpStream::from_sid(&host_a, sid_a);
+        stream.shutdown(How::Write).unwrap();
+        assert_eq!(host_a.tcp_send(sid_a, b"late".to_vec()).unwrap_err().kind(), ErrorKind::Unsupported);
+        assert_eq!((&stream).write(b"late").unwrap_err().kind(), ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn test_read_after_shutdown_write() {
+        let (host_a, sid_a, host_b, sid_b) = connected_pair("");
+        let (stream_a, stream_b) = (VTcpStream::from_sid(&host_a, sid_a), VTcpStream::from_sid(&host_b, sid_b));
+        stream_a.shutdown(How::Write).unwrap();
+        let mut buf = [0; 16];
+        assert_eq!((&stream_b).read(&mut buf).unwrap(), 0); //Our FIN got there
+        (&stream_b).write_all(b"still open").unwrap();
+        let mut received = Vec::new();
+        while received.len() < 10 {
+            let n = (&stream_a).read(&mut buf).unwrap();
+            assert!(n > 0);
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(received, b"still open");
+        stream_b.close().unwrap();
+        assert_eq!((&stream_a).read(&mut buf).unwrap(), 0); //And now theirs
+    }
+
+    #[test]
+    fn test_shutdown_read_discards() {
+        let (host_a, sid_a, host_b, sid_b) = connected_pair("");
+        host_b.tcp_send(sid_b, b"unread".to_vec()).unwrap();
+        host_b.shutdown(sid_b, How::Write).unwrap(); //Returns once everything is acknowledged, so it's sitting in our buffer
+        host_a.shutdown(sid_a, How::Read).unwrap();
+        assert_eq!(host_a.tcp_recieve(sid_a, 16).unwrap_err().kind(), ErrorKind::Unsupported);
+        let stream_a = VTcpStream::from_sid(&host_a, sid_a);
+        assert_eq!((&stream_a).read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    /// Whether a socket leaves the socket table within a second
+    fn leaves_table(host: &HostBackend, sid: SocketId) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while host.socket_table().contains_key(&sid) {
+            if Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn test_abort_resets() {
+        let (host_a, sid_a, host_b, sid_b) = connected_pair("");
+        host_a.abort(sid_a).unwrap();
+        assert!(leaves_table(&host_a, sid_a));
+        assert!(leaves_table(&host_b, sid_b)); //Only our RST would have taken it down
+        assert_eq!(host_a.abort(sid_a).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_linger_runs_out() {
+        let (host_a, sid_a, host_b, sid_b) = connected_pair("tcp recv-buffer 1000");
+        host_a.set_linger(sid_a, Some(Duration::from_millis(200))).unwrap();
+        host_a.tcp_send(sid_a, vec![7; 5000]).unwrap(); //More than host_b will take without reading
+        let start = Instant::now();
+        assert_eq!(host_a.close(sid_a).unwrap_err().kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(leaves_table(&host_a, sid_a));
+        assert!(leaves_table(&host_b, sid_b));
+    }
+
+    #[test]
+    fn test_user_timeout_aborts() {
+        //Nothing else starts out with these directives - the pair's link stays cut once this is done
+        let (host_a, sid_a, host_b, _sid_b) = connected_pair("tcp user-timeout 300\ntcp send-buffer 1000");
+        let receiver = host_a.clone();
+        let receiving = thread::spawn(move || receiver.tcp_recieve(sid_a, 16));
+        host_b.down(String::from("if0"));
+        let start = Instant::now();
+        // More than fits in the send buffer, and none of it ever gets acknowledged
+        assert_eq!(host_a.tcp_send(sid_a, vec![7; 3000]).unwrap_err().kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert_eq!(receiving.join().unwrap().unwrap_err().kind(), ErrorKind::TimedOut);
+        assert!(leaves_table(&host_a, sid_a));
+    }
+
+    #[test]
+    fn test_poll_times_out_empty() {
+        let (host_a, sid_a, _host_b, _sid_b) = connected_pair("");
+        let start = Instant::now();
+        let ready = host_a.poll(&[(sid_a, Interest::Read)], Some(Duration::from_millis(100))).unwrap();
+        assert!(ready.is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_poll_wakes_on_data() {
+        let (host_a, sid_a, host_b, sid_b) = connected_pair("");
+        let poller = host_a.clone();
+        let polling = thread::spawn(move || poller.poll(&[(sid_a, Interest::Read)], Some(Duration::from_secs(5))));
+        thread::sleep(Duration::from_millis(50)); //Let it go to sleep first
+        host_b.tcp_send(sid_b, b"wake up".to_vec()).unwrap();
+        let ready = polling.join().unwrap().unwrap();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].0, sid_a);
+        assert!(ready[0].1.readable);
+    }
+
+    #[test]
+    fn test_nonblocking_would_block() {
+        let (host_a, sid_a, host_b, _sid_b) = connected_pair("");
+        host_a.set_nonblocking(sid_a, true).unwrap();
+        assert_eq!(host_a.tcp_recieve(sid_a, 16).unwrap_err().kind(), ErrorKind::WouldBlock);
+        let port = listen_port();
+        let listener = host_b.listen(port, DEFAULT_BACKLOG).unwrap();
+        host_b.set_nonblocking(listener, true).unwrap();
+        assert_eq!(host_b.accept1(port).unwrap_err().kind(), ErrorKind::WouldBlock);
+    }
+}
@@ -0,0 +1,415 @@
+use crate::congestion::*;
+use crate::seq_num::SeqNum;
+use crate::prelude::*;
+use crate::retransmission::RateSample;
+use std::collections::VecDeque;
+
+/* BBR v1 (draft-cardwell-iccrg-bbr-congestion-control-00):
+
+Instead of reacting to loss, BBR builds a model of the path out of delivery rate samples:
+- BtlBw: windowed max of the delivery rate over the last 10 round trips
+- RTprop: windowed min of the RTT over the last 10 seconds
+BDP = BtlBw * RTprop
+
+Segments are paced out at pacing_gain * BtlBw and at most cwnd_gain * BDP bytes are kept in flight.
+
+STATE MACHINE:
+- STARTUP: pacing_gain = cwnd_gain = 2/ln2, exits once BtlBw stops growing by 25% for 3 rounds
+- DRAIN: pacing_gain = 1 / (2/ln2), exits once in flight <= BDP
+- PROBE_BW: cycles pacing_gain through [1.25, 0.75, 1, 1, 1, 1, 1, 1], one phase per RTprop
+- PROBE_RTT: entered when RTprop hasn't been refreshed for 10 seconds, cwnd = 4 segments
+  for max(200ms, 1 round trip), then back to PROBE_BW (or STARTUP if the pipe was never filled)
+
+Loss is handled with packet conservation during fast recovery - the model itself isn't touched,
+so random loss doesn't collapse the sending rate.
+
+*/
+
+const HIGH_GAIN: f64 = 2.885; // 2/ln(2)
+const DRAIN_GAIN: f64 = 1.0 / HIGH_GAIN;
+const CWND_GAIN: f64 = 2.0;
+const PACING_GAIN_CYCLE: [f64; 8] = [1.25, 0.75, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+const BTL_BW_FILTER_LEN: u64 = 10; // In round trips
+const MIN_RTT_FILTER_LEN: Duration = Duration::from_secs(10);
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+const MIN_PIPE_CWND: usize = 4; // In segments
+const FULL_BW_THRESH: f64 = 1.25;
+const FULL_BW_COUNT: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BbrMode {
+    Startup,
+    Drain,
+    ProbeBw,
+    ProbeRtt,
+}
+
+#[derive(Debug)]
+pub struct Bbr {
+    mss: usize,
+    mode: BbrMode,
+    cwnd: usize,
+    prior_cwnd: usize, // cwnd to go back to after recovery/PROBE_RTT
+    pacing_rate: f64,  // Bytes per second
+    pacing_gain: f64,
+    cwnd_gain: f64,
+    btl_bw_filter: VecDeque<(u64, f64)>, // (round, delivery rate) samples within the filter window
+    min_rtt: Option<Duration>,
+    min_rtt_stamp: Instant,
+    round_count: u64,
+    next_round_delivered: u64,
+    round_start: bool,
+    full_bw: f64,
+    full_bw_count: u32,
+    filled_pipe: bool,
+    cycle_index: usize,
+    cycle_stamp: Instant,
+    probe_rtt_done_stamp: Option<Instant>,
+    probe_rtt_round_done: bool,
+    recover: Option<SeqNum>, // Some(snd.nxt at time of loss) while in fast recovery
+}
+
+impl Bbr {
+    pub fn new(mss: usize) -> Bbr {
+        let now = Instant::now();
+        Bbr {
+            mss,
+            mode: BbrMode::Startup,
+            cwnd: initial_window(mss),
+            prior_cwnd: 0,
+            pacing_rate: 0.0,
+            pacing_gain: HIGH_GAIN,
+            cwnd_gain: HIGH_GAIN,
+            btl_bw_filter: VecDeque::new(),
+            min_rtt: None,
+            min_rtt_stamp: now,
+            round_count: 0,
+            next_round_delivered: 0,
+            round_start: false,
+            full_bw: 0.0,
+            full_bw_count: 0,
+            filled_pipe: false,
+            cycle_index: 0,
+            cycle_stamp: now,
+            probe_rtt_done_stamp: None,
+            probe_rtt_round_done: false,
+            recover: None,
+        }
+    }
+    /// Current bottleneck bandwidth estimate in bytes per second
+    fn btl_bw(&self) -> f64 {
+        self.btl_bw_filter
+            .iter()
+            .map(|(_, bw)| *bw)
+            .fold(0.0, f64::max)
+    }
+    /// Estimated bandwidth delay product in bytes, if we have a model yet
+    fn bdp(&self) -> Option<f64> {
+        let min_rtt = self.min_rtt?;
+        match self.btl_bw() {
+            bw if bw > 0.0 => Some(bw * min_rtt.as_secs_f64()),
+            _ => None,
+        }
+    }
+    fn min_pipe_cwnd(&self) -> usize {
+        MIN_PIPE_CWND * self.mss
+    }
+    fn update_round(&mut self, rs: &RateSample) {
+        self.round_start = rs.prior_delivered >= self.next_round_delivered;
+        if self.round_start {
+            self.next_round_delivered = rs.delivered;
+            self.round_count += 1;
+        }
+    }
+    fn update_btl_bw(&mut self, rs: &RateSample) {
+        if !rs.is_app_limited || rs.delivery_rate >= self.btl_bw() {
+            self.btl_bw_filter.push_back((self.round_count, rs.delivery_rate));
+        }
+        while let Some((round, _)) = self.btl_bw_filter.front() {
+            if round + BTL_BW_FILTER_LEN <= self.round_count {
+                self.btl_bw_filter.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+    fn check_full_pipe(&mut self, rs: &RateSample) {
+        if self.filled_pipe || !self.round_start || rs.is_app_limited {
+            return;
+        }
+        let btl_bw = self.btl_bw();
+        if btl_bw >= self.full_bw * FULL_BW_THRESH {
+            // Still growing
+            self.full_bw = btl_bw;
+            self.full_bw_count = 0;
+            return;
+        }
+        self.full_bw_count += 1;
+        self.filled_pipe = self.full_bw_count >= FULL_BW_COUNT;
+    }
+    fn check_drain(&mut self, in_flight: usize, now: Instant) {
+        if self.mode == BbrMode::Startup && self.filled_pipe {
+            self.mode = BbrMode::Drain;
+            self.pacing_gain = DRAIN_GAIN;
+            self.cwnd_gain = HIGH_GAIN;
+        }
+        if self.mode == BbrMode::Drain && self.bdp().is_none_or(|bdp| in_flight as f64 <= bdp) {
+            self.enter_probe_bw(now);
+        }
+    }
+    fn enter_probe_bw(&mut self, now: Instant) {
+        self.mode = BbrMode::ProbeBw;
+        self.cwnd_gain = CWND_GAIN;
+        // Start at a random phase other than the draining one
+        let index = rand::thread_rng().gen_range(0..PACING_GAIN_CYCLE.len() - 1);
+        self.cycle_index = if index == 0 { 0 } else { index + 1 };
+        self.pacing_gain = PACING_GAIN_CYCLE[self.cycle_index];
+        self.cycle_stamp = now;
+    }
+    fn advance_cycle_phase(&mut self, in_flight: usize, now: Instant) {
+        if self.mode != BbrMode::ProbeBw {
+            return;
+        }
+        let min_rtt = self.min_rtt.unwrap_or_default();
+        let full_length = now.duration_since(self.cycle_stamp) > min_rtt;
+        let bdp = self.bdp().unwrap_or(0.0);
+        let next_phase = if self.pacing_gain > 1.0 {
+            // Probing for more bandwidth - keep going until the extra data is actually in flight
+            full_length && in_flight as f64 >= self.pacing_gain * bdp
+        } else if self.pacing_gain < 1.0 {
+            // Draining the queue we just built - stop as soon as it's gone
+            full_length || in_flight as f64 <= bdp
+        } else {
+            full_length
+        };
+        if next_phase {
+            self.cycle_index = (self.cycle_index + 1) % PACING_GAIN_CYCLE.len();
+            self.pacing_gain = PACING_GAIN_CYCLE[self.cycle_index];
+            self.cycle_stamp = now;
+        }
+    }
+    fn update_min_rtt(&mut self, rtt: Duration, now: Instant) {
+        let expired = now.duration_since(self.min_rtt_stamp) > MIN_RTT_FILTER_LEN;
+        if self.min_rtt.is_none_or(|min_rtt| rtt <= min_rtt) || expired {
+            self.min_rtt = Some(rtt);
+            self.min_rtt_stamp = now;
+        }
+        if expired && self.mode != BbrMode::ProbeRtt {
+            self.mode = BbrMode::ProbeRtt;
+            self.pacing_gain = 1.0;
+            self.cwnd_gain = 1.0;
+            self.prior_cwnd = cmp::max(self.prior_cwnd, self.cwnd);
+            self.probe_rtt_done_stamp = None;
+        }
+    }
+    fn handle_probe_rtt(&mut self, rs: &RateSample, in_flight: usize, now: Instant) {
+        if self.mode != BbrMode::ProbeRtt {
+            return;
+        }
+        match self.probe_rtt_done_stamp {
+            None if in_flight <= self.min_pipe_cwnd() => {
+                self.probe_rtt_done_stamp = Some(now + PROBE_RTT_DURATION);
+                self.probe_rtt_round_done = false;
+                self.next_round_delivered = rs.delivered;
+            }
+            None => {}
+            Some(done_stamp) => {
+                if self.round_start {
+                    self.probe_rtt_round_done = true;
+                }
+                if self.probe_rtt_round_done && now > done_stamp {
+                    self.min_rtt_stamp = now;
+                    self.cwnd = cmp::max(self.cwnd, self.prior_cwnd);
+                    self.prior_cwnd = 0;
+                    if self.filled_pipe {
+                        self.enter_probe_bw(now);
+                    } else {
+                        self.mode = BbrMode::Startup;
+                        self.pacing_gain = HIGH_GAIN;
+                        self.cwnd_gain = HIGH_GAIN;
+                    }
+                }
+            }
+        }
+    }
+    fn set_pacing_rate(&mut self) {
+        let rate = self.pacing_gain * self.btl_bw();
+        if rate > 0.0 && (self.filled_pipe || rate > self.pacing_rate) {
+            self.pacing_rate = rate;
+        }
+    }
+    fn set_cwnd(&mut self, acked: usize, in_flight: usize) {
+        if self.recover.is_some() {
+            // Packet conservation - only send as much as just left the network
+            self.cwnd = cmp::max(self.cwnd, in_flight + acked);
+        } else {
+            match self.bdp() {
+                Some(bdp) => {
+                    let target = (self.cwnd_gain * bdp) as usize + 3 * self.mss;
+                    if self.filled_pipe {
+                        self.cwnd = cmp::min(self.cwnd + acked, target);
+                    } else if self.cwnd < target {
+                        self.cwnd += acked;
+                    }
+                }
+                None => self.cwnd += acked,
+            }
+        }
+        self.cwnd = cmp::max(self.cwnd, self.min_pipe_cwnd());
+        if self.mode == BbrMode::ProbeRtt {
+            self.cwnd = cmp::min(self.cwnd, self.min_pipe_cwnd());
+        }
+    }
+}
+
+impl CongestionControl for Bbr {
+    fn name(&self) -> &'static str {
+        "bbr"
+    }
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+    fn ssthresh(&self) -> usize {
+        usize::MAX // BBR has no use for ssthresh
+    }
+    fn in_recovery(&self) -> bool {
+        self.recover.is_some()
+    }
+    fn on_ack(&mut self, ack: AckInfo) -> CcAction {
+        let now = Instant::now();
+        let mut action = CcAction::Nothing;
+        if let Some(recover) = self.recover {
+            if ack.ack_num >= recover {
+                self.recover = None;
+                self.cwnd = cmp::max(self.cwnd, self.prior_cwnd);
+                self.prior_cwnd = 0;
+            } else {
+                action = CcAction::Retransmit; // Partial ACK
+            }
+        }
+        if let Some(rs) = ack.rate {
+            self.update_round(&rs);
+            self.update_btl_bw(&rs);
+            self.check_full_pipe(&rs);
+            self.check_drain(ack.in_flight, now);
+            self.advance_cycle_phase(ack.in_flight, now);
+            self.update_min_rtt(rs.rtt, now);
+            self.handle_probe_rtt(&rs, ack.in_flight, now);
+        }
+        self.set_pacing_rate();
+        self.set_cwnd(ack.acked, ack.in_flight);
+        action
+    }
+    fn on_dup_ack(&mut self, dup_count: u32, snd_nxt: SeqNum, in_flight: usize) -> CcAction {
+        if self.in_recovery() {
+            self.cwnd = cmp::max(self.cwnd, in_flight + self.mss);
+            return CcAction::Nothing;
+        }
+        if dup_count == DUP_ACK_THRESHOLD {
+            self.prior_cwnd = cmp::max(self.prior_cwnd, self.cwnd);
+            self.cwnd = cmp::max(in_flight + self.mss, self.min_pipe_cwnd());
+            self.recover = Some(snd_nxt);
+            return CcAction::Retransmit;
+        }
+        CcAction::Nothing
+    }
+    fn on_rto(&mut self, _in_flight: usize) {
+        self.prior_cwnd = cmp::max(self.prior_cwnd, self.cwnd);
+        self.cwnd = self.mss;
+        self.recover = None;
+    }
+    fn on_rtt_sample(&mut self, rtt: Duration) {
+        if self.min_rtt.is_none_or(|min_rtt| rtt <= min_rtt) {
+            self.min_rtt = Some(rtt);
+            self.min_rtt_stamp = Instant::now();
+        }
+        if self.pacing_rate == 0.0 {
+            // No bandwidth samples yet - pace the initial window out over one RTT
+            self.pacing_rate = HIGH_GAIN * self.cwnd as f64 / rtt.as_secs_f64().max(1e-3);
+        }
+    }
+    fn pacing_rate(&self) -> Option<f64> {
+        match self.pacing_rate {
+            rate if rate > 0.0 => Some(rate),
+            _ => None,
+        }
+    }
+    fn model(&self) -> Option<CcModel> {
+        Some(CcModel {
+            mode: match self.mode {
+                BbrMode::Startup => "STARTUP",
+                BbrMode::Drain => "DRAIN",
+                BbrMode::ProbeBw => "PROBE_BW",
+                BbrMode::ProbeRtt => "PROBE_RTT",
+            },
+            btl_bw: self.btl_bw(),
+            min_rtt: self.min_rtt,
+            pacing_rate: self.pacing_rate,
+            pacing_gain: self.pacing_gain,
+            cwnd_gain: self.cwnd_gain,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 1000;
+
+    /// Acks one round's worth of data delivered at the given rate
+    fn ack_round(bbr: &mut Bbr, delivered: &mut u64, rate: f64) {
+        let prior_delivered = *delivered;
+        *delivered += 10 * MSS as u64;
+        let rs = RateSample {
+            delivery_rate: rate,
+            delivered: *delivered,
+            prior_delivered,
+            interval: Duration::from_millis(10),
+            rtt: Duration::from_millis(10),
+            is_app_limited: false,
+        };
+        bbr.on_ack(AckInfo {
+            acked: 10 * MSS,
+            ack_num: SeqNum::new(*delivered as u32),
+            snd_nxt: SeqNum::new(*delivered as u32),
+            in_flight: 0,
+            rate: Some(rs),
+        });
+    }
+
+    #[test]
+    fn test_startup_exits_once_bandwidth_plateaus() {
+        let mut bbr = Bbr::new(MSS);
+        let mut delivered = 0;
+        for rate in [1e5, 2e5, 4e5, 8e5] {
+            ack_round(&mut bbr, &mut delivered, rate);
+            assert_eq!(bbr.mode, BbrMode::Startup);
+        }
+        for _ in 0..FULL_BW_COUNT {
+            ack_round(&mut bbr, &mut delivered, 8e5);
+        }
+        // Pipe is full, and with nothing in flight the drain phase ends right away
+        assert!(bbr.filled_pipe);
+        assert_eq!(bbr.mode, BbrMode::ProbeBw);
+        assert_eq!(bbr.btl_bw(), 8e5);
+        assert_eq!(bbr.min_rtt, Some(Duration::from_millis(10)));
+        // cwnd is capped at cwnd_gain * BDP (+ 3 segments)
+        assert!(bbr.cwnd() <= (2.0 * 8e5 * 0.01) as usize + 3 * MSS);
+    }
+
+    #[test]
+    fn test_loss_does_not_touch_the_model() {
+        let mut bbr = Bbr::new(MSS);
+        let mut delivered = 0;
+        ack_round(&mut bbr, &mut delivered, 5e5);
+        let cwnd = bbr.cwnd();
+        assert_eq!(bbr.on_dup_ack(3, SeqNum::new(0), 2 * MSS), CcAction::Retransmit);
+        assert_eq!(bbr.btl_bw(), 5e5);
+        assert_eq!(bbr.cwnd(), MIN_PIPE_CWND * MSS);
+        // Leaving recovery restores the old window
+        ack_round(&mut bbr, &mut delivered, 5e5);
+        assert!(!bbr.in_recovery());
+        assert!(bbr.cwnd() >= cwnd);
+    }
+}
@@ -4,6 +4,8 @@ use crate::prelude::*;
 use crate::utils::*;
 //use crate::tcp_utils::*;
 use crate::backends::{Backend, HostBackend, RouterBackend};
+use crate::tcp_config::TcpConfig;
+use crate::sockman_utils::SocketTable;
 //use crate::socket_manager::SocketManager;
 
 fn init_interfaces(
@@ -26,10 +28,12 @@ fn init_interfaces(
             }
         }
         //Add the completed Interfaces and InterfaceReps to their corresponding vectors for return
+        let mtu = inter_conf.mtu.map_or(DEFAULT_MTU, usize::from);
         let new_interface = Interface::new(
             // inter_conf.assigned_ip.clone(),
             inter_neighbors,
             inter_conf.udp_port,
+            mtu,
         );
         thread::spawn(move || new_interface.run(inter_chan));
         interface_reps.insert(
@@ -39,6 +43,7 @@ fn init_interfaces(
                 inter_conf.assigned_prefix, //.trunc(),
                 inter_conf.assigned_ip,
                 inter_rep_neighbors,
+                mtu,
                 inter_rep_chan.send,
             ),
         );
@@ -49,6 +54,7 @@ fn init_interfaces(
 
 // Handles initializing routers, returns to initialize
 pub fn initialize(config_info: IPConfig) -> Result<(Backend, Receiver<Packet>)> {
+    let tcp_config = TcpConfig::try_from(&config_info)?;
     // Create hashmap of interfaceReps (keys are names of interfaceReps)
     let (interface_reps, interface_recvers) =
         init_interfaces(config_info.interfaces, config_info.neighbors);
@@ -73,10 +79,7 @@ pub fn initialize(config_info: IPConfig) -> Result<(Backend, Receiver<Packet>)>
                 RouterBackend::new(backend_interface_reps, backend_forwarding_table, ip_sender);
             //Create RIP neighbors table
             let mut rip_table = HashMap::new();
-            let rip_neighbors = match config_info.rip_neighbors {
-                Some(rip_neighbors) => rip_neighbors,
-                None => Vec::new(), //Empty rip neighbors list
-            };
+            let rip_neighbors = config_info.rip_neighbors.unwrap_or_default(); //Empty rip neighbors list if none given
             add_rip_neighbors(&mut rip_table, rip_neighbors);
             //Construct and run ipdaemon
             let ipdaemon = RouterIpDaemon::new(
@@ -92,12 +95,13 @@ pub fn initialize(config_info: IPConfig) -> Result<(Backend, Receiver<Packet>)>
         }
         RoutingType::Static => {
             //Make host backend
-            let socket_table = Arc::new(RwLock::new(HashMap::new()));
+            let socket_table = Arc::new(RwLock::new(SocketTable::new()));
             let backend = HostBackend::new(
                 backend_interface_reps,
                 backend_forwarding_table,
                 socket_table,
                 ip_sender,
+                tcp_config,
             );
             //Construct and run ipdaemon
             let ipdaemon = HostIpDaemon::new(
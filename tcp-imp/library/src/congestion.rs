@@ -0,0 +1,311 @@
+use crate::bbr::Bbr;
+use crate::cubic::Cubic;
+use crate::ledbat::Ledbat;
+use crate::retransmission::RateSample;
+use crate::seq_num::SeqNum;
+use crate::vegas::Vegas;
+use std::cmp;
+use std::fmt::Debug;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::time::Duration;
+
+/* Congestion control:
+
+Every SendBuf owns a congestion controller and consults it before handing out new data.
+The amount of data allowed in flight is min(rem_window, cwnd), where rem_window is the
+window advertised by our partner and cwnd is the controller's congestion window.
+
+The controller is told about three kinds of events:
+- A new ACK (snd.una moves forward) -> on_ack
+- A duplicate ACK -> on_dup_ack
+- A retransmission timeout -> on_rto
+
+on_ack and on_dup_ack may ask the socket to retransmit the first unacknowledged segment
+(fast retransmit and partial ACKs during fast recovery).
+
+NEW RENO (RFC 5681 + RFC 6582):
+
+init state:
+cwnd = IW (RFC 5681 3.1)
+ssthresh = "arbitrarily high"
+
+ON ACK:
+- Slow start (cwnd < ssthresh): cwnd += min(acked, SMSS)
+- Congestion avoidance: cwnd += SMSS once per cwnd worth of acknowledged bytes
+- In fast recovery:
+    - Full ACK (ack >= recover): cwnd = min(ssthresh, max(flight_size, SMSS) + SMSS), leave recovery
+    - Partial ACK: retransmit first unacked segment, cwnd = cwnd - acked + SMSS
+
+ON THIRD DUP ACK:
+ssthresh = max(flight_size / 2, 2 * SMSS)
+recover = snd.nxt
+cwnd = ssthresh + 3 * SMSS
+retransmit first unacked segment, enter fast recovery
+Each further dup ACK inflates cwnd by SMSS
+
+ON RTO:
+ssthresh = max(flight_size / 2, 2 * SMSS)
+cwnd = SMSS (loss window)
+
+*/
+
+/// Number of duplicate ACKs that triggers a fast retransmit
+pub const DUP_ACK_THRESHOLD: u32 = 3;
+
+/// What the socket should do after the congestion controller has processed an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CcAction {
+    Nothing,
+    Retransmit, //Retransmit the first unacknowledged segment
+}
+
+/// Snapshot of the send side handed to the congestion controller when snd.una advances
+#[derive(Debug, Clone, Copy)]
+pub struct AckInfo {
+    pub acked: usize,     // Bytes newly acknowledged by this ACK
+    pub ack_num: SeqNum,  // Acknowledgement number of the ACK
+    pub snd_nxt: SeqNum,  // Next sequence number to be sent
+    pub in_flight: usize, // Bytes still outstanding after this ACK
+    pub rate: Option<RateSample>, // Delivery rate sample taken when this ACK arrived
+}
+
+/// Path model exposed by model-based controllers (BBR), used when displaying socket information
+#[derive(Debug, Clone, Copy)]
+pub struct CcModel {
+    pub mode: &'static str,
+    pub btl_bw: f64, // Bottleneck bandwidth estimate in bytes per second
+    pub min_rtt: Option<Duration>,
+    pub pacing_rate: f64, // Bytes per second
+    pub pacing_gain: f64,
+    pub cwnd_gain: f64,
+}
+
+pub trait CongestionControl: Debug + Send {
+    /// Name of the algorithm, used when displaying socket information
+    fn name(&self) -> &'static str;
+    /// Current congestion window in bytes
+    fn cwnd(&self) -> usize;
+    /// Current slow start threshold in bytes
+    fn ssthresh(&self) -> usize;
+    /// Whether the controller is currently in a loss recovery phase
+    fn in_recovery(&self) -> bool;
+    /// Called whenever an ACK acknowledges new data
+    fn on_ack(&mut self, ack: AckInfo) -> CcAction;
+    /// Called for every duplicate ACK, dup_count being the number of consecutive duplicates so far
+    fn on_dup_ack(&mut self, dup_count: u32, snd_nxt: SeqNum, in_flight: usize) -> CcAction;
+    /// Called when the retransmission timer expires
+    fn on_rto(&mut self, in_flight: usize);
+    /// Called for every valid RTT measurement
+    fn on_rtt_sample(&mut self, _rtt: Duration) {}
+    /// Rate (in bytes per second) segments should be paced out at, None to send as fast as cwnd allows
+    fn pacing_rate(&self) -> Option<f64> {
+        None
+    }
+    /// Internal path model of the controller, if it keeps one
+    fn model(&self) -> Option<CcModel> {
+        None
+    }
+}
+
+/// The congestion control algorithms a socket can use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CongestionAlgorithm {
+    #[default]
+    NewReno,
+    Cubic,
+    Bbr,
+    Vegas,
+    Ledbat,
+}
+
+impl CongestionAlgorithm {
+    /// Builds a fresh congestion controller running this algorithm
+    pub fn build(&self, mss: usize) -> Box<dyn CongestionControl> {
+        match self {
+            CongestionAlgorithm::NewReno => Box::new(NewReno::new(mss)),
+            CongestionAlgorithm::Cubic => Box::new(Cubic::new(mss)),
+            CongestionAlgorithm::Bbr => Box::new(Bbr::new(mss)),
+            CongestionAlgorithm::Vegas => Box::new(Vegas::new(mss)),
+            CongestionAlgorithm::Ledbat => Box::new(Ledbat::new(mss)),
+        }
+    }
+}
+
+impl FromStr for CongestionAlgorithm {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "newreno" | "reno" => Ok(CongestionAlgorithm::NewReno),
+            "cubic" => Ok(CongestionAlgorithm::Cubic),
+            "bbr" => Ok(CongestionAlgorithm::Bbr),
+            "vegas" => Ok(CongestionAlgorithm::Vegas),
+            "ledbat" => Ok(CongestionAlgorithm::Ledbat),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown congestion control algorithm \"{name}\""),
+            )),
+        }
+    }
+}
+
+/// Initial window, RFC 5681 (3.1)
+pub fn initial_window(mss: usize) -> usize {
+    if mss > 2190 {
+        2 * mss
+    } else if mss > 1095 {
+        3 * mss
+    } else {
+        4 * mss
+    }
+}
+
+/// ssthresh after a loss event, RFC 5681 (4)
+pub fn loss_ssthresh(in_flight: usize, mss: usize) -> usize {
+    cmp::max(in_flight / 2, 2 * mss)
+}
+
+#[derive(Debug)]
+pub struct NewReno {
+    mss: usize,
+    cwnd: usize,
+    ssthresh: usize,
+    bytes_acked: usize, // Appropriate byte counting for congestion avoidance (RFC 3465)
+    recover: Option<SeqNum>, // Some(snd.nxt at time of loss) while in fast recovery
+}
+
+impl NewReno {
+    pub fn new(mss: usize) -> NewReno {
+        NewReno {
+            mss,
+            cwnd: initial_window(mss),
+            ssthresh: usize::MAX,
+            bytes_acked: 0,
+            recover: None,
+        }
+    }
+}
+
+impl CongestionControl for NewReno {
+    fn name(&self) -> &'static str {
+        "newreno"
+    }
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+    fn in_recovery(&self) -> bool {
+        self.recover.is_some()
+    }
+    fn on_ack(&mut self, ack: AckInfo) -> CcAction {
+        if let Some(recover) = self.recover {
+            if ack.ack_num >= recover {
+                // Full ACK - deflate the window and leave fast recovery
+                self.cwnd = cmp::min(self.ssthresh, cmp::max(ack.in_flight, self.mss) + self.mss);
+                self.recover = None;
+                return CcAction::Nothing;
+            }
+            // Partial ACK - the next hole needs retransmitting right away
+            self.cwnd = self.cwnd.saturating_sub(ack.acked);
+            if ack.acked >= self.mss {
+                self.cwnd += self.mss;
+            }
+            self.cwnd = cmp::max(self.cwnd, self.mss);
+            return CcAction::Retransmit;
+        }
+        if self.cwnd < self.ssthresh {
+            // Slow start
+            self.cwnd += cmp::min(ack.acked, self.mss);
+        } else {
+            // Congestion avoidance
+            self.bytes_acked += ack.acked;
+            if self.bytes_acked >= self.cwnd {
+                self.bytes_acked -= self.cwnd;
+                self.cwnd += self.mss;
+            }
+        }
+        CcAction::Nothing
+    }
+    fn on_dup_ack(&mut self, dup_count: u32, snd_nxt: SeqNum, in_flight: usize) -> CcAction {
+        if self.in_recovery() {
+            // Every further duplicate means another segment has left the network
+            self.cwnd += self.mss;
+            return CcAction::Nothing;
+        }
+        if dup_count == DUP_ACK_THRESHOLD {
+            self.ssthresh = loss_ssthresh(in_flight, self.mss);
+            self.cwnd = self.ssthresh + 3 * self.mss;
+            self.recover = Some(snd_nxt);
+            self.bytes_acked = 0;
+            return CcAction::Retransmit;
+        }
+        CcAction::Nothing
+    }
+    fn on_rto(&mut self, in_flight: usize) {
+        self.ssthresh = loss_ssthresh(in_flight, self.mss);
+        self.cwnd = self.mss;
+        self.bytes_acked = 0;
+        self.recover = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 1000;
+
+    fn ack(acked: usize, ack_num: u32, in_flight: usize) -> AckInfo {
+        AckInfo {
+            acked,
+            ack_num: SeqNum::new(ack_num),
+            snd_nxt: SeqNum::new(ack_num) + in_flight as u32,
+            in_flight,
+            rate: None,
+        }
+    }
+
+    #[test]
+    fn test_slow_start_then_avoidance() {
+        let mut cc = NewReno::new(MSS);
+        assert_eq!(cc.cwnd(), 4 * MSS);
+        cc.on_ack(ack(MSS, 1000, 0));
+        assert_eq!(cc.cwnd(), 5 * MSS);
+        cc.on_rto(10 * MSS);
+        assert_eq!(cc.cwnd(), MSS);
+        assert_eq!(cc.ssthresh(), 5 * MSS);
+        for i in 0..4 {
+            cc.on_ack(ack(MSS, 2000 + i * 1000, 0));
+        }
+        assert_eq!(cc.cwnd(), 5 * MSS);
+        // Now in congestion avoidance, a full window of ACKs grows cwnd by one MSS
+        for i in 0..5 {
+            cc.on_ack(ack(MSS, 6000 + i * 1000, 0));
+        }
+        assert_eq!(cc.cwnd(), 6 * MSS);
+    }
+
+    #[test]
+    fn test_fast_recovery_partial_ack() {
+        let mut cc = NewReno::new(MSS);
+        assert_eq!(cc.on_dup_ack(1, SeqNum::new(11000), 10 * MSS), CcAction::Nothing);
+        assert_eq!(cc.on_dup_ack(2, SeqNum::new(11000), 10 * MSS), CcAction::Nothing);
+        assert_eq!(cc.on_dup_ack(3, SeqNum::new(11000), 10 * MSS), CcAction::Retransmit);
+        assert!(cc.in_recovery());
+        assert_eq!(cc.ssthresh(), 5 * MSS);
+        assert_eq!(cc.cwnd(), 8 * MSS);
+        cc.on_dup_ack(4, SeqNum::new(11000), 10 * MSS);
+        assert_eq!(cc.cwnd(), 9 * MSS);
+        // Partial ACK retransmits the next hole and stays in recovery
+        assert_eq!(cc.on_ack(ack(2 * MSS, 3000, 8 * MSS)), CcAction::Retransmit);
+        assert!(cc.in_recovery());
+        assert_eq!(cc.cwnd(), 8 * MSS);
+        // Full ACK deflates the window
+        assert_eq!(cc.on_ack(ack(8 * MSS, 11000, 0)), CcAction::Nothing);
+        assert!(!cc.in_recovery());
+        assert_eq!(cc.cwnd(), 2 * MSS);
+    }
+}
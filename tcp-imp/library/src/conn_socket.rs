@@ -1,12 +1,20 @@
+use crate::congestion::{CcAction, CongestionAlgorithm};
+use crate::poll::Readiness;
 use crate::prelude::*;
 use crate::retransmission::*;
 use crate::send_recv_utils::*;
+use crate::seq_num::SeqNum;
+use crate::sockman_utils::{How, SocketStats};
+use crate::tcp_config::{Keepalive, TcpConfig};
 use crate::tcp_utils::*;
 use crate::utils::*;
+use std::task::Waker;
 //TODO:
 //Get post ZWP functionality to work better (put more inside send_onwards)
 type SocketId = u16;
-const ZWP_TIMEOUT: u64 = 5000; //in millis
+const MAX_SACK_BLOCKS: usize = 4; //As many as fit in the 40 bytes of option space
+const MAX_SACK_BLOCKS_WITH_TS: usize = 3; //Timestamps take up 10 (+2 padding) of those bytes
+const TIMESTAMP_OPTION_LEN: usize = 12; //Including padding
 
 #[derive(Debug)]
 pub struct ConnectionSocket {
@@ -17,27 +25,57 @@ pub struct ConnectionSocket {
     closed_sender: Arc<Sender<SocketId>>,
     ip_sender: Arc<Sender<PacketBasis>>,
     stop_probing_recver: Arc<Mutex<Receiver<()>>>, //Needs to be an Arc so that it can be cloned and self can be dropped, needs to be a mutex so Rust doesn't freak out about two threads using the receiver at once
-    seq_num: u32, //Only edited in build_and_send() and viewed in build_packet()
-    ack_num: u32, //Edited every time a packet is received (first_syn_ack(), process_syn_ack(), establish_handler()) and viewed in build_packet()
+    snd_sender: Sender<SendCmd>, //Wakes up the send_onwards thread whenever more data may be sent
+    snd_recver: Arc<Mutex<Receiver<SendCmd>>>, //Same deal as stop_probing_recver
+    ack_sender: Sender<AckCmd>, //Starts the delayed_acker thread's timer
+    ack_recver: Arc<Mutex<Receiver<AckCmd>>>, //Same deal as stop_probing_recver
+    ack_pending: bool,    //Whether we owe our partner an acknowledgement that's being delayed
+    unacked_bytes: usize, //Bytes received since we last sent an acknowledgement
+    rcv_mss: usize,       //Largest segment we've received, our guess at our partner's MSS
+    seq_num: SeqNum, //Only edited in build_and_send() and viewed in build_packet()
+    ack_num: SeqNum, //Edited every time a packet is received (first_syn_ack(), process_syn_ack(), establish_handler()) and viewed in build_packet()
     read_buf: Arc<SyncBuf<RecvBuf>>,
     write_buf: Arc<SyncBuf<SendBuf>>,
     retr_timer: Arc<Mutex<RetransmissionTimer>>,
-    last_ack_num: u32,
+    last_ack_num: SeqNum,
     dup_ack_count: u32,
+    sack_permitted: bool, //Whether both sides agreed on using SACK during the handshake
+    window_scaling: bool, //Whether both sides agreed on scaling windows during the handshake
+    snd_wind_shift: u8,   //Window scale our partner applies to the windows it advertises
+    rcv_wind_shift: u8,   //Window scale we apply to the windows we advertise
+    timestamps: bool,     //Whether both sides agreed on sending timestamps during the handshake
+    ts_origin: Instant,   //Our timestamp clock ticks once per millisecond from here...
+    ts_offset: u32,       //...starting at a random value (RFC 7323 7.1)
+    ts_recent: u32,       //Most recent valid TSval from our partner, echoed back in our TSecr
+    echoed_ts: Option<u32>, //TSecr of the packet currently being processed
+    last_ack_sent: SeqNum,
+    last_window_sent: u32, //Receive window we last advertised
+    local_mss: u16, //MSS we advertise, fits the MTU of the interface we reach our partner through
+    handshake_sender: Option<Sender<Result<()>>>, //Lets connect() know how its connection attempt went
+    connect_deadline: Option<Instant>, //When to give up on our connection attempt if the handshake isn't done by then
+    last_heard: Instant, //When our partner last sent us a valid packet
+    keepalive_probes_sent: u32, //Keepalive probes that went unanswered since then
+    nonblocking: bool, //Sends and receives fail with WouldBlock instead of waiting
+    config: TcpConfig,
     // retr_queue: Arc<Mutex<RetransmissionQueue>>,
 }
 
 impl ConnectionSocket {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         state: Arc<RwLock<TcpState>>,
         src_addr: TcpAddress,
         dst_addr: TcpAddress,
+        seq_num: SeqNum,
         closed_sender: Arc<Sender<SocketId>>,
         ip_sender: Arc<Sender<PacketBasis>>,
+        local_mss: u16,
+        config: TcpConfig,
     ) -> ConnectionSocket {
         let mut rand_rng = rand::thread_rng();
-        let seq_num = rand_rng.gen::<u32>() / 2;
         let (stop_probing_sender, stop_probing_recver) = channel::<()>();
+        let (snd_sender, snd_recver) = channel::<SendCmd>();
+        let (ack_sender, ack_recver) = channel::<AckCmd>();
         ConnectionSocket {
             state,
             src_addr,
@@ -47,13 +85,47 @@ impl ConnectionSocket {
             closed_sender,
             ip_sender,
             stop_probing_recver: Arc::new(Mutex::new(stop_probing_recver)),
-            ack_num: 0, //We don't know what the ack number should be yet - in some sense, self.set_init_ack() finishes the initialization of the socket
-            read_buf: Arc::new(SyncBuf::new(RecvBuf::new())),
-            write_buf: Arc::new(SyncBuf::new(SendBuf::new(seq_num, stop_probing_sender))),
-            retr_timer: Arc::new(Mutex::new(RetransmissionTimer::new())),
+            snd_sender,
+            snd_recver: Arc::new(Mutex::new(snd_recver)),
+            ack_sender,
+            ack_recver: Arc::new(Mutex::new(ack_recver)),
+            ack_pending: false,
+            unacked_bytes: 0,
+            rcv_mss: 0,
+            ack_num: SeqNum::new(0), //We don't know what the ack number should be yet - in some sense, self.set_init_ack() finishes the initialization of the socket
+            read_buf: Arc::new(SyncBuf::new(RecvBuf::new(config.recv_buffer))),
+            write_buf: Arc::new(SyncBuf::new(SendBuf::new(
+                seq_num,
+                stop_probing_sender,
+                config.congestion_control,
+                config.send_buffer,
+            ))),
+            retr_timer: Arc::new(Mutex::new(RetransmissionTimer::new(
+                config.rto_initial,
+                config.rto_min,
+                config.rto_max,
+            ))),
             // retr_queue: Arc::new(Mutex::new(RetransmissionQueue::new())),
             dup_ack_count: 0,
-            last_ack_num: 0,
+            last_ack_num: seq_num,
+            sack_permitted: false,
+            window_scaling: false,
+            snd_wind_shift: 0,
+            rcv_wind_shift: 0,
+            timestamps: false,
+            ts_origin: Instant::now(),
+            ts_offset: rand_rng.gen::<u32>(),
+            ts_recent: 0,
+            echoed_ts: None,
+            last_ack_sent: SeqNum::new(0),
+            last_window_sent: 0,
+            local_mss,
+            handshake_sender: None,
+            connect_deadline: None,
+            last_heard: Instant::now(),
+            keepalive_probes_sent: 0,
+            nonblocking: false,
+            config,
         }
     }
     ///Returns input socket's sid
@@ -64,34 +136,121 @@ impl ConnectionSocket {
     /// Periodically does checking/elimination/retransmission from the queue and timer
     pub fn time_check(slf: Arc<Mutex<Self>>) {
         loop {
-            let current_rto = {
+            let (current_rto, user_timeout, keepalive_due, connect_deadline) = {
                 let slf = slf.lock().unwrap();
                 let retr_timer = slf.retr_timer.lock().unwrap();
-                retr_timer.rto
+                (retr_timer.rto, slf.config.user_timeout, slf.keepalive_due(), slf.connect_deadline)
             };
 
-            thread::sleep(current_rto);
+            let mut sleep_time = cmp::min(current_rto, user_timeout);
+            for due in [keepalive_due, connect_deadline].into_iter().flatten() {
+                sleep_time = cmp::min(sleep_time, due.saturating_duration_since(Instant::now()));
+            }
+            thread::sleep(sleep_time);
 
             let mut slf = slf.lock().unwrap();
+            if let TcpState::Closed = *slf.state.read().unwrap() {
+                return; //Nothing left to retransmit
+            }
+            //Give up on a connection attempt nobody answered in time - connect() finds out through the handshake receiver
+            if slf.handshake_sender.is_some() && connect_deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                slf.abort(ErrorKind::TimedOut, "Connection timed out");
+                return;
+            }
+            //Give up on the connection once data has gone unacknowledged for too long - unanswered SYNs are left to the connect timeout
+            let handshaking = matches!(*slf.state.read().unwrap(), TcpState::SynSent | TcpState::SynRecvd);
+            let unacked_for = slf.write_buf.get_buf().retr_queue.oldest_unacked_for();
+            if !handshaking && unacked_for.is_some_and(|unacked_for| unacked_for >= slf.config.user_timeout) {
+                eprintln!("Connection to {}:{} timed out", slf.dst_addr.ip, slf.dst_addr.port);
+                slf.abort(ErrorKind::TimedOut, "Connection timed out");
+                return;
+            }
+            //Probe an idle connection to check our partner is still there, giving up once enough probes go unanswered
+            if slf.keepalive_due().is_some_and(|keepalive_due| keepalive_due <= Instant::now()) {
+                if slf.keepalive_probes_sent >= slf.config.keepalive_params.probes {
+                    eprintln!("Connection to {}:{} timed out (keepalive)", slf.dst_addr.ip, slf.dst_addr.port);
+                    slf.abort(ErrorKind::TimedOut, "Connection timed out");
+                    return;
+                }
+                slf.keepalive_probes_sent += 1;
+                //An already acknowledged sequence number makes our partner answer with an ACK (RFC 1122 4.2.3.6)
+                let probe_seq = slf.seq_num - 1;
+                slf.send_segment(probe_seq, Vec::new(), ACK);
+            }
             if let Some(seg) = {
                 let write_buf = Arc::clone(&slf.write_buf);
                 let mut writer = write_buf.get_buf();
-                writer.retr_queue.get_next_timeout(current_rto)
+                let seg = writer.retr_queue.get_next_timeout(current_rto);
+                if seg.is_some() {
+                    writer.timed_out(); //Let congestion control know about the loss
+                }
+                seg
             } {
                 {
                     let mut retr_timer = slf.retr_timer.lock().unwrap();
                     retr_timer.do_retransmission();
                 }
-                slf.send_segment(seg.seq_num, seg.payload.clone(), seg.flags, seg.checksum);
+                if slf.config.max_retransmissions.is_some_and(|max| seg.retransmission_count > max) {
+                    eprintln!("Connection to {}:{} timed out (too many retransmissions)", slf.dst_addr.ip, slf.dst_addr.port);
+                    slf.abort(ErrorKind::TimedOut, "Connection timed out");
+                    return;
+                }
+                if seg.retransmission_count == slf.config.warn_retransmissions {
+                    eprintln!("Connection to {}:{} might be failing", slf.dst_addr.ip, slf.dst_addr.port);
+                }
+                slf.dup_ack_count = 0;
+                slf.send_segment(seg.seq_num, seg.payload.clone(), seg.flags);
             }
         }
     }
     //Sending first messages in handshake
-    pub fn first_syn(slf: Arc<Mutex<Self>>) {
+    ///Returns a receiver that gets the outcome of the handshake
+    pub fn first_syn(slf: Arc<Mutex<Self>>) -> Receiver<Result<()>> {
         let mut slf = slf.lock().unwrap();
+        let (handshake_sender, handshake_recver) = channel();
+        slf.handshake_sender = Some(handshake_sender);
+        slf.connect_deadline = Some(Instant::now() + slf.config.connect_timeout);
         slf.send_flags(SYN);
         let mut state = slf.state.write().unwrap();
         *state = TcpState::SynSent;
+        handshake_recver
+    }
+    ///Finishes a handshake that was answered with a SYN cookie - the socket was made with the cookie as its ISN,
+    ///and the input header is the ACK that brought it back
+    pub fn accept_cookie(&mut self, head: &TcpHeader, mss: u16) {
+        self.set_init_ack(SeqNum::new(head.sequence_number) - 1);
+        self.seq_num += 1; //Our SYN-ACK took up the cookie
+        self.last_ack_num = self.seq_num;
+        {
+            let mut writer = self.write_buf.get_buf();
+            writer.set_mss(cmp::min(mss, self.local_mss) as usize);
+            writer.set_congestion_control(self.config.congestion_control);
+            writer.update_window(head.window_size as u32); //No window scaling without options
+        }
+        *self.state.write().unwrap() = TcpState::Established;
+    }
+    ///When the next keepalive probe (or giving up on the connection) is due
+    ///None if keepalive is off or the connection isn't idle - unacknowledged data is the user timeout's business
+    fn keepalive_due(&self) -> Option<Instant> {
+        if !self.config.keepalive {
+            return None;
+        }
+        match *self.state.read().unwrap() {
+            TcpState::Established | TcpState::CloseWait => (),
+            _ => return None,
+        }
+        if !Self::is_send_complete(&self.write_buf.get_buf()) {
+            return None;
+        }
+        let params = self.config.keepalive_params;
+        Some(self.last_heard + params.idle + params.interval * self.keepalive_probes_sent)
+    }
+    ///Lets connect() know how the handshake went
+    fn finish_handshake(&mut self, outcome: Result<()>) {
+        if let Some(handshake_sender) = self.handshake_sender.take() {
+            let _ = handshake_sender.send(outcome); //connect() might have stopped waiting already
+            self.write_buf.alert_ready(); //Wakes up anyone watching for the outcome instead of waiting on it
+        }
     }
 
     //
@@ -99,18 +258,29 @@ impl ConnectionSocket {
     //
     //TODO: Clean this ugly ass function up
     pub fn handle_packet(slf: Arc<Mutex<Self>>, tpack: TcpPacket, ip_head: Ipv4Header) {
-        let slf_clone = Arc::clone(&slf); //Needed for closing in fin_wait_2_handler
+        let slf_clone = Arc::clone(&slf); //Needed for closing in the FIN_WAIT handlers
         let mut slf = slf.lock().unwrap();
-        let win_size = tpack.header.window_size;
+        //Windows in SYNs are never scaled (RFC 7323 2.2)
+        let win_shift = if tpack.header.syn { 0 } else { slf.snd_wind_shift };
+        let win_size = (tpack.header.window_size as u32) << win_shift;
         //Universal packet reception actions
-        if has_flags(&tpack.header, RST) {
-            panic!("Received RST packet");
-        } //Panics if RST flag received
-          //TODO: Get rid of clone, but I'm tired and lazy - will fix later - Alex
+        //TODO: Get rid of clone, but I'm tired and lazy - will fix later - Alex
         if !slf.check_tcp_checksum(tpack.clone(), ip_head) {
             eprintln!("Received packet with bad checksum, dropping.");
             return;
         }
+        if has_flags(&tpack.header, RST) {
+            return slf.handle_reset(&tpack);
+        }
+        if slf.timestamps && !slf.check_timestamp(&tpack) {
+            return;
+        }
+        //Our partner is still there
+        slf.last_heard = Instant::now();
+        slf.keepalive_probes_sent = 0;
+        if slf.is_keepalive_probe(&tpack) {
+            return slf.send_flags(ACK);
+        }
         {
             //Update (remote) window size
             let mut write_buf = slf.write_buf.get_buf();
@@ -129,7 +299,7 @@ impl ConnectionSocket {
                 TcpState::SynSent => slf.process_syn_ack(tpack),
                 TcpState::SynRecvd => slf.process_ack(tpack),
                 TcpState::Established => slf.established_handle(tpack),
-                TcpState::FinWait1 => slf.fin_wait_1_handler(tpack),
+                TcpState::FinWait1 => slf.fin_wait_1_handler(tpack, slf_clone),
                 TcpState::FinWait2 => slf.fin_wait_2_handler(tpack, slf_clone),
                 TcpState::TimeWait => slf.time_wait_handler(tpack),
                 TcpState::CloseWait => slf.close_wait_handler(tpack),
@@ -137,9 +307,23 @@ impl ConnectionSocket {
                 TcpState::Closed => slf.closed_handler(tpack),
             }
         };
+        if let TcpState::Established = new_state {
+            slf.finish_handshake(Ok(()));
+        }
         let mut state = slf.state.write().unwrap();
         *state = new_state;
     }
+    /// Keepalive probes carry a sequence number we've already acknowledged, and at most one byte of garbage
+    fn is_keepalive_probe(&self, tpack: &TcpPacket) -> bool {
+        let synchronized = matches!(
+            *self.state.read().unwrap(),
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 | TcpState::CloseWait
+        );
+        synchronized
+            && has_only_flags(&tpack.header, ACK)
+            && tpack.payload.len() <= 1
+            && SeqNum::new(tpack.header.sequence_number) == self.ack_num - 1
+    }
     /// Checks if a tcp packet complies to the TCP protocol checksum
     fn check_tcp_checksum(&mut self, tpack: TcpPacket, ip_head: Ipv4Header) -> bool {
         let proper_checksum = {
@@ -150,12 +334,72 @@ impl ConnectionSocket {
         };
         proper_checksum == tpack.header.checksum
     }
+    /// Validates an incoming reset (RFC 9293 3.10.7, RFC 5961 3.2) and tears the connection down if it checks out
+    fn handle_reset(&mut self, tpack: &TcpPacket) {
+        let state = self.state.read().unwrap().clone();
+        let seq_num = SeqNum::new(tpack.header.sequence_number);
+        let acceptable = match state {
+            //Only a reset acknowledging our SYN can be an answer to it
+            TcpState::SynSent => {
+                tpack.header.ack && SeqNum::new(tpack.header.acknowledgment_number) == self.seq_num
+            }
+            TcpState::Closed => false,
+            _ if seq_num == self.ack_num => true,
+            _ => {
+                //Somewhere else in our window could be a blind attack - make our partner prove itself with a challenge ACK
+                if seq_num - self.ack_num < self.read_buf.get_buf().window() {
+                    self.send_flags(ACK);
+                }
+                false
+            }
+        };
+        if !acceptable {
+            return;
+        }
+        if let TcpState::SynSent = state {
+            self.abort(ErrorKind::ConnectionRefused, "Connection refused");
+        } else {
+            eprintln!("Connection reset by {}:{}", self.dst_addr.ip, self.dst_addr.port);
+            self.abort(ErrorKind::ConnectionReset, "Connection reset by peer");
+        }
+    }
+    ///Tears the connection down for good - connect() and any blocked sends and receives fail with the input error
+    fn abort(&mut self, kind: ErrorKind, msg: &'static str) {
+        if let TcpState::Closed = *self.state.read().unwrap() {
+            return; //Already gone
+        }
+        self.finish_handshake(Err(Error::new(kind, msg)));
+        *self.state.write().unwrap() = TcpState::Closed;
+        self.read_buf.abort(kind, msg);
+        self.write_buf.abort(kind, msg);
+        self.enter_closed();
+    }
+    /// Protection Against Wrapped Sequences (RFC 7323 5.3) and TS.Recent bookkeeping
+    /// Returns false if the packet is an old duplicate that should be dropped
+    fn check_timestamp(&mut self, tpack: &TcpPacket) -> bool {
+        let Some((ts_val, ts_ecr)) = TcpOptions::parse(&tpack.header).timestamp else {
+            //Once timestamps are agreed on, every segment but a reset carries one - resets never get this far (RFC 7323 3.2)
+            self.echoed_ts = None;
+            return false;
+        };
+        if (ts_val.wrapping_sub(self.ts_recent) as i32) < 0 {
+            //Older than what we've already seen - let our partner know where we're at and drop it
+            self.send_flags(ACK);
+            return false;
+        }
+        if SeqNum::new(tpack.header.sequence_number) <= self.last_ack_sent {
+            self.ts_recent = ts_val;
+        }
+        self.echoed_ts = Some(ts_ecr);
+        true
+    }
     fn process_syn(&mut self, tpack: TcpPacket) -> TcpState {
         if has_only_flags(&tpack.header, SYN) {
             //Deal with receiving first sequence number of TCP partner
-            self.set_init_ack(tpack.header.sequence_number);
+            self.set_init_ack(SeqNum::new(tpack.header.sequence_number));
+            self.negotiate_options(&tpack.header);
             //Send response (SYN + ACK in this case) and change state
-            self.ack_rt(tpack.header.acknowledgment_number);
+            self.ack_rt(SeqNum::new(tpack.header.acknowledgment_number));
             self.send_flags(SYN | ACK);
             return TcpState::SynRecvd;
         }
@@ -166,17 +410,49 @@ impl ConnectionSocket {
     fn process_syn_ack(&mut self, tpack: TcpPacket) -> TcpState {
         if has_only_flags(&tpack.header, SYN | ACK) {
             //Deal with receiving first sequence number of TCP partner
-            self.set_init_ack(tpack.header.sequence_number);
-            self.ack_rt(tpack.header.acknowledgment_number);
+            self.set_init_ack(SeqNum::new(tpack.header.sequence_number));
+            self.negotiate_options(&tpack.header);
+            self.ack_rt(SeqNum::new(tpack.header.acknowledgment_number));
             //Send response (ACK in this case) and change state
             self.send_flags(ACK);
             return TcpState::Established;
         }
         TcpState::SynSent
     }
+    ///Takes note of the options our partner offered in its SYN/SYN-ACK
+    fn negotiate_options(&mut self, head: &TcpHeader) {
+        let options = TcpOptions::parse(head);
+        self.sack_permitted = options.sack_permitted;
+        if let Some((ts_val, _)) = options.timestamp {
+            self.timestamps = true;
+            self.ts_recent = ts_val;
+        }
+        if let Some(shift) = options.window_scale {
+            self.window_scaling = true;
+            self.snd_wind_shift = shift;
+            self.rcv_wind_shift = self.our_window_shift();
+        }
+        //The MSS leaves out options, so make room for the ones we'll be putting on data segments
+        let mut options_len = 0;
+        if self.timestamps {
+            options_len += TIMESTAMP_OPTION_LEN;
+        }
+        if self.sack_permitted {
+            let max_blocks = if self.timestamps { MAX_SACK_BLOCKS_WITH_TS } else { MAX_SACK_BLOCKS };
+            options_len += (2 + 8 * max_blocks).next_multiple_of(4);
+        }
+        let mss = cmp::min(options.mss.unwrap_or(DEFAULT_MSS), self.local_mss) as usize;
+        let mut writer = self.write_buf.get_buf();
+        writer.set_mss(cmp::max(mss.saturating_sub(options_len), 1));
+        writer.set_congestion_control(self.config.congestion_control); //Its windows are counted in segments
+    }
+    ///Window scale we need for our receive buffer's capacity to be advertisable
+    fn our_window_shift(&self) -> u8 {
+        window_shift_for(self.read_buf.get_buf().capacity())
+    }
     fn process_ack(&mut self, tpack: TcpPacket) -> TcpState {
         if has_only_flags(&tpack.header, ACK) {
-            self.ack_rt(tpack.header.acknowledgment_number);
+            self.ack_rt(SeqNum::new(tpack.header.acknowledgment_number));
             return TcpState::Established;
         }
         TcpState::SynRecvd
@@ -184,18 +460,18 @@ impl ConnectionSocket {
     fn established_handle(&mut self, tpack: TcpPacket) -> TcpState {
         match header_flags(&tpack.header) {
             ACK if tpack.payload.is_empty() => self.ack(tpack),
-            ACK => self.absorb_and_acknowledge(tpack),
+            ACK => {
+                //Data can carry acknowledgements for our own data too
+                self.ack(tpack.clone());
+                self.absorb_and_acknowledge(tpack);
+            }
             FINACK => {
                 //Other dude wants to close the connection
                 // Okay! I will close!
-                self.ack_num += 1;
-                self.read_buf
-                    .get_buf()
-                    .set_final_seq(tpack.header.sequence_number); //Allows receive to know when receiving is no longer allowed
-                self.read_buf.alert_ready(); //Allows any receiving thread to unblock itself and terminate
-                self.ack_rt(tpack.header.acknowledgment_number);
-                self.send_flags(ACK);
-                return TcpState::CloseWait;
+                self.ack_rt(SeqNum::new(tpack.header.acknowledgment_number));
+                if self.take_fin(tpack) {
+                    return TcpState::CloseWait;
+                }
             }
             _ => eprintln!(
                 "ESTABLISHED: I got no clue how to deal with a packet that has flags: {}",
@@ -204,8 +480,8 @@ impl ConnectionSocket {
         }
         TcpState::Established
     }
-    fn fin_wait_1_handler(&mut self, tpack: TcpPacket) -> TcpState {
-        self.ack_rt(tpack.header.acknowledgment_number);
+    fn fin_wait_1_handler(&mut self, tpack: TcpPacket, slf_clone: Arc<Mutex<Self>>) -> TcpState {
+        self.ack_rt(SeqNum::new(tpack.header.acknowledgment_number));
         // Still accepts both acks for data sent and incoming data
         // Transitions to FinWait2 upon reception of an ACK for its FIN
         match header_flags(&tpack.header) {
@@ -214,21 +490,31 @@ impl ConnectionSocket {
                 self.ack(tpack.clone());
 
                 // Check if this was an ack for our FIN
-                if tpack.header.acknowledgment_number == self.seq_num {
+                if SeqNum::new(tpack.header.acknowledgment_number) == self.seq_num {
+                    if self.read_buf.get_buf().fin_received() {
+                        // Simultaneous close - their FIN already came in
+                        thread::spawn(move || Self::wait_then_close(slf_clone));
+                        return TcpState::TimeWait;
+                    }
                     return TcpState::FinWait2;
                 }
                 TcpState::FinWait1
             }
             ACK => {
-                // Handle incoming data with acknowledgment
+                // Handle incoming data with acknowledgment - we only shut down writing, so it's still wanted
+                self.ack(tpack.clone());
+                let fin_acked = SeqNum::new(tpack.header.acknowledgment_number) == self.seq_num;
                 self.absorb_and_acknowledge(tpack);
+                if fin_acked {
+                    return TcpState::FinWait2;
+                }
                 TcpState::FinWait1
             }
             FINACK => {
                 // Handle simultaneous close case - acknowledge their FIN
-                self.ack_num += 1;
-                self.send_flags(ACK);
-                if tpack.header.acknowledgment_number == self.seq_num {
+                let fin_acked = SeqNum::new(tpack.header.acknowledgment_number) == self.seq_num;
+                if self.take_fin(tpack) && fin_acked {
+                    thread::spawn(move || Self::wait_then_close(slf_clone));
                     return TcpState::TimeWait;
                 }
                 TcpState::FinWait1
@@ -243,7 +529,7 @@ impl ConnectionSocket {
         }
     }
     fn fin_wait_2_handler(&mut self, tpack: TcpPacket, slf_clone: Arc<Mutex<Self>>) -> TcpState {
-        self.ack_rt(tpack.header.acknowledgment_number);
+        self.ack_rt(SeqNum::new(tpack.header.acknowledgment_number));
         match header_flags(&tpack.header) {
             ACK if tpack.payload.is_empty() => {
                 // Handle pure ACKs
@@ -256,15 +542,9 @@ impl ConnectionSocket {
                 TcpState::FinWait2
             }
             FINACK => {
-                // First absorb any final data that came with the FIN
-                if !tpack.payload.is_empty() {
-                    self.absorb_packet(tpack.clone());
+                if !self.take_fin(tpack) {
+                    return TcpState::FinWait2;
                 }
-
-                // Then handle the FIN
-                self.ack_num += 1;
-                self.send_flags(ACK);
-
                 // Start the TIME_WAIT timer
                 thread::spawn(move || Self::wait_then_close(slf_clone));
                 TcpState::TimeWait
@@ -283,7 +563,7 @@ impl ConnectionSocket {
         //Transitions to Closed after 2 * MAX RTO passes - timer started in fin_wait_2_handler
         match header_flags(&tpack.header) {
             ACK if tpack.payload.is_empty() => {
-                self.ack_rt(tpack.header.acknowledgment_number);
+                self.ack_rt(SeqNum::new(tpack.header.acknowledgment_number));
                 self.ack(tpack);
             }
             ACK => eprintln!("TIME_WAIT: I shouldn't get a packet with data from my TCP partner"),
@@ -299,7 +579,7 @@ impl ConnectionSocket {
         //Transitions to LastAck only when application runs close(), so no transitioning happening here
         match header_flags(&tpack.header) {
             ACK if tpack.payload.is_empty() => {
-                self.ack_rt(tpack.header.acknowledgment_number);
+                self.ack_rt(SeqNum::new(tpack.header.acknowledgment_number));
                 self.ack(tpack);
             }
             ACK => eprintln!("CLOSE_WAIT: I shouldn't get a packet with data from my TCP partner"),
@@ -315,8 +595,8 @@ impl ConnectionSocket {
         //Transitions to Closed when ack for FIN previously sent is received
         match header_flags(&tpack.header) {
             ACK if tpack.payload.is_empty() => {
-                self.ack_rt(tpack.header.acknowledgment_number);
-                if tpack.header.acknowledgment_number == self.seq_num {
+                self.ack_rt(SeqNum::new(tpack.header.acknowledgment_number));
+                if SeqNum::new(tpack.header.acknowledgment_number) == self.seq_num {
                     self.enter_closed();
                     return TcpState::Closed;
                 } else {
@@ -338,77 +618,148 @@ impl ConnectionSocket {
     }
 
     //PACKET HANDLING UTILITIES
-    ///Absorbs the packet and sends an acknowledgement for it
+    ///Absorbs the packet and acknowledges it, either right away or once the delayed ACK timer runs out (RFC 9293 3.8.6.3)
     fn absorb_and_acknowledge(&mut self, tpack: TcpPacket) {
+        let seq_num = SeqNum::new(tpack.header.sequence_number);
+        let len = tpack.payload.len();
         //Absorb packet
         self.absorb_packet(tpack);
-        //Send acknowledgement for received data
+        self.rcv_mss = cmp::max(self.rcv_mss, len);
+        self.unacked_bytes += len;
+        //Out of order and duplicate segments, and ones filling in a hole, are acknowledged immediately (RFC 5681 4.2)
+        let in_order = self.ack_num == seq_num + len as u32
+            && !self.read_buf.get_buf().has_out_of_order();
+        //So is every second full-sized segment, or half a buffer's worth for buffers too small to hold two
+        let ack_every = cmp::min(2 * self.rcv_mss, self.read_buf.get_buf().capacity() / 2);
+        if !in_order || self.config.ack_delay.is_zero() || self.unacked_bytes >= ack_every {
+            self.send_flags(ACK);
+        } else if !self.ack_pending {
+            self.ack_pending = true;
+            let _ = self.ack_sender.send(AckCmd::Arm); //Fails only if delayed_acker is already done
+        }
+    }
+    ///Takes in our partner's FIN along with any data that came with it, returning whether it could
+    ///A FIN that arrives ahead of data we're still missing is only acknowledged up to the hole - our partner will send it again
+    fn take_fin(&mut self, tpack: TcpPacket) -> bool {
+        let fin_seq = SeqNum::new(tpack.header.sequence_number) + tpack.payload.len() as u32;
+        if !tpack.payload.is_empty() {
+            self.absorb_packet(tpack);
+        }
+        if self.ack_num != fin_seq {
+            self.send_flags(ACK);
+            return false;
+        }
+        self.ack_num += 1;
+        self.read_buf.get_buf().set_final_seq(fin_seq); //Allows receive to know when receiving is no longer allowed
+        self.read_buf.alert_ready(); //Allows any receiving thread to unblock itself and terminate
         self.send_flags(ACK);
+        true
     }
     ///Handles adding the data from the packet to the recv buffer, incrementing ack num, and alert any receiving thread that data was added
     fn absorb_packet(&mut self, tpack: TcpPacket) {
         let mut recv_buf = self.read_buf.get_buf();
-        let new_ack = recv_buf.add(tpack.header.sequence_number, tpack.payload);
+        let new_ack = recv_buf.add(SeqNum::new(tpack.header.sequence_number), tpack.payload);
         self.ack_num = new_ack;
         self.read_buf.alert_ready();
     }
     ///Handles dropping all data associated with sequence numbers less than the ack number of the packet we just received and syncing this with retransmissions
+    ///Also keeps the congestion controller up to date on new and duplicate ACKs
     fn ack(&mut self, tpack: TcpPacket) {
-        let ack_num = tpack.header.acknowledgment_number;
-        let new_window = tpack.header.window_size;
-        let old_window = {
-            let send_buf = self.write_buf.get_buf();
-            send_buf.rem_window
+        let ack_num = SeqNum::new(tpack.header.acknowledgment_number);
+        let newly_sacked = self.sack_permitted && {
+            let options = TcpOptions::parse(&tpack.header);
+            self.write_buf.get_buf().retr_queue.sack(&options.sack_blocks)
         };
         // If ACK moves forward
         if ack_num > self.last_ack_num {
             self.last_ack_num = ack_num;
             self.dup_ack_count = 0;
-            {
+            // Remove acknowledged segments before processing the ACK
+            self.ack_rt(ack_num);
+            let action = {
                 let mut send_buf = self.write_buf.get_buf();
-                // Remove acknowledged segments before processing the ACK
-                send_buf.retr_queue.remove_acked_segments(ack_num);
-                send_buf.ack_data(ack_num);
+                send_buf.new_ack(ack_num)
+            };
+            if let CcAction::Retransmit = action {
+                // Partial ACK during fast recovery
+                self.retransmit_front();
+            } else {
+                self.retransmit_lost();
             }
-            self.ack_rt(ack_num);
-        } else if ack_num == self.last_ack_num {
-            // Check if this packet is just a window update
-            if new_window > old_window {
-                // Pure window update – do not treat as dup ACK
-                {
-                    let mut send_buf = self.write_buf.get_buf();
-                    send_buf.update_window(new_window);
-                }
-                // Reset dup_ack_count because this isn't a "true" duplicate ack
+            self.write_buf.alert_ready(); //Space was freed up in the send buffer
+            self.wake_sender();
+        } else if ack_num == self.last_ack_num && tpack.payload.is_empty() {
+            // With SACK, an ACK reporting new out of order data counts as a duplicate even if the window changed (RFC 6675)
+            let is_dup = newly_sacked || {
+                let send_buf = self.write_buf.get_buf();
+                send_buf.is_dup_ack()
+            };
+            if !is_dup {
+                // Pure window update – do not treat as dup ACK, but the new window might let us send more
                 self.dup_ack_count = 0;
+                self.wake_sender();
+                return;
+            }
+            // True duplicate ACK scenario
+            self.dup_ack_count += 1;
+            let action = {
+                let mut send_buf = self.write_buf.get_buf();
+                send_buf.dup_ack(self.dup_ack_count)
+            };
+            if let CcAction::Retransmit = action {
+                // Fast retransmit
+                self.retransmit_front();
             } else {
-                // True duplicate ACK scenario
-                self.dup_ack_count += 1;
-
-                if self.dup_ack_count == 3 {
-                    // Fast retransmit
-                    if let Some(seg) = {
-                        let send_buf = self.write_buf.get_buf();
-                        send_buf.retr_queue.queue.front().cloned()
-                    } {
-                        println!("Fast retransmit for seq={}", seg.seq_num);
-                        self.send_segment(
-                            seg.seq_num,
-                            seg.payload.clone(),
-                            seg.flags,
-                            seg.checksum,
-                        );
-                    }
-                }
+                self.retransmit_lost();
             }
+            self.wake_sender(); //Fast recovery inflates the congestion window
         }
     }
+    ///Immediately resends the first unacknowledged segment
+    fn retransmit_front(&mut self) {
+        let seg = {
+            let mut send_buf = self.write_buf.get_buf();
+            send_buf.retr_queue.retransmit_front()
+        };
+        if let Some(seg) = seg {
+            self.send_segment(seg.seq_num, seg.payload, seg.flags);
+        }
+    }
+    ///During loss recovery, resends the next hole our partner's SACK blocks tell us is lost
+    ///Only one per ACK, as each ACK means just one segment has left the network
+    fn retransmit_lost(&mut self) {
+        if !self.sack_permitted {
+            return;
+        }
+        let seg = {
+            let mut send_buf = self.write_buf.get_buf();
+            if !send_buf.cc.in_recovery() {
+                return;
+            }
+            send_buf.retr_queue.retransmit_next_lost()
+        };
+        if let Some(seg) = seg {
+            self.send_segment(seg.seq_num, seg.payload, seg.flags);
+        }
+    }
+    ///Lets the send_onwards thread know that it might be able to send more data
+    fn wake_sender(&self) {
+        let _ = self.snd_sender.send(SendCmd::DataAvailable); //Fails only if send_onwards is already done
+    }
     fn enter_closed(&self) {
+        let _ = self.snd_sender.send(SendCmd::Stop);
+        let _ = self.ack_sender.send(AckCmd::Stop);
         self.closed_sender.send(self.sid).unwrap();
     }
     fn wait_then_close(slf: Arc<Mutex<Self>>) {
-        thread::sleep(Duration::from_millis(2 * MAX_RTO));
+        let time_wait = slf.lock().unwrap().config.time_wait;
+        thread::sleep(time_wait);
         let slf = slf.lock().unwrap();
+        if let TcpState::Closed = *slf.state.read().unwrap() {
+            return; //Got reset in the meantime, already taken care of
+        }
+        let _ = slf.snd_sender.send(SendCmd::Stop);
+        let _ = slf.ack_sender.send(AckCmd::Stop);
         slf.closed_sender
             .send(slf.sid)
             .expect("Error sending to closing thread");
@@ -418,10 +769,9 @@ impl ConnectionSocket {
 
     //SETUP FINISHERS
     ///Sets up socket with knowledge of partner's sequence number
-    fn set_init_ack(&mut self, rem_seq_num: u32) {
+    fn set_init_ack(&mut self, rem_seq_num: SeqNum) {
         //Set ack_num
-        self.ack_num = rem_seq_num;
-        self.ack_num += 1; //Increment to be next expected value of sequence number
+        self.ack_num = rem_seq_num + 1; //Increment to be next expected value of sequence number
                            //Set Recv Buffer's initial remote sequence number
         let mut read_buf = self.read_buf.get_buf();
         read_buf.set_init_seq(rem_seq_num);
@@ -431,15 +781,162 @@ impl ConnectionSocket {
         let mut slf = slf.lock().unwrap();
         slf.sid = sid;
     }
-    fn ack_rt(&mut self, ack_num: u32) {
+    ///With timestamps, the RTO is driven by the echoed timestamp of every ACK that acknowledges new data (RFC 7323 4)
+    ///Congestion control still gets the finer grained, Karn-filtered samples timed off of our own segments
+    fn ack_rt(&mut self, ack_num: SeqNum) {
         let mut write_buf = self.write_buf.get_buf();
         let retr_queue = &mut write_buf.retr_queue;
-        if let Some(seg) = retr_queue.remove_acked_segments(ack_num) {
+        let queued = retr_queue.queue.len();
+        let seg_rtt = retr_queue
+            .remove_acked_segments(ack_num)
+            .map(|seg| Instant::now().duration_since(seg.time_of_send));
+        let acked_new = write_buf.retr_queue.queue.len() < queued;
+        if let Some(rtt) = seg_rtt {
+            write_buf.cc.on_rtt_sample(rtt);
+        }
+        let ts_rtt = match self.echoed_ts.take() {
+            Some(ts_ecr) if acked_new => Some(Duration::from_millis(
+                self.ts_clock().wrapping_sub(ts_ecr) as u64,
+            )),
+            _ => None,
+        };
+        if let Some(rtt) = ts_rtt.or(seg_rtt) {
             let mut retr_timer = self.retr_timer.lock().unwrap();
-            retr_timer.update_rto(Instant::now().duration_since(seg.time_of_send));
+            retr_timer.update_rto(rtt);
             retr_timer.reset();
         }
     }
+    ///Current value of our timestamp clock
+    fn ts_clock(&self) -> u32 {
+        let elapsed = Instant::now().duration_since(self.ts_origin).as_millis() as u32;
+        self.ts_offset.wrapping_add(elapsed)
+    }
+    ///Replaces the socket's congestion controller with a fresh one running the input algorithm
+    pub fn set_congestion_control(slf: Arc<Mutex<Self>>, algo: CongestionAlgorithm) {
+        let mut slf = slf.lock().unwrap();
+        slf.config.congestion_control = algo;
+        slf.write_buf.get_buf().set_congestion_control(algo);
+    }
+
+    ///Turns Nagle's algorithm off (true) or back on (false) for the socket
+    pub fn set_nodelay(slf: Arc<Mutex<Self>>, nodelay: bool) {
+        let slf = slf.lock().unwrap();
+        slf.write_buf.get_buf().set_nodelay(nodelay);
+        slf.wake_sender(); //Anything Nagle was holding back may go now
+    }
+
+    ///Sets how long sent data may go unacknowledged before the connection gets aborted (RFC 5482)
+    pub fn set_user_timeout(slf: Arc<Mutex<Self>>, user_timeout: Duration) {
+        let mut slf = slf.lock().unwrap();
+        slf.config.user_timeout = user_timeout;
+    }
+
+    ///Turns keepalive probing of the connection on (true) or off (false)
+    pub fn set_keepalive(slf: Arc<Mutex<Self>>, keepalive: bool) {
+        let mut slf = slf.lock().unwrap();
+        slf.config.keepalive = keepalive;
+    }
+
+    ///Sets how long the connection may sit idle before probing starts, how often to probe, and how many probes may go unanswered
+    pub fn set_keepalive_params(slf: Arc<Mutex<Self>>, params: Keepalive) {
+        let mut slf = slf.lock().unwrap();
+        slf.config.keepalive_params = params;
+    }
+    pub fn set_linger(slf: Arc<Mutex<Self>>, linger: Option<Duration>) {
+        let mut slf = slf.lock().unwrap();
+        slf.config.linger = linger;
+    }
+
+    ///Resizes the send buffer
+    pub fn set_send_buffer(slf: Arc<Mutex<Self>>, size: usize) {
+        let slf = slf.lock().unwrap();
+        slf.write_buf.get_buf().set_capacity(size);
+        slf.write_buf.alert_ready(); //Sends waiting on a full buffer might fit now
+    }
+
+    ///Resizes the receive buffer, which the windows we advertise follow - a smaller one never pulls back a window already offered
+    ///Window scaling is settled during the handshake, so windows stay capped at what the buffer size back then called for
+    pub fn set_recv_buffer(slf: Arc<Mutex<Self>>, size: usize) {
+        let mut slf = slf.lock().unwrap();
+        let (old_window, new_window) = {
+            let mut read_buf = slf.read_buf.get_buf();
+            let old_window = read_buf.window();
+            read_buf.set_capacity(size);
+            (old_window, read_buf.window())
+        };
+        let receiving = matches!(
+            *slf.state.read().unwrap(),
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+        );
+        if receiving && new_window > old_window {
+            slf.send_flags(ACK); //Lets our partner know about the bigger window right away
+        }
+    }
+
+    pub fn set_nonblocking(slf: Arc<Mutex<Self>>, nonblocking: bool) {
+        let mut slf = slf.lock().unwrap();
+        slf.nonblocking = nonblocking;
+    }
+
+    ///What the socket is ready for - see poll()
+    pub fn readiness(slf: Arc<Mutex<Self>>) -> Readiness {
+        let slf = slf.lock().unwrap();
+        let error = slf.read_buf.aborted().is_some() || slf.write_buf.aborted().is_some();
+        let closed = matches!(*slf.state.read().unwrap(), TcpState::Closed);
+        let (readable, fin_received) = {
+            let recv_buf = slf.read_buf.get_buf();
+            (recv_buf.ready() || !recv_buf.can_receive(), recv_buf.fin_received())
+        };
+        //Once sending isn't allowed anymore, send() fails right away
+        let writable = !slf.sending_state() || slf.write_buf.get_buf().ready();
+        Readiness {
+            readable: readable || closed || error,
+            writable: writable || error,
+            acceptable: false,
+            hung_up: fin_received || closed,
+            error,
+        }
+    }
+
+    ///Has the waker woken whenever the socket might have become readable or writable
+    pub fn watch(slf: Arc<Mutex<Self>>, key: u64, waker: &Waker) {
+        let slf = slf.lock().unwrap();
+        slf.read_buf.watch(key, waker);
+        slf.write_buf.watch(key, waker);
+    }
+
+    pub fn unwatch(slf: Arc<Mutex<Self>>, key: u64) {
+        let slf = slf.lock().unwrap();
+        slf.read_buf.unwatch(key);
+        slf.write_buf.unwatch(key);
+    }
+
+    ///Gathers congestion control and timer information about the socket
+    pub fn stats(slf: Arc<Mutex<Self>>) -> SocketStats {
+        let slf = slf.lock().unwrap();
+        let (srtt, rto) = {
+            let timer = slf.retr_timer.lock().unwrap();
+            (timer.srtt(), timer.rto)
+        };
+        let state = slf.state.read().unwrap().clone();
+        let recv_buffer = slf.read_buf.get_buf().capacity();
+        let write_buf = slf.write_buf.get_buf();
+        SocketStats {
+            state,
+            cc_name: write_buf.cc.name(),
+            cwnd: write_buf.cc.cwnd(),
+            ssthresh: write_buf.cc.ssthresh(),
+            in_recovery: write_buf.cc.in_recovery(),
+            srtt,
+            rto,
+            rem_window: write_buf.rem_window,
+            in_flight: write_buf.nxt,
+            mss: write_buf.mss(),
+            model: write_buf.cc.model(),
+            send_buffer: write_buf.capacity(),
+            recv_buffer,
+        }
+    }
 
     // TODO: CLEAN UP HORRIBLE UGLY ADDING TO RETRANSMISSION QUEUE
     //
@@ -458,7 +955,7 @@ impl ConnectionSocket {
         // Add to retransmission queue if it's a SYN or FIN
         if (flags & (SYN | FIN)) != 0 {
             self.add_to_queue(
-                packet.header.sequence_number,
+                SeqNum::new(packet.header.sequence_number),
                 packet.payload,
                 flags,
                 packet.header.checksum,
@@ -472,7 +969,7 @@ impl ConnectionSocket {
             Ok(packet) => {
                 // Only increment seq_num after the original data send
                 self.add_to_queue(
-                    packet.header.sequence_number,
+                    SeqNum::new(packet.header.sequence_number),
                     packet.payload.clone(),
                     ACK,
                     packet.header.checksum,
@@ -482,10 +979,14 @@ impl ConnectionSocket {
             Err(e) => eprintln!("Error sending data packet: {}", e),
         }
     }
-    fn send_probe(&mut self, data: Vec<u8>) {
+    ///Sends the first zero window probe and returns its sequence number
+    ///The probe byte is taken out of the send buffer like any other data, but isn't put on the retransmission queue
+    fn send_probe(&mut self, data: Vec<u8>) -> SeqNum {
+        let probe_seq = self.seq_num;
         self.build_and_send(data, ACK)
-            .expect("Error sending probe packe to partner");
-        //Don't adjust sequence number for probes
+            .expect("Error sending probe packet to partner");
+        self.seq_num += 1;
+        probe_seq
     }
     /// Builds and sends a TCP packet with the given payload and flags
     fn build_and_send(
@@ -494,28 +995,42 @@ impl ConnectionSocket {
         flags: u8,
     ) -> result::Result<TcpPacket, SendError<PacketBasis>> {
         let new_pack = self.build_packet(payload, flags);
+        self.acknowledgement_sent();
         let pbasis = self.packet_basis(new_pack.clone());
         match self.ip_sender.send(pbasis) {
             Ok(()) => Ok(new_pack),
             Err(e) => Err(e),
         }
     }
-    fn add_to_queue(&mut self, seq_num: u32, data: Vec<u8>, flags: u8, checksum: u16) {
+    ///Every segment we send acknowledges everything received so far, so nothing is owed anymore
+    fn acknowledgement_sent(&mut self) {
+        self.last_ack_sent = self.ack_num;
+        self.last_window_sent = self.read_buf.get_buf().window();
+        self.ack_pending = false;
+        self.unacked_bytes = 0;
+    }
+    fn add_to_queue(&mut self, seq_num: SeqNum, data: Vec<u8>, flags: u8, checksum: u16) {
         let mut write_buf = self.write_buf.get_buf();
         let retr_queue = &mut write_buf.retr_queue;
         retr_queue.add_segment(seq_num, data.clone(), flags, checksum);
     }
-    /// Takes in a TCP header and a u8 representing flags and builds a TCP packet
+    /// Takes in a payload and a u8 representing flags and builds a TCP packet
     fn build_packet(&self, payload: Vec<u8>, flags: u8) -> TcpPacket {
-        let window_size = { self.read_buf.get_buf().window() };
-        let mut tcp_header = TcpHeader::new(
-            self.src_addr.port,
-            self.dst_addr.port,
-            self.seq_num,
-            window_size,
-        );
-        tcp_header.acknowledgment_number = self.ack_num;
+        self.build_segment(self.seq_num, payload, flags)
+    }
+    /// Same as build_packet, but with an explicit sequence number (used for retransmissions)
+    fn build_segment(&self, seq_num: SeqNum, payload: Vec<u8>, flags: u8) -> TcpPacket {
+        let window = { self.read_buf.get_buf().window() };
+        //Windows in SYNs are never scaled (RFC 7323 2.2)
+        let win_shift = if (flags & SYN) != 0 { 0 } else { self.rcv_wind_shift };
+        let window_size = cmp::min(window >> win_shift, u16::MAX as u32) as u16;
+        let mut tcp_header =
+            TcpHeader::new(self.src_addr.port, self.dst_addr.port, seq_num.value(), window_size);
+        tcp_header.acknowledgment_number = self.ack_num.value();
         ConnectionSocket::set_flags(&mut tcp_header, flags);
+        tcp_header
+            .set_options(&self.segment_options(flags))
+            .expect("TCP options too long");
         let src_ip = self.src_addr.ip.clone().octets();
         let dst_ip = self.dst_addr.ip.clone().octets();
         let checksum = tcp_header
@@ -527,6 +1042,47 @@ impl ConnectionSocket {
             payload,
         }
     }
+    /// Options to put on an outgoing segment with the given flags
+    fn segment_options(&self, flags: u8) -> Vec<TcpOptionElement> {
+        let mut options = Vec::new();
+        if (flags & SYN) != 0 {
+            // Offer SACK in our SYN, only agree to it in our SYN-ACK if our partner offered it
+            if (flags & ACK) == 0 || self.sack_permitted {
+                options.push(TcpOptionElement::SelectiveAcknowledgementPermitted);
+            }
+            // Same deal for window scaling and timestamps
+            if (flags & ACK) == 0 || self.window_scaling {
+                options.push(TcpOptionElement::WindowScale(self.our_window_shift()));
+            }
+            if (flags & ACK) == 0 || self.timestamps {
+                options.push(TcpOptionElement::Timestamp(self.ts_clock(), self.ts_recent));
+            }
+            // Always sent on SYNs, whatever our partner did (RFC 9293 3.7.1)
+            options.push(TcpOptionElement::MaximumSegmentSize(self.local_mss));
+            return options;
+        }
+        if self.timestamps {
+            options.push(TcpOptionElement::Timestamp(self.ts_clock(), self.ts_recent));
+        }
+        if (flags & ACK) != 0 && self.sack_permitted {
+            let max_blocks = if self.timestamps { MAX_SACK_BLOCKS_WITH_TS } else { MAX_SACK_BLOCKS };
+            let blocks: Vec<(u32, u32)> = self
+                .read_buf
+                .get_buf()
+                .sack_blocks(max_blocks)
+                .into_iter()
+                .map(|(left, right)| (left.value(), right.value()))
+                .collect();
+            if let Some((first, rest)) = blocks.split_first() {
+                let mut others = [None; 3];
+                for (other, block) in others.iter_mut().zip(rest) {
+                    *other = Some(*block);
+                }
+                options.push(TcpOptionElement::SelectiveAcknowledgement(*first, others));
+            }
+        }
+        options
+    }
     /// Takes in a TCP header and a u8 representing flags and sets the corresponding flags in the header.
     fn set_flags(head: &mut TcpHeader, flags: u8) {
         if (flags & SYN) != 0 {
@@ -561,7 +1117,7 @@ impl ConnectionSocket {
     //SENDING AND RECVING
     //
 
-    // Loops through sending packets of max size 1500 bytes until everything's been sent
+    // Loops through filling the send buffer until everything has been buffered, waking send_onwards each time
     pub fn send(slf: Arc<Mutex<Self>>, mut to_send: Vec<u8>) -> Result<u32> {
         if !Self::send_allowed(Arc::clone(&slf)) {
             return Err(Error::new(
@@ -569,134 +1125,156 @@ impl ConnectionSocket {
                 "Send not allowed - already closed socket on this side",
             ));
         }
-        // Condvar for checking if the buffer has been updated
-        //let buf_update = Arc::new(Condvar::new());
-        //let thread_buf_update = Arc::clone(&buf_update);
-        // AtomicBool for checking if the send should stop
-        //let terminate_send = Arc::new(AtomicBool::new(false));
-        //let thread_terminate_send = Arc::clone(&terminate_send);
-        let (so_sender, snd_recver) = channel::<SendCmd>();
-
-        let thread_slf = Arc::clone(&slf);
-        let thread_send_onwards = thread::Builder::new()
-            .name("send_onwards".to_string())
-            .spawn(move || Self::send_onwards(thread_slf, snd_recver))
-            .expect("Could not spawn thread");
-        //Continuously wait for there to be space in the buffer and add data till buffer is full
-        let write_buf = {
+        let (write_buf, so_sender, nonblocking) = {
             let slf = slf.lock().unwrap();
-            Arc::clone(&slf.write_buf)
+            (Arc::clone(&slf.write_buf), slf.snd_sender.clone(), slf.nonblocking)
         };
+        //Continuously wait for there to be space in the buffer and add data till buffer is full
+        //Nonblocking sockets stop at the first full buffer instead, and only fail if nothing fit at all
         let mut bytes_sent = 0;
         while !to_send.is_empty() {
-            let mut writer = write_buf.wait();
+            let mut writer = match nonblocking {
+                true => match write_buf.try_wait() {
+                    Some(writer) => writer,
+                    None if bytes_sent > 0 => break,
+                    None => return Err(Error::new(ErrorKind::WouldBlock, "Send buffer is full")),
+                },
+                false => write_buf.wait(),
+            };
+            if let Some(e) = write_buf.aborted() {
+                return Err(e);
+            }
             let old_len = to_send.len();
             to_send = writer.fill_with(to_send);
             bytes_sent += old_len - to_send.len();
+            drop(writer);
             so_sender
                 .send(SendCmd::DataAvailable)
                 .expect("Error sending to send onwards");
         }
-        so_sender
-            .send(SendCmd::Stop)
-            .expect("Error sending to send onwards");
-        thread_send_onwards
-            .join()
-            .expect("Send onwards thread panicked");
         Ok(bytes_sent as u32)
     }
 
-    fn send_onwards(slf: Arc<Mutex<Self>>, snd_recver: Receiver<SendCmd>) {
+    /// Runs for the lifetime of the socket, sending buffered data whenever the send and congestion windows allow it
+    /// Woken up by send() when new data is buffered and by ack() when the windows open up
+    pub fn send_onwards(slf: Arc<Mutex<Self>>) {
         //Grab proper resources from slf before relinquishing its lock
-        let (write_buf, stop_probing_recver) = {
+        let (write_buf, stop_probing_recver, snd_recver) = {
             let slf = slf.lock().unwrap();
             (
                 Arc::clone(&slf.write_buf),
                 Arc::clone(&slf.stop_probing_recver),
+                Arc::clone(&slf.snd_recver),
             )
         };
-        let stop_probing_recver = stop_probing_recver.lock().unwrap(); //No other thread should need to use this while this thread is, so good to claim this lock for the duration of the threads existence
-                                                                       //Start data sending loop
+        //No other thread should need to use these while this thread is, so good to claim these locks for the duration of the threads existence
+        let stop_probing_recver = stop_probing_recver.lock().unwrap();
+        let snd_recver = snd_recver.lock().unwrap();
+        //Start data sending loop
         loop {
             let to_send = {
-                //Get data to send
-                let mut writer = write_buf.get_buf();
-                writer.next_data()
-            };
-            match to_send {
-                NextData::Data(data) => {
-                    let mut slf = slf.lock().unwrap();
+                //Get data to send - slf stays locked so that sequence numbers line up with the buffer
+                let mut slf = slf.lock().unwrap();
+                let next = write_buf.get_buf().next_data();
+                if let NextData::Data(data) = next {
+                    let pacing = write_buf.get_buf().pacing_delay(data.len());
                     slf.send_data(data);
+                    //Pace segments out if the congestion controller asks for it
+                    if let Some(delay) = pacing {
+                        drop(slf);
+                        thread::sleep(delay);
+                    }
+                    continue;
                 }
+                next
+            };
+            match to_send {
+                NextData::Data(_) => unreachable!("Data is sent while slf is still locked"),
                 NextData::ZeroWindow(probe) => {
+                    let probe_seq = {
+                        let mut slf = slf.lock().unwrap();
+                        slf.send_probe(probe.clone())
+                    };
                     //Run a thread to zero window probe
                     let slf_clone = Arc::clone(&slf);
                     let done_probing = Arc::new(AtomicBool::new(false));
                     let done_probing_clone = Arc::clone(&done_probing);
                     thread::spawn(move || {
-                        Self::zero_window_probe(slf_clone, probe, done_probing_clone)
+                        Self::zero_window_probe(slf_clone, probe, probe_seq, done_probing_clone)
                     });
-                    //Await signal to stop zero window probing
-                    stop_probing_recver.recv().unwrap();
-                    //Stop zero window probing thread and recover from probing
-                    done_probing.store(true, Ordering::SeqCst);
-                    {
-                        //Account for probe packet now successfully transmitted
-                        let mut slf = slf.lock().unwrap();
-                        slf.seq_num += 1;
+                    //Await signal to stop zero window probing (our probe byte got acknowledged)
+                    if stop_probing_recver.recv().is_err() {
+                        return;
                     }
+                    //Stop zero window probing thread
+                    done_probing.store(true, Ordering::SeqCst);
                 }
                 NextData::NoData => {
-                    //Check to see if we're just done sending
-                    match snd_recver
-                        .recv()
-                        .expect("Error receiving from sending thread")
-                    {
-                        SendCmd::DataAvailable => {} //Continue, we now have data available
-                        SendCmd::Stop => return,     //Stop sending, we're done
+                    //Wait until there might be more to send
+                    match snd_recver.recv() {
+                        Ok(SendCmd::DataAvailable) => {} //Continue, we now might have data available
+                        Ok(SendCmd::Stop) | Err(_) => return, //Stop sending, we're done
                     }
                 }
             }
         }
     }
+    /// Runs for the lifetime of the socket, sending acknowledgements that absorb_and_acknowledge held back
+    /// once the delay runs out - unless something else we sent carried the acknowledgement first
+    pub fn delayed_acker(slf: Arc<Mutex<Self>>) {
+        let (ack_recver, ack_delay) = {
+            let slf = slf.lock().unwrap();
+            (Arc::clone(&slf.ack_recver), slf.config.ack_delay)
+        };
+        let ack_recver = ack_recver.lock().unwrap();
+        loop {
+            match ack_recver.recv() {
+                Ok(AckCmd::Arm) => {}
+                Ok(AckCmd::Stop) | Err(_) => return,
+            }
+            thread::sleep(ack_delay);
+            let mut slf = slf.lock().unwrap();
+            if let TcpState::Closed = *slf.state.read().unwrap() {
+                return;
+            }
+            if slf.ack_pending {
+                slf.send_flags(ACK);
+            }
+        }
+    }
     fn zero_window_probe(
         slf: Arc<Mutex<Self>>,
         probe_data: Vec<u8>,
+        probe_seq: SeqNum,
         done_probing: Arc<AtomicBool>,
     ) {
+        //Keep resending the probe byte until our partner has room to accept it and acknowledges it
+        let zwp_interval = slf.lock().unwrap().config.zwp_interval;
         loop {
-            thread::sleep(Duration::from_millis(ZWP_TIMEOUT));
+            thread::sleep(zwp_interval);
             if done_probing.load(Ordering::SeqCst) {
                 return; // stop probing
             }
             let mut slf = slf.lock().unwrap();
-
-            // Before sending another probe, check if the remote window is still zero
-            let current_window = {
-                let write_buf = slf.write_buf.get_buf();
-                write_buf.rem_window
-            };
-
-            if current_window == 0 {
-                slf.send_probe(probe_data.clone());
-            } else {
-                // Window opened, stop probing
-                done_probing.store(true, Ordering::SeqCst);
+            if let TcpState::Closed = *slf.state.read().unwrap() {
                 return;
             }
+            slf.send_segment(probe_seq, probe_data.clone(), ACK);
         }
     }
     fn send_allowed(slf: Arc<Mutex<Self>>) -> bool {
         let slf = slf.lock().unwrap();
-        let proper_state = match *slf.state.read().unwrap() {
+        slf.sending_state()
+    }
+    fn sending_state(&self) -> bool {
+        !matches!(
+            *self.state.read().unwrap(),
             TcpState::FinWait1
-            | TcpState::FinWait2
-            | TcpState::TimeWait
-            | TcpState::LastAck
-            | TcpState::Closed => false,
-            _ => true,
-        };
-        proper_state
+                | TcpState::FinWait2
+                | TcpState::TimeWait
+                | TcpState::LastAck
+                | TcpState::Closed
+        )
     }
     pub fn receive(slf: Arc<Mutex<Self>>, bytes: u16) -> Result<Vec<u8>> {
         if !Self::receive_allowed(Arc::clone(&slf)) {
@@ -705,38 +1283,61 @@ impl ConnectionSocket {
                 "Reception not allow; there's nothing left to receive",
             ));
         }
-        let read_buf = {
+        let (read_buf, nonblocking) = {
             let slf = slf.lock().unwrap();
-            Arc::clone(&slf.read_buf)
+            (Arc::clone(&slf.read_buf), slf.nonblocking)
+        };
+        let mut recv_buf: std::sync::MutexGuard<'_, RecvBuf> = match nonblocking {
+            true => read_buf
+                .try_wait()
+                .ok_or_else(|| Error::new(ErrorKind::WouldBlock, "Nothing to receive yet"))?,
+            false => read_buf.wait(),
         };
-        let mut recv_buf: std::sync::MutexGuard<'_, RecvBuf> = read_buf.wait();
+        if let Some(e) = read_buf.aborted() {
+            return Err(e);
+        }
         let received = recv_buf.read(bytes);
+        drop(recv_buf);
         if received.is_empty() {
             return Err(Error::new(
                 ErrorKind::Unsupported,
                 "Reception not allow; there's nothing left to receive",
             ));
         }
+        Self::window_update(slf);
         Ok(received)
     }
+    ///Lets our partner know reading opened the window back up, if the one we last advertised was small enough to be holding it back
+    ///Only windows that at least doubled, by a segment or half the buffer, are worth an ACK of their own (RFC 1122 4.2.3.3)
+    fn window_update(slf: Arc<Mutex<Self>>) {
+        let mut slf = slf.lock().unwrap();
+        let receiving = matches!(
+            *slf.state.read().unwrap(),
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2
+        );
+        if !receiving || slf.rcv_mss == 0 {
+            return;
+        }
+        let (window, capacity) = {
+            let read_buf = slf.read_buf.get_buf();
+            (read_buf.window(), read_buf.capacity())
+        };
+        let threshold = cmp::min(capacity / 2, slf.rcv_mss) as u32;
+        if window >= 2 * slf.last_window_sent && window - slf.last_window_sent >= threshold {
+            slf.send_flags(ACK);
+        }
+    }
     fn receive_allowed(slf: Arc<Mutex<Self>>) -> bool {
         let slf = slf.lock().unwrap();
-        let proper_state = match *slf.state.read().unwrap() {
-            //Proper state is any state where close() hasn't already been called on us
-            TcpState::FinWait1
-            | TcpState::FinWait2
-            | TcpState::TimeWait
-            | TcpState::LastAck
-            | TcpState::Closed => false,
-            _ => true,
-        };
+        //Shutting down writing doesn't stop us from reading until our partner's FIN, shutting down reading does (through the buffer)
+        let proper_state = !matches!(*slf.state.read().unwrap(), TcpState::Closed);
         let data_out_there = slf.read_buf.get_buf().can_receive();
         proper_state && data_out_there
     }
-    fn send_segment(&mut self, seq_num: u32, payload: Vec<u8>, flags: u8, checksum: u16) {
-        let mut tpack: TcpPacket = self.build_packet(payload, flags);
-        tpack.header.checksum = checksum;
-        tpack.header.sequence_number = seq_num;
+    fn send_segment(&mut self, seq_num: SeqNum, payload: Vec<u8>, flags: u8) {
+        //Rebuilt from scratch, as our ack number and window have likely changed since the original was sent
+        let tpack: TcpPacket = self.build_segment(seq_num, payload, flags);
+        self.acknowledgement_sent();
         let pbasis = self.packet_basis(tpack);
         match self.ip_sender.send(pbasis) {
             Ok(()) => (),
@@ -744,23 +1345,76 @@ impl ConnectionSocket {
         }
     }
     ///Initializes closing procedure
-    pub fn close(slf: Arc<Mutex<Self>>) {
-        // First wait for all data to be sent and acknowledged
-        loop {
-            let send_complete = {
-                let slf = slf.lock().unwrap();
-                let write_buf = slf.write_buf.get_buf();
-                // Check if all data has been sent and acknowledged
-                write_buf.circ_buffer.is_empty()
-                    && write_buf.nxt == 0
-                    && !write_buf.probing
-                    && write_buf.retr_queue.is_empty()
-            };
-
-            if send_complete {
-                break;
+    ///With linger set, buffered data only gets that long to be sent before the connection is reset - no time at all resets it right away
+    pub fn close(slf: Arc<Mutex<Self>>) -> Result<()> {
+        let linger = slf.lock().unwrap().config.linger;
+        match linger {
+            Some(linger) if linger.is_zero() => {
+                Self::reset(slf);
+                Ok(())
+            }
+            Some(linger) => {
+                Self::shutdown(Arc::clone(&slf), How::Read)?;
+                Self::shutdown_write(slf, Some(Instant::now() + linger))
+            }
+            None => Self::shutdown(slf, How::Both),
+        }
+    }
+    ///Abortive close (RFC 9293 3.10.4) - anything still buffered is dropped, and our partner gets a reset if it knows about us
+    ///Blocked sends and receives fail with ConnectionAborted
+    pub fn reset(slf: Arc<Mutex<Self>>) {
+        let mut slf = slf.lock().unwrap();
+        let state = slf.state.read().unwrap().clone();
+        if let TcpState::SynRecvd
+        | TcpState::Established
+        | TcpState::FinWait1
+        | TcpState::FinWait2
+        | TcpState::CloseWait = state
+        {
+            //Our partner only takes a reset that lands right where it expects our next byte, which can't be past its window
+            //(a zero window probe may have taken us there)
+            let seq_num = slf.seq_num.min(slf.write_buf.get_buf().window_end());
+            slf.send_segment(seq_num, Vec::new(), RST);
+        }
+        slf.abort(ErrorKind::ConnectionAborted, "Connection aborted");
+    }
+    ///Shuts down reading, writing or both
+    ///Shutting down writing sends our FIN once everything buffered has been sent and acknowledged, blocking until then
+    ///Nonblocking sockets fail with WouldBlock instead, as long as there's still data to get across
+    pub fn shutdown(slf: Arc<Mutex<Self>>, how: How) -> Result<()> {
+        if let How::Write | How::Both = how {
+            let slf = slf.lock().unwrap();
+            if slf.nonblocking && slf.sending_state() && !Self::is_send_complete(&slf.write_buf.get_buf()) {
+                return Err(Error::new(ErrorKind::WouldBlock, "Data still waiting to be sent and acknowledged"));
             }
-            thread::sleep(Duration::from_millis(10));
+        }
+        if let How::Read | How::Both = how {
+            let read_buf = Arc::clone(&slf.lock().unwrap().read_buf);
+            read_buf.get_buf().shut_down();
+            read_buf.alert_ready(); //Lets any receiving thread give up
+        }
+        if let How::Write | How::Both = how {
+            Self::shutdown_write(slf, None)?;
+        }
+        Ok(())
+    }
+    ///Sends our FIN once all data has been sent and acknowledged - if that hasn't happened by the deadline, resets the connection instead
+    fn shutdown_write(slf: Arc<Mutex<Self>>, deadline: Option<Instant>) -> Result<()> {
+        if !slf.lock().unwrap().sending_state() {
+            return Ok(()); //Our FIN already went out
+        }
+        // First wait for all data to be sent and acknowledged
+        let write_buf = Arc::clone(&slf.lock().unwrap().write_buf);
+        let send_complete = write_buf.wait_for(Self::is_send_complete, deadline);
+        if write_buf.aborted().is_some() {
+            return Ok(()); //Nothing left to close
+        }
+        if !send_complete {
+            Self::reset(slf);
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                "Data still unacknowledged after lingering - connection reset",
+            ));
         }
 
         // Now we can proceed with the closing sequence
@@ -771,8 +1425,10 @@ impl ConnectionSocket {
                 TcpState::Established => TcpState::FinWait1,
                 TcpState::CloseWait => TcpState::LastAck,
                 _ => {
-                    eprintln!("Closing in non closing state not allowed!");
-                    return;
+                    return Err(Error::new(
+                        ErrorKind::NotConnected,
+                        "Closing in non closing state not allowed!",
+                    ))
                 }
             }
         };
@@ -781,6 +1437,7 @@ impl ConnectionSocket {
         slf.send_flags(FIN | ACK);
         let mut state = slf.state.write().unwrap();
         *state = new_state;
+        Ok(())
     }
 
     // Helper method to check if all data has been sent and acknowledged
@@ -796,3 +1453,359 @@ enum SendCmd {
     DataAvailable,
     Stop,
 }
+
+enum AckCmd {
+    Arm, //An acknowledgement is being delayed
+    Stop,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp_config::DEFAULT_BUFFER_SIZE;
+
+    const PEER_ISN: u32 = 5000;
+
+    /// An established connection, along with the packets it sends and the socket IDs it reports as closed
+    fn established(config: TcpConfig) -> (ConnectionSocket, Receiver<PacketBasis>, Receiver<SocketId>) {
+        let (ip_sender, ip_recver) = channel();
+        let (closed_sender, closed_recver) = channel();
+        let mut sock = ConnectionSocket::new(
+            Arc::new(RwLock::new(TcpState::Established)),
+            TcpAddress::new(Ipv4Addr::new(10, 0, 0, 1), 1000),
+            TcpAddress::new(Ipv4Addr::new(10, 0, 0, 2), 2000),
+            SeqNum::new(100),
+            Arc::new(closed_sender),
+            Arc::new(ip_sender),
+            1460,
+            config,
+        );
+        sock.set_init_ack(SeqNum::new(PEER_ISN));
+        (sock, ip_recver, closed_recver)
+    }
+
+    /// Everything the socket has sent so far
+    fn sent(ip_recver: &Receiver<PacketBasis>) -> Vec<TcpPacket> {
+        ip_recver
+            .try_iter()
+            .map(|pbasis| deserialize_tcp(pbasis.msg).unwrap())
+            .collect()
+    }
+
+    /// A segment from our partner, <offset> bytes into its data
+    fn incoming(sock: &ConnectionSocket, offset: u32, flags: u8, payload: Vec<u8>) -> TcpPacket {
+        let mut head = TcpHeader::new(2000, 1000, (PEER_ISN + 1).wrapping_add(offset), u16::MAX);
+        head.acknowledgment_number = sock.seq_num.value();
+        ConnectionSocket::set_flags(&mut head, flags);
+        TcpPacket::new(head, payload)
+    }
+
+    fn peer_seq(offset: u32) -> SeqNum {
+        SeqNum::new(PEER_ISN + 1) + offset
+    }
+
+    #[test]
+    fn test_sack_blocks_round_trip() {
+        let (mut sock, ip_recver, _closed) = established(TcpConfig::default());
+        sock.sack_permitted = true;
+        for offset in [100, 300, 200] {
+            let tpack = incoming(&sock, offset, ACK, vec![0; 50]);
+            sock.absorb_and_acknowledge(tpack);
+        }
+        // Out of order data gets acknowledged right away, the block with the latest arrival coming first
+        let acks = sent(&ip_recver);
+        assert_eq!(acks.len(), 3);
+        let options = TcpOptions::parse(&acks[2].header);
+        assert_eq!(SeqNum::new(acks[2].header.acknowledgment_number), peer_seq(0));
+        assert_eq!(
+            options.sack_blocks,
+            vec![(peer_seq(200), peer_seq(250)), (peer_seq(100), peer_seq(150)), (peer_seq(300), peer_seq(350))]
+        );
+        // Only four blocks fit in the option space, or three next to a timestamp
+        for offset in [400, 500] {
+            let tpack = incoming(&sock, offset, ACK, vec![0; 50]);
+            sock.absorb_and_acknowledge(tpack);
+        }
+        let options = TcpOptions::parse(&sent(&ip_recver).pop().unwrap().header);
+        assert_eq!(options.sack_blocks.len(), MAX_SACK_BLOCKS);
+        assert_eq!(options.sack_blocks[0], (peer_seq(500), peer_seq(550)));
+        sock.timestamps = true;
+        sock.send_flags(ACK);
+        let options = TcpOptions::parse(&sent(&ip_recver).pop().unwrap().header);
+        assert_eq!(options.sack_blocks.len(), MAX_SACK_BLOCKS_WITH_TS);
+    }
+
+    #[test]
+    fn test_syn_backoff() {
+        let config = TcpConfig {
+            rto_initial: Duration::from_millis(100),
+            connect_timeout: Duration::from_millis(1000),
+            ..TcpConfig::default()
+        };
+        let (sock, ip_recver, _closed) = established(config);
+        let sock = Arc::new(Mutex::new(sock));
+        let start = Instant::now();
+        let handshake_recver = ConnectionSocket::first_syn(Arc::clone(&sock));
+        let timer_sock = Arc::clone(&sock);
+        thread::spawn(move || ConnectionSocket::time_check(timer_sock));
+        // Nobody answers, so the SYN goes out again after 100ms, then 200ms, then 400ms until connect() gives up
+        let mut syns_sent_at = Vec::new();
+        while let Ok(pbasis) = ip_recver.recv_timeout(Duration::from_millis(1200).saturating_sub(start.elapsed())) {
+            assert!(deserialize_tcp(pbasis.msg).unwrap().header.syn);
+            syns_sent_at.push(start.elapsed());
+        }
+        assert_eq!(handshake_recver.recv().unwrap().unwrap_err().kind(), ErrorKind::TimedOut);
+        // Counted from when each got here, so only how long after the first SYN they went out is certain
+        assert_eq!(syns_sent_at.len(), 4);
+        for (sent_at, due) in syns_sent_at.iter().zip([0, 100, 300, 700]) {
+            assert!(*sent_at >= Duration::from_millis(due), "{sent_at:?}");
+        }
+    }
+
+    #[test]
+    fn test_max_retransmissions_aborts() {
+        let config = TcpConfig {
+            rto_initial: Duration::from_millis(100),
+            max_retransmissions: Some(2),
+            ..TcpConfig::default()
+        };
+        let (sock, ip_recver, closed_recver) = established(config);
+        sock.write_buf.get_buf().update_window(u16::MAX as u32);
+        let sock = Arc::new(Mutex::new(sock));
+        let (timer_sock, sending_sock) = (Arc::clone(&sock), Arc::clone(&sock));
+        thread::spawn(move || ConnectionSocket::time_check(timer_sock));
+        thread::spawn(move || ConnectionSocket::send_onwards(sending_sock));
+        let receiving_sock = Arc::clone(&sock);
+        let receiving = thread::spawn(move || ConnectionSocket::receive(receiving_sock, 16));
+        ConnectionSocket::send(Arc::clone(&sock), vec![7; 100]).unwrap();
+        // Nobody acknowledges it, so it goes out three times before the connection is given up on, long before the user timeout
+        let sid = closed_recver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(sid, sock.lock().unwrap().sid);
+        assert_eq!(sent(&ip_recver).iter().filter(|tpack| !tpack.payload.is_empty()).count(), 3);
+        assert_eq!(receiving.join().unwrap().unwrap_err().kind(), ErrorKind::TimedOut);
+    }
+
+    /// An established connection probing once it's been idle for 200ms, then every 100ms, giving up after 3 probes
+    fn keepalive_socket() -> (Arc<Mutex<ConnectionSocket>>, Receiver<PacketBasis>, Receiver<SocketId>) {
+        let config = TcpConfig {
+            keepalive: true,
+            keepalive_params: Keepalive {
+                idle: Duration::from_millis(200),
+                interval: Duration::from_millis(100),
+                probes: 3,
+            },
+            ..TcpConfig::default()
+        };
+        let (sock, ip_recver, closed_recver) = established(config);
+        let sock = Arc::new(Mutex::new(sock));
+        let timer_sock = Arc::clone(&sock);
+        thread::spawn(move || ConnectionSocket::time_check(timer_sock));
+        (sock, ip_recver, closed_recver)
+    }
+
+    /// Waits for the next keepalive probe, checking it's an empty ACK of data we've already had acknowledged
+    fn next_probe(sock: &Arc<Mutex<ConnectionSocket>>, ip_recver: &Receiver<PacketBasis>) -> Instant {
+        let probe = deserialize_tcp(ip_recver.recv_timeout(Duration::from_secs(2)).unwrap().msg).unwrap();
+        assert!(has_only_flags(&probe.header, ACK) && probe.payload.is_empty());
+        assert_eq!(SeqNum::new(probe.header.sequence_number), sock.lock().unwrap().seq_num - 1);
+        Instant::now()
+    }
+
+    #[test]
+    fn test_keepalive_answered() {
+        let (sock, ip_recver, closed_recver) = keepalive_socket();
+        let mut quiet_since = Instant::now();
+        for _ in 0..3 {
+            let probed_at = next_probe(&sock, &ip_recver);
+            assert!(probed_at - quiet_since >= Duration::from_millis(200));
+            // Our partner's answer starts the idle time over
+            let mut answer = incoming(&sock.lock().unwrap(), 0, ACK, Vec::new());
+            let ip_head = Ipv4Header {
+                source: [10, 0, 0, 2],
+                destination: [10, 0, 0, 1],
+                protocol: IpNumber::TCP,
+                ..Default::default()
+            };
+            answer.header.checksum = answer.header.calc_checksum_ipv4(&ip_head, &[]).unwrap();
+            quiet_since = Instant::now();
+            ConnectionSocket::handle_packet(Arc::clone(&sock), answer, ip_head);
+        }
+        assert!(closed_recver.try_recv().is_err());
+        assert!(matches!(*sock.lock().unwrap().state.read().unwrap(), TcpState::Established));
+    }
+
+    #[test]
+    fn test_keepalive_unanswered() {
+        let (sock, ip_recver, closed_recver) = keepalive_socket();
+        let start = Instant::now();
+        for due in [200, 300, 400] {
+            let probed_at = next_probe(&sock, &ip_recver);
+            assert!(probed_at - start >= Duration::from_millis(due));
+        }
+        // Nobody answered any of them, so the connection gets given up on an interval after the last
+        let sid = closed_recver.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(sid, sock.lock().unwrap().sid);
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert!(sent(&ip_recver).is_empty());
+        assert!(matches!(*sock.lock().unwrap().state.read().unwrap(), TcpState::Closed));
+    }
+
+    /// MSS we'd use after our partner's SYN came with the input options, our own MSS being 1460
+    fn negotiated_mss(options: &[TcpOptionElement]) -> usize {
+        let (mut sock, _ip_recver, _closed) = established(TcpConfig::default());
+        let mut syn = incoming(&sock, 0, SYN, Vec::new()).header;
+        syn.set_options(options).unwrap();
+        sock.negotiate_options(&syn);
+        let mss = sock.write_buf.get_buf().mss();
+        mss
+    }
+
+    #[test]
+    fn test_negotiated_mss() {
+        use TcpOptionElement::*;
+        // The smaller of the two MSSes, less room for the options data segments will carry
+        assert_eq!(negotiated_mss(&[MaximumSegmentSize(1400)]), 1400);
+        assert_eq!(negotiated_mss(&[MaximumSegmentSize(9000)]), 1460);
+        assert_eq!(negotiated_mss(&[MaximumSegmentSize(1400), SelectiveAcknowledgementPermitted]), 1400 - 36);
+        assert_eq!(
+            negotiated_mss(&[MaximumSegmentSize(1400), SelectiveAcknowledgementPermitted, Timestamp(1, 0)]),
+            1400 - 12 - 28
+        );
+        // No MSS means the one every host has to take (RFC 9293 3.7.1)
+        assert_eq!(negotiated_mss(&[]), DEFAULT_MSS as usize);
+        assert_eq!(negotiated_mss(&[Timestamp(1, 0)]), DEFAULT_MSS as usize - 12);
+    }
+
+    /// A segment from our partner carrying a timestamp
+    fn timestamped(sock: &ConnectionSocket, offset: u32, ts_val: u32) -> TcpPacket {
+        let mut tpack = incoming(sock, offset, ACK, vec![0; 10]);
+        tpack.header.set_options(&[TcpOptionElement::Timestamp(ts_val, 7)]).unwrap();
+        tpack
+    }
+
+    #[test]
+    fn test_paws_drops_old_timestamps() {
+        let (mut sock, ip_recver, _closed) = established(TcpConfig::default());
+        sock.timestamps = true;
+        sock.ts_recent = 1000;
+        sock.last_ack_sent = sock.ack_num;
+        assert!(!sock.check_timestamp(&timestamped(&sock, 0, 999)));
+        assert_eq!(sock.ts_recent, 1000);
+        // Our partner gets an ACK to let it know where we're at
+        assert_eq!(sent(&ip_recver).len(), 1);
+        // The same timestamp as last time is still fine
+        assert!(sock.check_timestamp(&timestamped(&sock, 0, 1000)));
+        assert_eq!(sock.echoed_ts, Some(7));
+    }
+
+    #[test]
+    fn test_missing_timestamp_dropped() {
+        let (mut sock, ip_recver, _closed) = established(TcpConfig::default());
+        sock.timestamps = true;
+        sock.last_ack_sent = sock.ack_num;
+        assert!(!sock.check_timestamp(&incoming(&sock, 0, ACK, vec![0; 10])));
+        // Silently, unlike an old timestamp
+        assert!(sent(&ip_recver).is_empty());
+        assert!(sock.check_timestamp(&timestamped(&sock, 0, 1000)));
+    }
+
+    #[test]
+    fn test_paws_accepts_wrapped_timestamps() {
+        let (mut sock, ip_recver, _closed) = established(TcpConfig::default());
+        sock.timestamps = true;
+        sock.ts_recent = u32::MAX - 10;
+        sock.last_ack_sent = sock.ack_num;
+        assert!(sock.check_timestamp(&timestamped(&sock, 0, 5)));
+        assert_eq!(sock.ts_recent, 5);
+        assert!(sent(&ip_recver).is_empty());
+        // Segments past what we last acknowledged don't update TS.Recent (RFC 7323 4.3)
+        assert!(sock.check_timestamp(&timestamped(&sock, 100, 20)));
+        assert_eq!(sock.ts_recent, 5);
+    }
+
+    #[test]
+    fn test_ack_every_second_full_segment() {
+        let (mut sock, ip_recver, _closed) = established(TcpConfig::default());
+        sock.absorb_and_acknowledge(incoming(&sock, 0, ACK, vec![0; 1000]));
+        assert!(sent(&ip_recver).is_empty());
+        assert!(sock.ack_pending);
+        sock.absorb_and_acknowledge(incoming(&sock, 1000, ACK, vec![0; 1000]));
+        let acks = sent(&ip_recver);
+        assert_eq!(acks.len(), 1);
+        assert_eq!(SeqNum::new(acks[0].header.acknowledgment_number), peer_seq(2000));
+        assert!(!sock.ack_pending);
+    }
+
+    #[test]
+    fn test_out_of_order_acked_right_away() {
+        let (mut sock, ip_recver, _closed) = established(TcpConfig::default());
+        sock.absorb_and_acknowledge(incoming(&sock, 1000, ACK, vec![0; 1000]));
+        let acks = sent(&ip_recver);
+        assert_eq!(acks.len(), 1);
+        assert_eq!(SeqNum::new(acks[0].header.acknowledgment_number), peer_seq(0));
+        // Filling the hole gets acknowledged right away too
+        sock.absorb_and_acknowledge(incoming(&sock, 0, ACK, vec![0; 1000]));
+        let acks = sent(&ip_recver);
+        assert_eq!(acks.len(), 1);
+        assert_eq!(SeqNum::new(acks[0].header.acknowledgment_number), peer_seq(2000));
+    }
+
+    #[test]
+    fn test_delayed_ack_timer() {
+        let config = TcpConfig {
+            ack_delay: Duration::from_millis(50),
+            ..TcpConfig::default()
+        };
+        let (sock, ip_recver, _closed) = established(config);
+        let sock = Arc::new(Mutex::new(sock));
+        let acker = {
+            let sock = Arc::clone(&sock);
+            thread::spawn(move || ConnectionSocket::delayed_acker(sock))
+        };
+        let start = Instant::now();
+        {
+            let mut sock = sock.lock().unwrap();
+            let tpack = incoming(&sock, 0, ACK, vec![0; 100]);
+            sock.absorb_and_acknowledge(tpack);
+        }
+        assert!(sent(&ip_recver).is_empty());
+        let ack = deserialize_tcp(ip_recver.recv_timeout(Duration::from_secs(1)).unwrap().msg).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(SeqNum::new(ack.header.acknowledgment_number), peer_seq(100));
+        sock.lock().unwrap().ack_sender.send(AckCmd::Stop).unwrap();
+        acker.join().unwrap();
+    }
+
+    #[test]
+    fn test_reset_with_exact_sequence_number() {
+        let (mut sock, ip_recver, closed_recver) = established(TcpConfig::default());
+        sock.handle_reset(&incoming(&sock, 0, RST, Vec::new()));
+        assert!(matches!(*sock.state.read().unwrap(), TcpState::Closed));
+        assert_eq!(sock.read_buf.aborted().map(|e| e.kind()), Some(ErrorKind::ConnectionReset));
+        assert_eq!(closed_recver.try_recv(), Ok(sock.sid));
+        assert!(sent(&ip_recver).is_empty());
+    }
+
+    #[test]
+    fn test_reset_in_window_gets_challenge_ack() {
+        let (mut sock, ip_recver, closed_recver) = established(TcpConfig::default());
+        sock.handle_reset(&incoming(&sock, 1000, RST, Vec::new()));
+        assert!(matches!(*sock.state.read().unwrap(), TcpState::Established));
+        let acks = sent(&ip_recver);
+        assert_eq!(acks.len(), 1);
+        assert!(acks[0].header.ack && !acks[0].header.rst);
+        assert_eq!(SeqNum::new(acks[0].header.acknowledgment_number), peer_seq(0));
+        assert!(closed_recver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_reset_out_of_window_dropped() {
+        let (mut sock, ip_recver, closed_recver) = established(TcpConfig::default());
+        for offset in [DEFAULT_BUFFER_SIZE as u32, u32::MAX - 10] {
+            sock.handle_reset(&incoming(&sock, offset, RST, Vec::new()));
+        }
+        assert!(matches!(*sock.state.read().unwrap(), TcpState::Established));
+        assert!(sent(&ip_recver).is_empty());
+        assert!(closed_recver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::congestion::*;
+use crate::seq_num::SeqNum;
+use std::cmp;
+use std::time::{Duration, Instant};
+
+/* CUBIC (RFC 9438):
+
+Window sizes in the formulas below are in segments, cwnd itself is kept in bytes.
+
+CONSTANTS:
+- C = 0.4
+- BETA_CUBIC = 0.7
+- ALPHA_CUBIC = 3 * (1 - BETA_CUBIC) / (1 + BETA_CUBIC) (TCP friendly increase per RTT)
+
+CONGESTION AVOIDANCE (start of an epoch is the first ACK after a loss):
+K = cbrt((W_max - cwnd_epoch) / C)
+W_cubic(t) = C * (t - K)^3 + W_max
+target = clamp(W_cubic(t + RTT), cwnd, 1.5 * cwnd)
+W_est += ALPHA_CUBIC * acked / cwnd (Reno-friendly estimate)
+- If W_cubic(t) < W_est -> TCP friendly region, cwnd = W_est
+- Otherwise -> cwnd += (target - cwnd) / cwnd per acknowledged segment
+
+ON LOSS:
+- Fast convergence: if cwnd < W_max, W_max = cwnd * (1 + BETA_CUBIC) / 2, else W_max = cwnd
+ssthresh = max(cwnd * BETA_CUBIC, 2 * SMSS)
+Fast recovery and RTO handling otherwise the same as NewReno
+
+*/
+
+const C: f64 = 0.4;
+const BETA_CUBIC: f64 = 0.7;
+const ALPHA_CUBIC: f64 = 3.0 * (1.0 - BETA_CUBIC) / (1.0 + BETA_CUBIC);
+
+#[derive(Debug)]
+pub struct Cubic {
+    mss: usize,
+    cwnd: usize,
+    ssthresh: usize,
+    w_max: f64, //Window (in segments) right before the last reduction
+    k: f64,     //Time (in seconds) it takes to grow back to w_max
+    w_est: f64, //Reno-friendly window estimate (in segments)
+    epoch_start: Option<Instant>,
+    srtt: Option<Duration>,
+    recover: Option<SeqNum>, // Some(snd.nxt at time of loss) while in fast recovery
+}
+
+impl Cubic {
+    pub fn new(mss: usize) -> Cubic {
+        Cubic {
+            mss,
+            cwnd: initial_window(mss),
+            ssthresh: usize::MAX,
+            w_max: 0.0,
+            k: 0.0,
+            w_est: 0.0,
+            epoch_start: None,
+            srtt: None,
+            recover: None,
+        }
+    }
+    /// W_cubic(t), in segments
+    fn w_cubic(&self, t: f64) -> f64 {
+        C * (t - self.k).powi(3) + self.w_max
+    }
+    fn segments(&self, bytes: usize) -> f64 {
+        bytes as f64 / self.mss as f64
+    }
+    /// Starts a new congestion avoidance epoch
+    fn start_epoch(&mut self, now: Instant) {
+        let cwnd = self.segments(self.cwnd);
+        self.epoch_start = Some(now);
+        if cwnd < self.w_max {
+            self.k = ((self.w_max - cwnd) / C).cbrt();
+        } else {
+            self.k = 0.0;
+            self.w_max = cwnd;
+        }
+        self.w_est = cwnd;
+    }
+    /// Congestion avoidance window growth for `acked` newly acknowledged bytes
+    fn avoid_congestion(&mut self, acked: usize, now: Instant) {
+        if self.epoch_start.is_none() {
+            self.start_epoch(now);
+        }
+        let t = now.duration_since(self.epoch_start.unwrap()).as_secs_f64();
+        let rtt = self.srtt.unwrap_or_default().as_secs_f64();
+        let cwnd = self.segments(self.cwnd);
+        let acked_segs = self.segments(acked);
+
+        self.w_est += ALPHA_CUBIC * acked_segs / cwnd;
+        let new_cwnd = if self.w_cubic(t) < self.w_est {
+            // TCP friendly region
+            self.w_est
+        } else {
+            let target = self.w_cubic(t + rtt).clamp(cwnd, 1.5 * cwnd);
+            cwnd + (target - cwnd) / cwnd * acked_segs
+        };
+        self.cwnd = cmp::max((new_cwnd * self.mss as f64) as usize, self.cwnd);
+    }
+    /// Multiplicative decrease shared by fast retransmit and RTO
+    fn reduce(&mut self) {
+        let cwnd = self.segments(self.cwnd);
+        // Fast convergence - release bandwidth to newer flows
+        self.w_max = if cwnd < self.w_max {
+            cwnd * (1.0 + BETA_CUBIC) / 2.0
+        } else {
+            cwnd
+        };
+        self.ssthresh = cmp::max(
+            (self.cwnd as f64 * BETA_CUBIC) as usize,
+            2 * self.mss,
+        );
+        self.epoch_start = None;
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn name(&self) -> &'static str {
+        "cubic"
+    }
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+    fn in_recovery(&self) -> bool {
+        self.recover.is_some()
+    }
+    fn on_ack(&mut self, ack: AckInfo) -> CcAction {
+        if let Some(recover) = self.recover {
+            if ack.ack_num >= recover {
+                // Full ACK - leave fast recovery at ssthresh
+                self.cwnd = self.ssthresh;
+                self.recover = None;
+                return CcAction::Nothing;
+            }
+            // Partial ACK - the next hole needs retransmitting right away
+            self.cwnd = self.cwnd.saturating_sub(ack.acked);
+            if ack.acked >= self.mss {
+                self.cwnd += self.mss;
+            }
+            self.cwnd = cmp::max(self.cwnd, self.mss);
+            return CcAction::Retransmit;
+        }
+        if self.cwnd < self.ssthresh {
+            // Slow start
+            self.cwnd += cmp::min(ack.acked, self.mss);
+        } else {
+            self.avoid_congestion(ack.acked, Instant::now());
+        }
+        CcAction::Nothing
+    }
+    fn on_dup_ack(&mut self, dup_count: u32, snd_nxt: SeqNum, _in_flight: usize) -> CcAction {
+        if self.in_recovery() {
+            self.cwnd += self.mss;
+            return CcAction::Nothing;
+        }
+        if dup_count == DUP_ACK_THRESHOLD {
+            self.reduce();
+            self.cwnd = self.ssthresh + 3 * self.mss;
+            self.recover = Some(snd_nxt);
+            return CcAction::Retransmit;
+        }
+        CcAction::Nothing
+    }
+    fn on_rto(&mut self, _in_flight: usize) {
+        self.reduce();
+        self.cwnd = self.mss;
+        self.recover = None;
+    }
+    fn on_rtt_sample(&mut self, rtt: Duration) {
+        self.srtt = Some(match self.srtt {
+            Some(srtt) => srtt.mul_f64(7.0 / 8.0) + rtt.mul_f64(1.0 / 8.0),
+            None => rtt,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 1000;
+
+    #[test]
+    fn test_reduction_and_fast_convergence() {
+        let mut cc = Cubic::new(MSS);
+        cc.cwnd = 100 * MSS;
+        assert_eq!(cc.on_dup_ack(3, SeqNum::new(0), 100 * MSS), CcAction::Retransmit);
+        assert_eq!(cc.ssthresh(), 70 * MSS);
+        assert_eq!(cc.w_max, 100.0);
+        // Losing again before reaching w_max releases some bandwidth
+        cc.recover = None;
+        cc.cwnd = 80 * MSS;
+        cc.on_rto(80 * MSS);
+        assert_eq!(cc.cwnd(), MSS);
+        assert_eq!(cc.w_max, 80.0 * (1.0 + BETA_CUBIC) / 2.0);
+    }
+
+    #[test]
+    fn test_concave_growth_towards_w_max() {
+        let mut cc = Cubic::new(MSS);
+        cc.cwnd = 70 * MSS;
+        cc.ssthresh = 70 * MSS;
+        cc.w_max = 100.0;
+        let start = Instant::now();
+        cc.start_epoch(start);
+        assert!((cc.k - (30.0 / C).cbrt()).abs() < 1e-9);
+        assert!((cc.w_cubic(cc.k) - 100.0).abs() < 1e-9);
+        // Growth is fastest right after the reduction and never overshoots the 1.5 * cwnd cap
+        cc.avoid_congestion(70 * MSS, start + Duration::from_millis(100));
+        assert!(cc.cwnd() > 70 * MSS && cc.cwnd() <= 105 * MSS);
+    }
+}
@@ -7,6 +7,7 @@ pub struct Interface {
     pub neighbors: HashMap<Ipv4Addr, u16>,
     pub status: Mutex<InterfaceStatus>, //Only non-static field - represents current status of the interface
     pub udp_sock: UdpSocket,
+    pub mtu: usize,
 }
 
 impl Interface {
@@ -14,6 +15,7 @@ impl Interface {
         // v_ip: Ipv4Addr,
         neighbors: HashMap<Ipv4Addr, u16>,
         udp_port: u16,
+        mtu: usize,
     ) -> Interface {
         Interface {
             // v_ip,
@@ -21,6 +23,7 @@ impl Interface {
             status: Mutex::new(InterfaceStatus::Up), //Status always starts as Up
             udp_sock: UdpSocket::bind(format!("127.0.0.1:{}", udp_port))
                 .expect("Unable to bind to port"),
+            mtu,
         }
     }
     pub fn run(self, chan: BiChan<Packet, InterCmd>) {
@@ -54,6 +57,10 @@ impl Interface {
             let pack = match slf.recv() {
                 Ok(pack) => pack,
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(ref e) if e.kind() == ErrorKind::InvalidData => {
+                    eprintln!("Dropping received packet: {e}");
+                    continue;
+                }
                 Err(e) => panic!("Error while trying to recv: {e:?}"),
             };
             let status = slf.status.lock().unwrap();
@@ -78,11 +85,24 @@ impl Interface {
     }
     fn send(&self, pack: Packet, next_hop: Ipv4Addr) -> std::io::Result<()> {
         // Grab neighbor address to send to
-        let dst_neighbor = self.neighbors.get(&next_hop).unwrap();
+        let Some(dst_neighbor) = self.neighbors.get(&next_hop) else {
+            // Nobody there to hand it to - same as it getting lost on the link
+            eprintln!("No neighbor {next_hop} on this interface, dropping packet");
+            return Ok(());
+        };
         let mut message = vec![0u8; 20];
         let mut writer = &mut message[..];
         pack.header.write(&mut writer)?;
         message.extend(pack.data);
+        if message.len() > self.mtu {
+            // No fragmentation - a packet that doesn't fit the link is lost
+            eprintln!(
+                "Dropping {} byte packet, larger than the interface mtu of {}",
+                message.len(),
+                self.mtu
+            );
+            return Ok(());
+        }
 
         // Send
         let sock = &self.udp_sock;
@@ -94,7 +114,7 @@ impl Interface {
     }
     fn recv(&self) -> Result<Packet> {
         let mut received = false;
-        let mut buf: [u8; 1500] = [0; 1500];
+        let mut buf = vec![0u8; self.mtu];
         let socket = &self.udp_sock;
         while !received {
             let len = socket.recv(&mut buf)?; // Break if receive
@@ -105,6 +125,13 @@ impl Interface {
         match Ipv4Header::from_slice(&buf) {
             Ok((head, rest)) => {
                 let len = (head.total_len - 20) as usize;
+                if len > rest.len() {
+                    // Got cut off by our mtu
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Received packet larger than the interface mtu",
+                    ));
+                }
                 let pay: Vec<u8> = Vec::from_iter(rest[0..len].iter().cloned());
                 Ok(Packet {
                     header: head,
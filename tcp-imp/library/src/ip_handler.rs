@@ -47,26 +47,28 @@ impl IpHandler {
         let tpack = deserialize_tcp(pack.data.clone()).expect("Malformed TCP packet");
         let socket_table = socket_table.read().unwrap();
         match Self::proper_socket(&pack.header, &tpack, &socket_table) {
-            Some(sid) => {
-                let sock_entry = socket_table
-                    .get(&sid)
-                    .expect("Internal logic issue - check proper_socket");
-                match sock_entry {
-                    SocketEntry::Connection(ent) => {
-                        let sock = Arc::clone(&ent.sock);
-                        thread::spawn(move || {
-                            ConnectionSocket::handle_packet(sock, tpack, pack.header)
-                        });
-                    }
-                    SocketEntry::Listener(ent) => {
-                        //Blegh, ownership
-                        let port = ent.port;
-                        let sock_man = Arc::clone(&socket_manager);
-                        thread::spawn(move || sock_man.lock().unwrap().handle_incoming(pack, port));
-                    }
+            Some(sid) => match socket_table
+                .get(&sid)
+                .expect("Internal logic issue - check proper_socket")
+            {
+                SocketEntry::Connection(ent) => {
+                    let sock = Arc::clone(&ent.sock);
+                    thread::spawn(move || {
+                        ConnectionSocket::handle_packet(sock, tpack, pack.header)
+                    });
                 }
+                SocketEntry::Listener(ent) => {
+                    //Blegh, ownership
+                    let port = ent.port;
+                    let sock_man = Arc::clone(&socket_manager);
+                    thread::spawn(move || sock_man.lock().unwrap().handle_incoming(pack, port));
+                }
+            },
+            None => {
+                //Nobody gives a crap about it - let the sender know (socket table is let go first, the socket manager locks it too)
+                drop(socket_table);
+                socket_manager.lock().unwrap().send_reset(&pack.header, &tpack);
             }
-            None => {} //Drop packet cause nobody gives a crap about it
         }
     }
     ///Finds the proper socket for a TcpPacket given an associated IP header
@@ -75,33 +77,17 @@ impl IpHandler {
         tcp_pack: &TcpPacket,
         socket_table: &RwLockReadGuard<SocketTable>,
     ) -> Option<SocketId> {
-        //let socket_table = self.socket_table.read().unwrap();
         //Extract necessary data
-        let src_ip = Ipv4Addr::from(ip_head.source);
-        let dst_ip = Ipv4Addr::from(ip_head.destination);
-        let src_port = &tcp_pack.header.source_port;
-        let dst_port = &tcp_pack.header.destination_port;
-        //Loop through and find the proper socket ID
-        let mut listener_id = None;
-        for (sock_id, sock_entry) in &**socket_table {
-            match sock_entry {
-                SocketEntry::Connection(ent) => {
-                    if (ent.dst_addr.ip == src_ip)
-                        && (ent.src_addr.ip == dst_ip)
-                        && (ent.dst_addr.port == *src_port)
-                        && (ent.src_addr.port == *dst_port)
-                    {
-                        return Some(*sock_id);
-                    }
-                }
-                SocketEntry::Listener(ent) => {
-                    if (ent.port == *dst_port) && is_syn(&tcp_pack.header) {
-                        listener_id = Some(*sock_id)
-                    }
-                }
-            }
+        let local = TcpAddress::new(Ipv4Addr::from(ip_head.destination), tcp_pack.header.destination_port);
+        let remote = TcpAddress::new(Ipv4Addr::from(ip_head.source), tcp_pack.header.source_port);
+        if let Some(sid) = socket_table.connection(&local, &remote) {
+            return Some(sid);
+        }
+        //SYNs, and ACKs that might be carrying back a SYN cookie
+        if is_syn(&tcp_pack.header) || is_cookie_ack(&tcp_pack.header) {
+            return socket_table.listener(local.port);
         }
-        listener_id
+        None
     }
     fn handle_test_packet(pack: Packet) {
         let src = Self::string_ip(pack.header.source);
@@ -0,0 +1,171 @@
+use crate::prelude::*;
+use crate::seq_num::SeqNum;
+use crate::tcp_utils::TcpAddress;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use siphasher::sip::SipHasher24;
+use std::hash::Hasher;
+use std::sync::atomic::AtomicU32;
+
+/* Initial sequence number generation (RFC 6528):
+
+ISN = M + F(localip, localport, remoteip, remoteport, secretkey)
+
+M is a timer ticking once every 4 microseconds, so a new incarnation of a connection starts
+ahead of anything the old one could still have in flight. F is a keyed hash of the connection's
+4-tuple, which keeps outsiders from guessing our ISNs and gives every 4-tuple its own sequence
+number space. F is SipHash-2-4 under a 128 bit key picked at random when the node starts.
+
+With a seed, the key comes from the seed and M is replaced by a counter that moves forward by
+SEEDED_STEP for every ISN handed out, so the same sequence of connections gets the same ISNs on
+every run.
+
+*/
+
+/* SYN cookies (RFC 4987 3.6):
+
+Once a listener's backlog is full, we stop keeping state for new handshakes. Instead, the ISN of our
+SYN-ACK holds everything needed to set up the connection later, and nothing gets allocated until
+the final ACK comes back acknowledging cookie + 1:
+
+bits 31-27: t, a counter that ticks once every COOKIE_TICK (mod 32)
+bits 26-3: keyed hash of the 4-tuple, t and our partner's ISN
+bits 2-0: index of the MSS in COOKIE_MSS_TABLE
+
+A cookie is good for the tick it was made in and the one after. There's no room for options
+other than the MSS, so cookie connections go without SACK, window scaling and timestamps.
+
+*/
+
+const CLOCK_TICK_MICROS: u128 = 4;
+const SEEDED_STEP: u32 = 250_000; //About a second's worth of clock ticks
+const COOKIE_TICK: Duration = Duration::from_secs(64);
+const COOKIE_MSS_TABLE: [u16; 8] = [216, 536, 1024, 1220, 1300, 1380, 1440, 1460];
+const COOKIE_HASH_MASK: u32 = 0x00FF_FFFF;
+
+#[derive(Debug)]
+pub struct IsnGenerator {
+    key: u128,
+    origin: Instant,
+    issued: Option<AtomicU32>, //Some(ISNs handed out so far) if seeded
+}
+
+impl IsnGenerator {
+    pub fn new(seed: Option<u64>) -> IsnGenerator {
+        let (key, issued) = match seed {
+            Some(seed) => (StdRng::seed_from_u64(seed).gen(), Some(AtomicU32::new(0))),
+            None => (rand::thread_rng().gen(), None),
+        };
+        IsnGenerator {
+            key,
+            origin: Instant::now(),
+            issued,
+        }
+    }
+    /// Picks the initial sequence number for a connection between the input addresses
+    pub fn generate(&self, src_addr: &TcpAddress, dst_addr: &TcpAddress) -> SeqNum {
+        let m = match &self.issued {
+            Some(issued) => issued.fetch_add(1, Ordering::SeqCst).wrapping_mul(SEEDED_STEP),
+            None => (self.origin.elapsed().as_micros() / CLOCK_TICK_MICROS) as u32, //Wraps around every ~4.8 hours
+        };
+        let f = self.keyed_hasher(src_addr, dst_addr).finish() as u32;
+        SeqNum::new(m) + f
+    }
+    /// Picks the SYN cookie answering a SYN with the input ISN - the MSS gets rounded down to one the cookie can hold,
+    /// and there's no cookie for an MSS below all of them
+    pub fn cookie(&self, src_addr: &TcpAddress, dst_addr: &TcpAddress, peer_isn: SeqNum, mss: u16) -> Option<SeqNum> {
+        let t = self.cookie_tick();
+        let mss_index = COOKIE_MSS_TABLE.iter().rposition(|&table_mss| table_mss <= mss)?;
+        let hash = self.cookie_hash(src_addr, dst_addr, peer_isn, t);
+        Some(SeqNum::new((t << 27) | (hash << 3) | mss_index as u32))
+    }
+    /// Checks a cookie that came back in an ACK, returning the MSS it holds if it's one of ours and still fresh
+    pub fn check_cookie(&self, src_addr: &TcpAddress, dst_addr: &TcpAddress, peer_isn: SeqNum, cookie: SeqNum) -> Option<u16> {
+        let cookie = cookie.value();
+        let t = cookie >> 27;
+        let age = self.cookie_tick().wrapping_sub(t) & 0x1F;
+        if age > 1 || (cookie >> 3) & COOKIE_HASH_MASK != self.cookie_hash(src_addr, dst_addr, peer_isn, t) {
+            return None;
+        }
+        Some(COOKIE_MSS_TABLE[(cookie & 0x7) as usize])
+    }
+    fn cookie_tick(&self) -> u32 {
+        (self.origin.elapsed().as_secs() / COOKIE_TICK.as_secs()) as u32 & 0x1F
+    }
+    fn cookie_hash(&self, src_addr: &TcpAddress, dst_addr: &TcpAddress, peer_isn: SeqNum, t: u32) -> u32 {
+        let mut hasher = self.keyed_hasher(src_addr, dst_addr);
+        hasher.write_u32(peer_isn.value());
+        hasher.write_u32(t);
+        hasher.finish() as u32 & COOKIE_HASH_MASK
+    }
+    /// Hasher that has already taken in our key and the connection's 4-tuple
+    fn keyed_hasher(&self, src_addr: &TcpAddress, dst_addr: &TcpAddress) -> SipHasher24 {
+        let mut hasher = SipHasher24::new_with_keys((self.key >> 64) as u64, self.key as u64);
+        hasher.write(&src_addr.ip.octets());
+        hasher.write_u16(src_addr.port);
+        hasher.write(&dst_addr.ip.octets());
+        hasher.write_u16(dst_addr.port);
+        hasher
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(port: u16) -> (TcpAddress, TcpAddress) {
+        (
+            TcpAddress::new(Ipv4Addr::new(10, 0, 0, 1), port),
+            TcpAddress::new(Ipv4Addr::new(10, 1, 0, 2), 9999),
+        )
+    }
+
+    #[test]
+    fn test_seeded_generators_repeat() {
+        let (src, dst) = addrs(20000);
+        let first = IsnGenerator::new(Some(7));
+        let second = IsnGenerator::new(Some(7));
+        assert_eq!(first.generate(&src, &dst), second.generate(&src, &dst));
+        assert_eq!(first.generate(&src, &dst), second.generate(&src, &dst));
+        assert_ne!(
+            IsnGenerator::new(Some(8)).generate(&src, &dst),
+            IsnGenerator::new(Some(7)).generate(&src, &dst)
+        );
+    }
+
+    #[test]
+    fn test_seeded_isn_pinned() {
+        let (src, dst) = addrs(20000);
+        let isn_gen = IsnGenerator::new(Some(7));
+        assert_eq!(isn_gen.generate(&src, &dst), SeqNum::new(1972573685)); //Changes only if the hash or the seeded key does
+    }
+
+    #[test]
+    fn test_reused_tuple_moves_forward() {
+        let (src, dst) = addrs(20000);
+        let (other_src, _) = addrs(20001);
+        let isn_gen = IsnGenerator::new(Some(7));
+        let first = isn_gen.generate(&src, &dst);
+        let other = isn_gen.generate(&other_src, &dst);
+        let second = isn_gen.generate(&src, &dst);
+        assert!(second > first);
+        assert_eq!(second - first, 2 * SEEDED_STEP);
+        assert_ne!(other - first, SEEDED_STEP); //Other tuples live somewhere else in the space
+    }
+
+    #[test]
+    fn test_cookie_round_trip() {
+        let (src, dst) = addrs(20000);
+        let (other_src, _) = addrs(20001);
+        let isn_gen = IsnGenerator::new(Some(7));
+        let peer_isn = SeqNum::new(1234);
+        let cookie = isn_gen.cookie(&src, &dst, peer_isn, 1420).unwrap();
+        assert_eq!(isn_gen.check_cookie(&src, &dst, peer_isn, cookie), Some(1380));
+        assert_eq!(isn_gen.check_cookie(&other_src, &dst, peer_isn, cookie), None);
+        assert_eq!(isn_gen.check_cookie(&src, &dst, peer_isn + 1, cookie), None);
+        assert_eq!(isn_gen.check_cookie(&src, &dst, peer_isn, cookie + 8), None);
+        let small = isn_gen.cookie(&src, &dst, peer_isn, 216).unwrap();
+        assert_eq!(isn_gen.check_cookie(&src, &dst, peer_isn, small), Some(216));
+        assert_eq!(isn_gen.cookie(&src, &dst, peer_isn, 215), None); //Can't promise anything that small
+    }
+}
@@ -0,0 +1,173 @@
+use crate::congestion::*;
+use crate::seq_num::SeqNum;
+use std::cmp;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/* LEDBAT (RFC 6817):
+
+A "scavenger" controller meant for background transfers - it fills up whatever capacity is left
+over, and backs off as soon as its own traffic starts to build a queue, yielding to everyone else.
+
+We don't have one-way delay measurements, so delays below are taken from RTT samples instead:
+- base_delay: minimum RTT over the last BASE_HISTORY intervals of BASE_INTERVAL each
+- current_delay: minimum of the last CURRENT_FILTER RTT samples
+queuing_delay = current_delay - base_delay
+
+ON ACK:
+off_target = (TARGET - queuing_delay) / TARGET
+cwnd += GAIN * off_target * bytes_newly_acked * MSS / cwnd
+cwnd = min(cwnd, flight_size + ALLOWED_INCREASE * MSS)
+cwnd = max(cwnd, MIN_CWND * MSS)
+
+ON LOSS (at most once per RTT):
+cwnd = max(cwnd / 2, MIN_CWND * MSS)
+
+ON RTO:
+cwnd = MSS
+
+*/
+
+// RFC 6817 caps TARGET at 100ms - our emulated links only see a few milliseconds of delay, so we aim lower
+const TARGET: Duration = Duration::from_millis(25);
+const GAIN: f64 = 1.0;
+const ALLOWED_INCREASE: usize = 1; // In segments
+const MIN_CWND: usize = 2; // In segments
+const INIT_CWND: usize = 2; // In segments
+const BASE_HISTORY: usize = 10;
+const BASE_INTERVAL: Duration = Duration::from_secs(60);
+const CURRENT_FILTER: usize = 4;
+
+#[derive(Debug)]
+pub struct Ledbat {
+    mss: usize,
+    cwnd: f64,                                  // Bytes, kept fractional so small increases add up
+    base_delays: VecDeque<(Instant, Duration)>, // (interval start, minimum RTT during that interval)
+    current_delays: VecDeque<Duration>,
+    recover: Option<SeqNum>, // Some(snd.nxt at time of loss) while in fast recovery
+}
+
+impl Ledbat {
+    pub fn new(mss: usize) -> Ledbat {
+        Ledbat {
+            mss,
+            cwnd: (INIT_CWND * mss) as f64,
+            base_delays: VecDeque::new(),
+            current_delays: VecDeque::new(),
+            recover: None,
+        }
+    }
+    fn min_cwnd(&self) -> f64 {
+        (MIN_CWND * self.mss) as f64
+    }
+    /// Estimated time our data spends sitting in queues, None until we have an RTT sample
+    fn queuing_delay(&self) -> Option<Duration> {
+        let base_delay = self.base_delays.iter().map(|(_, delay)| *delay).min()?;
+        let current_delay = self.current_delays.iter().min()?;
+        Some(current_delay.saturating_sub(base_delay))
+    }
+    fn update_base_delay(&mut self, rtt: Duration, now: Instant) {
+        match self.base_delays.back_mut() {
+            Some((start, delay)) if now.duration_since(*start) < BASE_INTERVAL => {
+                *delay = cmp::min(*delay, rtt);
+            }
+            _ => {
+                self.base_delays.push_back((now, rtt));
+                if self.base_delays.len() > BASE_HISTORY {
+                    self.base_delays.pop_front();
+                }
+            }
+        }
+    }
+}
+
+impl CongestionControl for Ledbat {
+    fn name(&self) -> &'static str {
+        "ledbat"
+    }
+    fn cwnd(&self) -> usize {
+        self.cwnd as usize
+    }
+    fn ssthresh(&self) -> usize {
+        usize::MAX // No slow start
+    }
+    fn in_recovery(&self) -> bool {
+        self.recover.is_some()
+    }
+    fn on_ack(&mut self, ack: AckInfo) -> CcAction {
+        if let Some(recover) = self.recover {
+            if ack.ack_num < recover {
+                // Partial ACK - the next hole needs retransmitting right away
+                return CcAction::Retransmit;
+            }
+            self.recover = None;
+        }
+        if let Some(queuing_delay) = self.queuing_delay() {
+            let off_target = (TARGET.as_secs_f64() - queuing_delay.as_secs_f64()) / TARGET.as_secs_f64();
+            self.cwnd += GAIN * off_target * ack.acked as f64 * self.mss as f64 / self.cwnd;
+            // Don't let cwnd grow past what we're actually using
+            let max_allowed = (ack.in_flight + ack.acked + ALLOWED_INCREASE * self.mss) as f64;
+            self.cwnd = self.cwnd.min(max_allowed).max(self.min_cwnd());
+        }
+        CcAction::Nothing
+    }
+    fn on_dup_ack(&mut self, dup_count: u32, snd_nxt: SeqNum, _in_flight: usize) -> CcAction {
+        if self.in_recovery() || dup_count != DUP_ACK_THRESHOLD {
+            return CcAction::Nothing;
+        }
+        self.cwnd = (self.cwnd / 2.0).max(self.min_cwnd());
+        self.recover = Some(snd_nxt);
+        CcAction::Retransmit
+    }
+    fn on_rto(&mut self, _in_flight: usize) {
+        self.cwnd = self.mss as f64;
+        self.recover = None;
+    }
+    fn on_rtt_sample(&mut self, rtt: Duration) {
+        self.update_base_delay(rtt, Instant::now());
+        self.current_delays.push_back(rtt);
+        if self.current_delays.len() > CURRENT_FILTER {
+            self.current_delays.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 1000;
+
+    fn ack(cc: &mut Ledbat, rtt: Duration) {
+        cc.on_rtt_sample(rtt);
+        cc.on_ack(AckInfo {
+            acked: MSS,
+            ack_num: SeqNum::new(0),
+            snd_nxt: SeqNum::new(0),
+            in_flight: 100 * MSS,
+            rate: None,
+        });
+    }
+
+    #[test]
+    fn test_yields_once_delay_passes_target() {
+        let mut cc = Ledbat::new(MSS);
+        let base = Duration::from_millis(5);
+        // Empty queue - grows by about a segment per window of ACKs
+        for _ in 0..40 {
+            ack(&mut cc, base);
+        }
+        let grown = cc.cwnd();
+        assert!(grown > 8 * MSS);
+        // Queue at twice the target - shrinks at the same pace, once the delay filter catches on
+        for _ in 0..2 * CURRENT_FILTER {
+            ack(&mut cc, base + 2 * TARGET);
+        }
+        assert!(cc.cwnd() < grown);
+        // Never below MIN_CWND though
+        for _ in 0..1000 {
+            ack(&mut cc, base + 2 * TARGET);
+        }
+        assert_eq!(cc.cwnd(), MIN_CWND * MSS);
+    }
+}
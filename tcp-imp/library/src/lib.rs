@@ -13,19 +13,33 @@ RegisterRecvHandler(protocolNum uint8, callbackFunc HandlerFunc)
 
 */
 
+pub mod async_net;
 pub mod backends;
+mod bbr;
 pub mod config;
+pub mod congestion;
 mod conn_socket;
+mod cubic;
 mod interface;
+mod isn;
 pub mod ip_daemons;
 pub mod ip_handler; //b/c right now REPL makes IpHandler, although ideally this is a config task
+mod ledbat;
+pub mod net;
+pub mod poll;
 mod prelude;
+mod reassembly;
 pub mod retransmission;
 mod rip_trait;
 pub mod rip_utils;
 mod send_recv_utils;
+pub mod seq_num;
 pub mod socket_manager;
 pub mod sockman_utils;
+pub mod tcp_config;
 mod tcp_utils;
+#[cfg(test)]
+mod test_utils;
 pub mod utils; //pub for testing purposes - should change back on deployment
+mod vegas;
 pub mod vnode_traits;
@@ -0,0 +1,273 @@
+use crate::backends::HostBackend;
+use crate::prelude::*;
+use crate::sockman_utils::*;
+use std::io::{Read, Write};
+use std::net::SocketAddrV4;
+
+/* Socket handles:
+
+VTcpStream and VTcpListener wrap a socket ID along with the backend it lives on, the same way
+std::net's TcpStream and TcpListener wrap a file descriptor - so anything written against Read and
+Write (io::copy, BufReader, serializers...) runs over our TCP as is. Reads hand back whatever the
+receive buffer holds, up to the size asked for, and a read of 0 bytes means our partner closed its
+end. Writes block until everything has made it into the send buffer, which is all a flush needs
+to wait for. Dropping a handle closes its socket - nonblocking ones leave that to a thread of its own,
+so the connection winds down in the background.
+
+*/
+
+/// A connection, read from and written to like std::net::TcpStream
+pub struct VTcpStream {
+    backend: HostBackend,
+    sid: SocketId,
+    closed: bool, //close() was already called, so there's nothing left for drop to do
+    nonblocking: AtomicBool,
+}
+
+impl VTcpStream {
+    /// Opens a connection, blocking until the handshake is done - see HostBackend::connect()
+    pub fn connect(backend: &HostBackend, ip_addr: Ipv4Addr, port: u16) -> Result<VTcpStream> {
+        let sid = backend.connect(ip_addr, port)?;
+        Ok(VTcpStream::from_sid(backend, sid))
+    }
+    pub(crate) fn from_sid(backend: &HostBackend, sid: SocketId) -> VTcpStream {
+        VTcpStream {
+            backend: backend.clone(),
+            sid,
+            closed: false,
+            nonblocking: AtomicBool::new(false),
+        }
+    }
+    /// The socket ID behind the stream, for anything HostBackend offers that the stream doesn't
+    pub fn sid(&self) -> SocketId {
+        self.sid
+    }
+    pub fn local_addr(&self) -> Result<SocketAddrV4> {
+        let ent = self.entry()?;
+        Ok(SocketAddrV4::new(ent.src_addr.ip, ent.src_addr.port))
+    }
+    pub fn peer_addr(&self) -> Result<SocketAddrV4> {
+        let ent = self.entry()?;
+        Ok(SocketAddrV4::new(ent.dst_addr.ip, ent.dst_addr.port))
+    }
+    fn entry(&self) -> Result<ConnectionEntry> {
+        match self.backend.socket_table().get(&self.sid) {
+            Some(SocketEntry::Connection(ent)) => Ok(ent.clone()),
+            _ => Err(Error::new(ErrorKind::NotConnected, "Connection is already closed")),
+        }
+    }
+    pub fn shutdown(&self, how: How) -> Result<()> {
+        self.backend.shutdown(self.sid, how)
+    }
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        self.backend.set_nonblocking(self.sid, nonblocking)?;
+        self.nonblocking.store(nonblocking, Ordering::SeqCst);
+        Ok(())
+    }
+    pub fn set_nodelay(&self, nodelay: bool) -> Result<()> {
+        self.backend.set_nodelay(self.sid, nodelay)
+    }
+    /// Closes the connection like dropping the stream does, but tells how it went
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.backend.close(self.sid)
+    }
+    pub(crate) fn backend(&self) -> &HostBackend {
+        &self.backend
+    }
+    /// For handles that closed the connection some other way
+    pub(crate) fn mark_closed(&mut self) {
+        self.closed = true;
+    }
+}
+
+impl Read for &VTcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let bytes = cmp::min(buf.len(), u16::MAX as usize) as u16;
+        match self.backend.tcp_recieve(self.sid, bytes) {
+            Ok(data) => {
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            Err(e) if e.kind() == ErrorKind::Unsupported => Ok(0), //Nothing left to receive
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Write for &VTcpStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.backend.tcp_send(self.sid, buf.to_vec()) {
+            Ok(bytes_sent) => Ok(bytes_sent as usize),
+            Err(e) if e.kind() == ErrorKind::Unsupported => {
+                Err(Error::new(ErrorKind::BrokenPipe, "Connection was shut down for writing"))
+            }
+            Err(e) => Err(e),
+        }
+    }
+    fn flush(&mut self) -> Result<()> {
+        Ok(()) //Writes only return once the send buffer has taken everything
+    }
+}
+
+impl Read for VTcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for VTcpStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (&*self).write(buf)
+    }
+    fn flush(&mut self) -> Result<()> {
+        (&*self).flush()
+    }
+}
+
+impl Drop for VTcpStream {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        match self.nonblocking.load(Ordering::SeqCst) {
+            true => {
+                let (backend, sid) = (self.backend.clone(), self.sid);
+                thread::spawn(move || backend.close(sid));
+            }
+            false => {
+                let _ = self.backend.close(self.sid); //Nobody left to tell if it fails
+            }
+        }
+    }
+}
+
+/// A listening socket, handing out incoming connections like std::net::TcpListener
+pub struct VTcpListener {
+    backend: HostBackend,
+    sid: SocketId,
+    port: u16,
+    released: bool, //into_sid() handed the listener off, so drop leaves it open
+}
+
+impl VTcpListener {
+    /// Starts listening on a port, with the default backlog
+    /// Fails with AddrInUse if something is already listening there
+    pub fn bind(backend: &HostBackend, port: u16) -> Result<VTcpListener> {
+        let sid = backend.listen(port, DEFAULT_BACKLOG)?;
+        Ok(VTcpListener {
+            backend: backend.clone(),
+            sid,
+            port,
+            released: false,
+        })
+    }
+    pub fn sid(&self) -> SocketId {
+        self.sid
+    }
+    /// Listeners take connections to any of our addresses
+    pub fn local_addr(&self) -> Result<SocketAddrV4> {
+        Ok(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, self.port))
+    }
+    /// Blocks until a connection comes in, unless the listener is nonblocking
+    pub fn accept(&self) -> Result<(VTcpStream, SocketAddrV4)> {
+        let sid = self.backend.accept1(self.port)?;
+        let stream = VTcpStream::from_sid(&self.backend, sid);
+        let peer_addr = stream.peer_addr()?;
+        Ok((stream, peer_addr))
+    }
+    /// Accepts connections forever
+    pub fn incoming(&self) -> Incoming<'_> {
+        Incoming { listener: self }
+    }
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        self.backend.set_nonblocking(self.sid, nonblocking)
+    }
+    /// Gives up the handle without closing the listener, leaving it to whoever holds the socket ID
+    pub fn into_sid(mut self) -> SocketId {
+        self.released = true;
+        self.sid
+    }
+    pub(crate) fn backend(&self) -> &HostBackend {
+        &self.backend
+    }
+}
+
+impl Drop for VTcpListener {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        let _ = self.backend.close(self.sid); //Connections it already handed out carry on
+    }
+}
+
+/// Iterator over the connections coming in on a listener, see VTcpListener::incoming()
+pub struct Incoming<'a> {
+    listener: &'a VTcpListener,
+}
+
+impl Iterator for Incoming<'_> {
+    type Item = Result<VTcpStream>;
+    fn next(&mut self) -> Option<Result<VTcpStream>> {
+        Some(self.listener.accept().map(|(stream, _)| stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::io::{self, Cursor};
+
+    #[test]
+    fn test_bind_addr_in_use() {
+        let (_host_a, host_b) = host_pair("");
+        let port = listen_port();
+        let binds: Vec<_> = (0..8)
+            .map(|_| {
+                let host_b = host_b.clone();
+                thread::spawn(move || VTcpListener::bind(&host_b, port))
+            })
+            .collect();
+        let mut binds: Vec<Result<VTcpListener>> = binds.into_iter().map(|bind| bind.join().unwrap()).collect();
+        binds.retain(|bind| !matches!(bind, Err(e) if e.kind() == ErrorKind::AddrInUse));
+        assert_eq!(binds.len(), 1); //Only one of them got the port
+        let listener = binds.pop().unwrap().unwrap();
+        drop(listener);
+        let listener = VTcpListener::bind(&host_b, port).unwrap(); //Free again once closed
+        let sid = listener.into_sid();
+        assert_eq!(VTcpListener::bind(&host_b, port).err().map(|e| e.kind()), Some(ErrorKind::AddrInUse));
+        host_b.close(sid).unwrap();
+    }
+
+    #[test]
+    fn test_io_copy() {
+        let (host_a, host_b) = host_pair("");
+        let port = listen_port();
+        let mut rng = StdRng::seed_from_u64(22);
+        let data: Vec<u8> = (0..200_000).map(|_| rng.gen()).collect();
+        let listener = VTcpListener::bind(&host_b, port).unwrap();
+        let to_send = data.clone();
+        let sending = thread::spawn(move || {
+            let mut stream = VTcpStream::connect(&host_a, HOST_B, port).unwrap();
+            io::copy(&mut Cursor::new(to_send), &mut stream).unwrap();
+            stream.close().unwrap();
+        });
+        let (mut stream, peer_addr) = listener.accept().unwrap();
+        assert_eq!(*peer_addr.ip(), HOST_A);
+        let mut received = Vec::new();
+        assert_eq!(io::copy(&mut stream, &mut received).unwrap(), data.len() as u64);
+        assert_eq!(received, data);
+        assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0); //Still at the end
+        sending.join().unwrap();
+    }
+}
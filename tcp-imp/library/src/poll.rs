@@ -0,0 +1,173 @@
+use crate::prelude::*;
+use std::sync::atomic::AtomicU64;
+use std::task::{Wake, Waker};
+
+/* Readiness polling:
+
+poll() lets a single thread wait on many sockets at once. Every SyncBuf, and every listener, keeps
+a set of watchers - wakers keyed by whoever registered them - and wakes all of them whenever it
+alerts its own waiters. poll() registers a waker backed by a condvar of its own with every socket
+it's asked about, then checks their readiness, and sleeps until one of them wakes it up or the
+timeout runs out. Watching before checking means nothing that happens in between gets lost.
+
+*/
+
+static NEXT_WATCH_KEY: AtomicU64 = AtomicU64::new(0);
+
+/// Picks a key no other watcher uses, so a watcher can be swapped out or removed later
+pub fn watch_key() -> u64 {
+    NEXT_WATCH_KEY.fetch_add(1, Ordering::Relaxed)
+}
+
+/// What a poll() caller wants to hear about - hang ups and errors are always reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interest {
+    Read, //For listeners, a connection to accept
+    Write,
+    Both,
+}
+
+/// What a socket is ready for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Readiness {
+    pub readable: bool,   //A receive won't block
+    pub writable: bool,   //A send can buffer at least some data without blocking
+    pub acceptable: bool, //A listener has a connection waiting for accept1()
+    pub hung_up: bool,    //Our partner won't send anything more, or the socket is gone
+    pub error: bool,      //The connection was aborted, so sends and receives fail
+}
+
+impl Readiness {
+    /// Leaves only what the input interest asks about
+    pub fn filter(self, interest: Interest) -> Readiness {
+        let read = matches!(interest, Interest::Read | Interest::Both);
+        let write = matches!(interest, Interest::Write | Interest::Both);
+        Readiness {
+            readable: self.readable && read,
+            writable: self.writable && write,
+            acceptable: self.acceptable && read,
+            ..self
+        }
+    }
+    pub fn any(&self) -> bool {
+        self.readable || self.writable || self.acceptable || self.hung_up || self.error
+    }
+}
+
+/// Wakes up a thread blocked in poll()
+#[derive(Debug, Default)]
+pub struct PollWaiter {
+    woken: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl PollWaiter {
+    pub fn new() -> PollWaiter {
+        PollWaiter::default()
+    }
+    /// Forgets earlier wakeups - call before checking readiness
+    pub fn reset(&self) {
+        *self.woken.lock().unwrap() = false;
+    }
+    /// Blocks until woken up or until the deadline passes, if there is one
+    pub fn wait(&self, deadline: Option<Instant>) {
+        let mut woken = self.woken.lock().unwrap();
+        while !*woken {
+            woken = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return;
+                    }
+                    self.cond.wait_timeout(woken, deadline - now).unwrap().0
+                }
+                None => self.cond.wait(woken).unwrap(),
+            };
+        }
+    }
+}
+
+impl Wake for PollWaiter {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        *self.woken.lock().unwrap() = true;
+        self.cond.notify_all();
+    }
+}
+
+/// Wakers registered with a buffer or listener, all woken whenever it might have become ready
+#[derive(Debug, Default)]
+pub struct Watchers {
+    wakers: Mutex<HashMap<u64, Waker>>,
+}
+
+impl Watchers {
+    pub fn new() -> Watchers {
+        Watchers::default()
+    }
+    /// Registers the waker under the input key, replacing whatever was registered under it before
+    pub fn watch(&self, key: u64, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        match wakers.get(&key) {
+            Some(old) if old.will_wake(waker) => {}
+            _ => {
+                wakers.insert(key, waker.clone());
+            }
+        }
+    }
+    pub fn unwatch(&self, key: u64) {
+        self.wakers.lock().unwrap().remove(&key);
+    }
+    pub fn wake_all(&self) {
+        for waker in self.wakers.lock().unwrap().values() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_readiness_filter() {
+        let all = Readiness {
+            readable: true,
+            writable: true,
+            acceptable: true,
+            hung_up: true,
+            error: true,
+        };
+        assert_eq!(all.filter(Interest::Both), all);
+        assert_eq!(all.filter(Interest::Read), Readiness { writable: false, ..all });
+        assert_eq!(all.filter(Interest::Write), Readiness { readable: false, acceptable: false, ..all });
+        //Hang ups and errors get through whatever was asked for
+        let hung_up = Readiness { hung_up: true, ..Readiness::default() };
+        assert!(hung_up.filter(Interest::Write).any());
+        assert!(!Readiness { readable: true, ..Readiness::default() }.filter(Interest::Write).any());
+    }
+
+    #[test]
+    fn test_watchers_wake_waiter() {
+        let (watchers, waiter) = (Arc::new(Watchers::new()), Arc::new(PollWaiter::new()));
+        let key = watch_key();
+        watchers.watch(key, &Waker::from(Arc::clone(&waiter)));
+        let waking = Arc::clone(&watchers);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            waking.wake_all();
+        });
+        let start = Instant::now();
+        waiter.wait(Some(start + Duration::from_secs(5)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        //Once unwatched, waking does nothing - the wait runs until the deadline
+        waiter.reset();
+        watchers.unwatch(key);
+        watchers.wake_all();
+        let start = Instant::now();
+        waiter.wait(Some(start + Duration::from_millis(100)));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -1,5 +1,4 @@
-pub use circular_buffer::CircularBuffer;
-pub use etherparse::{IpNumber, Ipv4Header, TcpHeader};
+pub use etherparse::{IpNumber, Ipv4Header, TcpHeader, TcpOptionElement};
 pub use ipnet::Ipv4Net;
 pub use lnxparser::{IPConfig, InterfaceConfig, NeighborConfig, RoutingType, StaticRoute};
 pub use rand::Rng;
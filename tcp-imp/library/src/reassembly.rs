@@ -0,0 +1,223 @@
+use crate::seq_num::SeqNum;
+use std::cmp;
+use std::collections::BTreeMap;
+
+/* Receive reassembly:
+
+Segments show up out of order, duplicated, and - once retransmissions get repacketized - overlapping
+each other and whatever we already have. Data is held as disjoint ranges of bytes, keyed by the
+distance from a base sequence number to their first byte:
+
+insert(seq, data, lo, hi):
+    trim data to [lo, hi) - lo is the next byte expected, hi the right edge of our window
+    skip over whatever part of it an existing range already covers
+    store each uncovered gap as a range of its own
+
+pop(lo): takes the range starting at lo, if there is one
+
+Only bytes we don't already hold get stored, so the total size is exactly the number of distinct
+bytes held, and nothing outside the window ever gets stored. Sequence numbers themselves have no
+total order, so they can't key the BTreeMap. Distances from the base can: the base never passes lo,
+and gets moved up to it whenever nothing is held or it falls REBASE_DISTANCE behind. Everything held
+sits in the window past lo, which is far less than 2^31 wide, so distances never wrap around.
+
+*/
+
+const REBASE_DISTANCE: u32 = 1 << 30;
+
+/// Out of order data, held as disjoint byte ranges
+#[derive(Debug, Default)]
+pub struct Reassembly {
+    base: SeqNum,                  //Ranges are keyed on how far past this their first byte is
+    ranges: BTreeMap<u32, Vec<u8>>, //Never holds anything before the base
+    size: usize,                   //Bytes held across all ranges
+}
+
+impl Reassembly {
+    pub fn new() -> Reassembly {
+        Reassembly::default()
+    }
+    /// Stores whatever part of a segment falls within [lo, hi) and isn't already held
+    pub fn insert(&mut self, seq_num: SeqNum, data: &[u8], lo: SeqNum, hi: SeqNum) {
+        let end = seq_num + data.len() as u32;
+        if end <= lo || seq_num >= hi {
+            return; //Nothing inside the window
+        }
+        if self.ranges.is_empty() || lo - self.base >= REBASE_DISTANCE {
+            self.rebase(lo);
+        }
+        let data_start = self.base - seq_num; //Only used once pos is past seq_num, so any wrapping cancels out
+        let (mut pos, end) = (seq_num.max(lo) - self.base, end.min(hi) - self.base);
+        if let Some((&start, held)) = self.ranges.range(..=pos).next_back() {
+            pos = cmp::max(pos, start + held.len() as u32);
+        }
+        while pos < end {
+            let next = self.ranges.range(pos..end).next().map(|(&start, held)| (start, start + held.len() as u32));
+            let gap_end = next.map_or(end, |(start, _)| start);
+            if pos < gap_end {
+                let gap = data[data_start.wrapping_add(pos) as usize..data_start.wrapping_add(gap_end) as usize].to_vec();
+                self.size += gap.len();
+                self.ranges.insert(pos, gap);
+            }
+            match next {
+                Some((_, held_end)) => pos = held_end,
+                None => break,
+            }
+        }
+    }
+    /// Takes the range starting at the input sequence number, if we're holding one
+    pub fn pop(&mut self, seq_num: SeqNum) -> Option<Vec<u8>> {
+        let data = self.ranges.remove(&(seq_num - self.base))?;
+        self.size -= data.len();
+        Some(data)
+    }
+    /// Returns the (start, end) sequence number range of every stored range, in ascending order
+    pub fn ranges(&self) -> Vec<(SeqNum, SeqNum)> {
+        self.ranges
+            .iter()
+            .map(|(&start, data)| (self.base + start, self.base + start + data.len() as u32))
+            .collect()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+    /// Moves the base up to the input sequence number, which mustn't be past anything held
+    fn rebase(&mut self, base: SeqNum) {
+        let shift = base - self.base;
+        self.ranges = std::mem::take(&mut self.ranges)
+            .into_iter()
+            .map(|(start, data)| (start - shift, data))
+            .collect();
+        self.base = base;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::send_recv_utils::RecvBuf;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+
+    /// Cuts a stream into random segments, with duplicates and overlapping retransmissions, in random order
+    fn segments(rng: &mut StdRng, stream_len: usize) -> Vec<(usize, usize)> {
+        let mut segs = Vec::new();
+        let mut start = 0;
+        while start < stream_len {
+            let end = std::cmp::min(stream_len, start + rng.gen_range(1..200));
+            segs.push((start, end));
+            start = end;
+        }
+        for _ in 0..stream_len / 50 {
+            let start = rng.gen_range(0..stream_len);
+            let end = std::cmp::min(stream_len, start + rng.gen_range(1..400));
+            segs.push((start, end));
+        }
+        segs.shuffle(rng);
+        segs
+    }
+
+    #[test]
+    fn test_trims_overlaps() {
+        let base = SeqNum::new(1000);
+        let data: Vec<u8> = (0..100).collect();
+        let mut reassembly = Reassembly::new();
+        reassembly.insert(base + 20, &data[20..40], base, base + 100);
+        reassembly.insert(base + 60, &data[60..80], base, base + 100);
+        reassembly.insert(base + 10, &data[10..90], base, base + 100);
+        assert_eq!(reassembly.size, 80);
+        assert_eq!(
+            reassembly.ranges(),
+            vec![
+                (base + 10, base + 20),
+                (base + 20, base + 40),
+                (base + 40, base + 60),
+                (base + 60, base + 80),
+                (base + 80, base + 90)
+            ]
+        );
+        reassembly.insert(base + 30, &data[30..50], base, base + 100);
+        assert_eq!(reassembly.size, 80);
+    }
+
+    #[test]
+    fn test_clips_to_window() {
+        let base = SeqNum::new(u32::MAX - 5);
+        let data = [7u8; 30];
+        let mut reassembly = Reassembly::new();
+        reassembly.insert(base - 10, &data, base, base + 15);
+        assert_eq!(reassembly.ranges(), vec![(base, base + 15)]);
+        assert_eq!(reassembly.pop(base), Some(vec![7u8; 15]));
+        assert!(reassembly.is_empty());
+    }
+
+    #[test]
+    fn test_rebases_far_behind() {
+        let base = SeqNum::new(u32::MAX - 5);
+        let far = base + (1 << 31) - 100;
+        let mut reassembly = Reassembly::new();
+        reassembly.insert(far, &[1; 10], base, far + 10);
+        //The next byte expected has moved most of the way there, so the base gets moved up before anything is keyed off it
+        let lo = far - 10;
+        reassembly.insert(lo, &[2; 15], lo, far + 10);
+        assert_eq!(reassembly.base, lo);
+        assert_eq!(reassembly.ranges(), vec![(lo, far), (far, far + 10)]);
+        assert_eq!(reassembly.pop(lo), Some(vec![2; 10]));
+        assert_eq!(reassembly.pop(far), Some(vec![1; 10]));
+        assert!(reassembly.is_empty());
+    }
+
+    #[test]
+    fn test_random_reassembly() {
+        let mut rng = StdRng::seed_from_u64(25);
+        for _ in 0..50 {
+            let stream: Vec<u8> = (0..rng.gen_range(1..5000)).map(|_| rng.gen()).collect();
+            let isn = SeqNum::new(rng.gen_range(u32::MAX - 3000..=u32::MAX)); //Make sure wraparound comes up
+            let (mut expected, mut out) = (isn, Vec::new());
+            let mut reassembly = Reassembly::new();
+            let hi = isn + stream.len() as u32;
+            for (start, end) in segments(&mut rng, stream.len()) {
+                reassembly.insert(isn + start as u32, &stream[start..end], expected, hi);
+                while let Some(data) = reassembly.pop(expected) {
+                    expected += data.len() as u32;
+                    out.extend(data);
+                }
+                let ranges = reassembly.ranges();
+                assert_eq!(reassembly.size, ranges.iter().map(|(s, e)| (*e - *s) as usize).sum());
+                assert!(ranges.windows(2).all(|pair| pair[0].1 <= pair[1].0));
+                assert!(ranges.iter().all(|(s, e)| *s > expected && *e <= hi));
+            }
+            assert_eq!(out, stream);
+            assert!(reassembly.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_random_recv_buf() {
+        let mut rng = StdRng::seed_from_u64(2018);
+        for _ in 0..50 {
+            let stream: Vec<u8> = (0..rng.gen_range(1..20000)).map(|_| rng.gen()).collect();
+            let capacity = rng.gen_range(100..3000);
+            let isn = SeqNum::new(rng.gen_range(u32::MAX - 3000..=u32::MAX));
+            let mut recv_buf = RecvBuf::new(capacity);
+            recv_buf.set_init_seq(isn - 1);
+            let (mut ack, mut out) = (isn, Vec::new());
+            let segs = segments(&mut rng, stream.len());
+            //Keep resending until everything gets through the window, reading a bit in between
+            while out.len() < stream.len() {
+                for &(start, end) in &segs {
+                    let right_edge = ack + recv_buf.window();
+                    ack = recv_buf.add(isn + start as u32, stream[start..end].to_vec());
+                    assert!(ack - isn >= out.len() as u32 && ack - isn <= stream.len() as u32);
+                    assert!(recv_buf.sack_blocks(usize::MAX).iter().all(|(_, e)| *e <= right_edge));
+                    if rng.gen_bool(0.3) {
+                        out.extend(recv_buf.read(rng.gen_range(1..500)));
+                    }
+                }
+                out.extend(recv_buf.read(u16::MAX));
+            }
+            assert_eq!(out, stream);
+        }
+    }
+}
@@ -1,5 +1,8 @@
-use crate::tcp_utils::FIN;
+use crate::congestion::DUP_ACK_THRESHOLD;
+use crate::seq_num::SeqNum;
+use crate::tcp_utils::{FIN, SYN};
 use std::collections::VecDeque;
+use std::cmp;
 use std::time::{Duration, Instant};
 
 /* Algorithm for calculatating RTO and successive:
@@ -31,35 +34,76 @@ CONSTANTS:
 
 */
 
+/* Delivery rate estimation (draft-cheng-iccrg-delivery-rate-estimation):
+
+The queue keeps a running count of delivered (acknowledged) bytes. Every segment takes a snapshot
+of that count when it is (re)sent. When a segment gets acknowledged:
+
+send_elapsed = segment's time of send - first_sent_time at time of send
+ack_elapsed = now - delivered_time at time of send
+delivery_rate = (delivered - segment's delivered) / max(send_elapsed, ack_elapsed)
+
+A sample is app limited if the application didn't have enough data to fill the window when the
+segment was sent - those samples underestimate the bottleneck bandwidth.
+
+*/
+
+/* SACK scoreboard (RFC 2018 + RFC 6675):
+
+Every segment in the queue remembers whether our partner has reported it in a SACK block. During
+loss recovery a segment that isn't SACKed counts as lost once DupThresh segments sent after it
+have been SACKed, and lost segments are retransmitted one per incoming ACK, lowest first.
+HighRxt (the end of the highest retransmitted segment) keeps us from resending a hole twice
+before the partner had a chance to report on it.
+
+*/
+
+/* Giving up (RFC 9293 3.8.3 + RFC 5482):
+
+Segments get retransmitted until they're acknowledged - never dropped. Once a segment has been
+retransmitted R1 times, the connection might be failing and we say so. Once the oldest segment
+has gone unacknowledged for longer than the user timeout (R2), the connection is aborted.
+
+*/
+
 // NOTE: These should be 1 millisecond and 60000 milliseconds for turn in
-// CONSTANTS
-const MIN_RTO: u64 = 10; // Milliseconds
+// CONSTANTS - defaults, the lnx file can override them
+pub const INITIAL_RTO: u64 = 1000; // Milliseconds, RFC 6298 (2.1)
+pub const MIN_RTO: u64 = 10; // Milliseconds
 pub const MAX_RTO: u64 = 60000; // Milliseconds
-const MAX_RETRANSMISSIONS: u32 = 3;
+pub const R1: u32 = 3; // Retransmissions of a segment before we warn that the connection might be failing
 
 #[derive(Debug)]
 pub struct RetransmissionTimer {
     pub rto: Duration,             // RTO: retransmission timeout
     srtt: Option<Duration>,        // Initially none, see above algo
     rttvar: Option<Duration>,      // Initially none, see above algo
-    min_rto: Duration,             // Minimum RTO: 1ms for imp, 150-250ms for testing
-    max_rto: Duration,             // Maximum RTO: 100ms(?)
-    pub retransmission_count: u32, // Attempt counter ; stop at 3
+    initial_rto: Duration,         // RTO until the first RTT measurement - tcp rto-initial in the lnx file
+    min_rto: Duration,             // Minimum RTO: 1ms for imp, 150-250ms for testing - tcp rto-min in the lnx file
+    max_rto: Duration,             // Maximum RTO - tcp rto-max in the lnx file
+    pub retransmission_count: u32, // Attempt counter
 }
 impl Default for RetransmissionTimer {
     fn default() -> Self {
-        Self::new()
+        Self::new(
+            Duration::from_millis(INITIAL_RTO),
+            Duration::from_millis(MIN_RTO),
+            Duration::from_millis(MAX_RTO),
+        )
     }
 }
 
 impl RetransmissionTimer {
-    pub fn new() -> RetransmissionTimer {
+    /// The RTO starts out at the initial RTO, kept between the minimum and maximum, until the first RTT measurement
+    pub fn new(initial_rto: Duration, min_rto: Duration, max_rto: Duration) -> RetransmissionTimer {
+        let initial_rto = initial_rto.clamp(min_rto, max_rto);
         RetransmissionTimer {
-            rto: Duration::from_millis(MIN_RTO),
+            rto: initial_rto,
             srtt: None,
             rttvar: None,
-            min_rto: Duration::from_millis(MIN_RTO),
-            max_rto: Duration::from_millis(MAX_RTO),
+            initial_rto,
+            min_rto,
+            max_rto,
             retransmission_count: 0,
         }
     }
@@ -92,6 +136,10 @@ impl RetransmissionTimer {
         self.rto = Duration::from_secs_f64(rto_secs);
         self.rto = self.rto.clamp(self.min_rto, self.max_rto);
     }
+    /// Smoothed RTT, None until the first measurement
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
     pub fn do_retransmission(&mut self) {
         self.retransmission_count += 1;
         self.rto *= 2; // RFC 6298 (5.5)
@@ -108,31 +156,103 @@ impl RetransmissionTimer {
             self.rto = self.rto.clamp(self.min_rto, self.max_rto);
         } else {
             // If no RTT measurements yet, set RTO to initial value
-            self.rto = Duration::from_millis(MIN_RTO);
+            self.rto = self.initial_rto;
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct RetrSegment {
-    pub seq_num: u32,
+    pub seq_num: SeqNum,
     pub payload: Vec<u8>,
     pub flags: u8,
     pub time_of_send: Instant,
+    pub first_sent: Instant, // time_of_send changes with every retransmission, this doesn't
     pub checksum: u16,
     pub retransmission_count: u32,
+    pub delivery: DeliverySnapshot,
+    pub sacked: bool, // Whether our partner reported receiving this segment in a SACK block
+}
+
+/// State of the connection's delivery tracker when a segment was last sent
+#[derive(Debug, Clone, Copy)]
+pub struct DeliverySnapshot {
+    pub delivered: u64,
+    pub delivered_time: Instant,
+    pub first_sent_time: Instant,
+    pub app_limited: bool,
+}
+
+/// A delivery rate sample, taken whenever an ACK acknowledges new data
+#[derive(Debug, Clone, Copy)]
+pub struct RateSample {
+    pub delivery_rate: f64,   // Bytes per second
+    pub delivered: u64,       // Total bytes delivered once this ACK arrived
+    pub prior_delivered: u64, // Total bytes delivered when the acked segment was sent
+    pub interval: Duration,
+    pub rtt: Duration, // Time between the acked segment's last transmission and its ACK
+    pub is_app_limited: bool,
+}
+
+/// Connection wide delivery counters used to build rate samples
+#[derive(Debug)]
+pub struct DeliveryTracker {
+    pub delivered: u64,
+    delivered_time: Instant,
+    first_sent_time: Instant,
+    app_limited: u64, // 0 if not app limited, otherwise the value of delivered at which we stop being app limited
+}
+
+impl Default for DeliveryTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeliveryTracker {
+    pub fn new() -> DeliveryTracker {
+        let now = Instant::now();
+        DeliveryTracker {
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            app_limited: 0,
+        }
+    }
+    /// Snapshot to attach to a segment that's being sent right now
+    fn snapshot(&mut self, nothing_in_flight: bool) -> DeliverySnapshot {
+        if nothing_in_flight {
+            // Start of a new flight - don't count the idle time
+            let now = Instant::now();
+            self.first_sent_time = now;
+            self.delivered_time = now;
+        }
+        DeliverySnapshot {
+            delivered: self.delivered,
+            delivered_time: self.delivered_time,
+            first_sent_time: self.first_sent_time,
+            app_limited: self.app_limited != 0,
+        }
+    }
+    /// Marks the connection as app limited until everything currently in flight is delivered
+    pub fn mark_app_limited(&mut self, in_flight: usize) {
+        self.app_limited = cmp::max(self.delivered + in_flight as u64, 1);
+    }
 }
 
 impl RetrSegment {
     /// Creates a new retransmission segment
-    pub fn new(seq_num: u32, data: Vec<u8>, flags: u8, checksum: u16) -> RetrSegment {
+    pub fn new(seq_num: SeqNum, data: Vec<u8>, flags: u8, checksum: u16) -> RetrSegment {
         RetrSegment {
             seq_num,
             payload: data,
             flags,
             time_of_send: Instant::now(),
+            first_sent: Instant::now(),
             checksum,
             retransmission_count: 0,
+            delivery: DeliveryTracker::new().snapshot(true),
+            sacked: false,
         }
     }
     /// Checks if a retransmission segment has timed out
@@ -144,11 +264,19 @@ impl RetrSegment {
     fn update_time_of_send(&mut self) {
         self.time_of_send = Instant::now();
     }
+    /// Sequence number directly after this segment (SYN and FIN each take up one sequence number)
+    pub fn end_seq(&self) -> SeqNum {
+        let flag_len = ((self.flags & SYN) != 0) as u32 + ((self.flags & FIN) != 0) as u32;
+        self.seq_num + self.payload.len() as u32 + flag_len
+    }
 }
 
 #[derive(Debug)]
 pub struct RetransmissionQueue {
     pub queue: VecDeque<RetrSegment>,
+    pub delivery: DeliveryTracker,
+    rate_sample: Option<RateSample>, //Sample from the last ACK that removed segments
+    high_rxt: Option<SeqNum>,        //Sequence number after the highest segment retransmitted so far
 }
 
 impl Default for RetransmissionQueue {
@@ -161,20 +289,76 @@ impl RetransmissionQueue {
     pub fn new() -> RetransmissionQueue {
         RetransmissionQueue {
             queue: VecDeque::new(),
+            delivery: DeliveryTracker::new(),
+            rate_sample: None,
+            high_rxt: None,
         }
     }
-    pub fn remove_acked_segments(&mut self, ack_num: u32) -> Option<RetrSegment> {
+    /// How long the oldest segment in the queue has gone unacknowledged
+    pub fn oldest_unacked_for(&self) -> Option<Duration> {
+        self.queue.front().map(|seg| seg.first_sent.elapsed())
+    }
+    /// Removes all segments fully acknowledged by ack_num
+    /// Returns the most recently sent of them if it was never retransmitted, as only those give valid RTT samples (Karn's algorithm)
+    /// Also takes a delivery rate sample, retrievable with take_rate_sample()
+    pub fn remove_acked_segments(&mut self, ack_num: SeqNum) -> Option<RetrSegment> {
+        let mut rtt_sample = None;
+        let mut newest_sent: Option<RetrSegment> = None;
+        let now = Instant::now();
         while let Some(front) = self.queue.front() {
-            if front.seq_num < ack_num {
-                self.queue.pop_front();
+            if front.end_seq() <= ack_num {
+                let seg = self.queue.pop_front().unwrap();
+                self.delivery.delivered += seg.payload.len() as u64;
+                self.delivery.delivered_time = now;
+                if newest_sent
+                    .as_ref()
+                    .is_none_or(|newest| seg.time_of_send >= newest.time_of_send)
+                {
+                    newest_sent = Some(seg.clone());
+                }
+                rtt_sample = match seg.retransmission_count {
+                    0 => Some(seg),
+                    _ => None,
+                };
             } else {
                 break;
             }
         }
-        None
+        if let Some(seg) = newest_sent {
+            self.rate_sample = self.build_rate_sample(&seg, now);
+        }
+        rtt_sample
     }
-    pub fn add_segment(&mut self, seq_num: u32, data: Vec<u8>, flags: u8, checksum: u16) {
-        let segment = RetrSegment::new(seq_num, data, flags, checksum);
+    /// Builds a delivery rate sample from the most recently sent segment an ACK acknowledged
+    fn build_rate_sample(&mut self, seg: &RetrSegment, now: Instant) -> Option<RateSample> {
+        let snap = seg.delivery;
+        if self.delivery.app_limited != 0 && self.delivery.delivered > self.delivery.app_limited {
+            self.delivery.app_limited = 0;
+        }
+        self.delivery.first_sent_time = seg.time_of_send;
+        let send_elapsed = seg.time_of_send.duration_since(snap.first_sent_time);
+        let ack_elapsed = now.duration_since(snap.delivered_time);
+        let interval = cmp::max(send_elapsed, ack_elapsed);
+        if interval.is_zero() {
+            return None;
+        }
+        let delivered = self.delivery.delivered - snap.delivered;
+        Some(RateSample {
+            delivery_rate: delivered as f64 / interval.as_secs_f64(),
+            delivered: self.delivery.delivered,
+            prior_delivered: snap.delivered,
+            interval,
+            rtt: now.duration_since(seg.time_of_send),
+            is_app_limited: snap.app_limited,
+        })
+    }
+    /// Returns the delivery rate sample taken by the last call to remove_acked_segments, if any
+    pub fn take_rate_sample(&mut self) -> Option<RateSample> {
+        self.rate_sample.take()
+    }
+    pub fn add_segment(&mut self, seq_num: SeqNum, data: Vec<u8>, flags: u8, checksum: u16) {
+        let mut segment = RetrSegment::new(seq_num, data, flags, checksum);
+        segment.delivery = self.delivery.snapshot(self.queue.is_empty());
         // If this segment has a FIN, ensure it remains at the bottom of the queue
         if (flags & FIN) != 0 {
             // Remove any previously queued FIN segment, as only one FIN should be at the bottom
@@ -192,19 +376,148 @@ impl RetransmissionQueue {
         self.queue.is_empty()
     }
 
-    pub fn get_next_timeout(&mut self, current_rto: Duration) -> Option<RetrSegment> {
-        if let Some(front) = self.queue.front_mut() {
-            if front.timed_out(current_rto) {
-                if front.retransmission_count >= MAX_RETRANSMISSIONS {
-                    println!("Dropping segment seq={}", front.seq_num);
-                    self.queue.pop_front();
-                    return None;
-                }
-                front.retransmission_count += 1;
-                front.update_time_of_send();
-                return Some(front.clone());
+    /// Marks the first unacknowledged segment as retransmitted and returns it - used for fast retransmits
+    pub fn retransmit_front(&mut self) -> Option<RetrSegment> {
+        let front = self.queue.front_mut()?;
+        front.retransmission_count += 1;
+        front.update_time_of_send();
+        front.delivery = self.delivery.snapshot(false);
+        let front = front.clone();
+        self.raise_high_rxt(front.end_seq());
+        Some(front)
+    }
+
+    /// Marks every segment fully covered by one of the input SACK blocks as SACKed
+    /// Returns whether any segment got newly SACKed
+    pub fn sack(&mut self, blocks: &[(SeqNum, SeqNum)]) -> bool {
+        let mut newly_sacked = false;
+        for seg in self.queue.iter_mut().filter(|seg| !seg.sacked) {
+            let end_seq = seg.end_seq();
+            seg.sacked = blocks
+                .iter()
+                .any(|(start, end)| *start <= seg.seq_num && end_seq <= *end);
+            newly_sacked |= seg.sacked;
+        }
+        newly_sacked
+    }
+    /// Starts a new loss recovery episode - whatever got retransmitted in earlier ones says nothing about what's lost now
+    pub fn enter_recovery(&mut self) {
+        self.high_rxt = None;
+    }
+    fn raise_high_rxt(&mut self, end_seq: SeqNum) {
+        self.high_rxt = Some(self.high_rxt.map_or(end_seq, |high_rxt| high_rxt.max(end_seq)));
+    }
+    /// Marks the lowest lost segment that hasn't been retransmitted yet as retransmitted and returns it (RFC 6675 NextSeg())
+    pub fn retransmit_next_lost(&mut self) -> Option<RetrSegment> {
+        let mut sacked_after = 0;
+        let mut lost = None;
+        // Walk from the back so we know how many segments past each one were SACKed
+        for (i, seg) in self.queue.iter().enumerate().rev() {
+            if seg.sacked {
+                sacked_after += 1;
+            } else if sacked_after >= DUP_ACK_THRESHOLD && self.high_rxt.is_none_or(|high_rxt| seg.seq_num >= high_rxt) {
+                lost = Some(i);
             }
         }
-        None
+        let seg = self.queue.get_mut(lost?)?;
+        seg.retransmission_count += 1;
+        seg.update_time_of_send();
+        seg.delivery = self.delivery.snapshot(false);
+        let seg = seg.clone();
+        self.raise_high_rxt(seg.end_seq());
+        Some(seg)
+    }
+    /// Marks the first unacknowledged segment as retransmitted and returns it if it has timed out
+    /// Our partner may have reneged on what it SACKed, so the scoreboard starts over (RFC 6675 5.1)
+    pub fn get_next_timeout(&mut self, current_rto: Duration) -> Option<RetrSegment> {
+        if !self.queue.front()?.timed_out(current_rto) {
+            return None;
+        }
+        self.queue.iter_mut().for_each(|seg| seg.sacked = false);
+        let front = self.queue.front_mut()?;
+        front.retransmission_count += 1;
+        front.update_time_of_send();
+        front.delivery = self.delivery.snapshot(false);
+        self.high_rxt = Some(front.end_seq());
+        Some(front.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_initial_rto_until_measured() {
+        let mut timer = RetransmissionTimer::default();
+        assert_eq!(timer.rto, Duration::from_secs(1));
+        timer.do_retransmission();
+        timer.reset();
+        assert_eq!(timer.rto, Duration::from_secs(1));
+        timer.update_rto(Duration::from_millis(2));
+        assert_eq!(timer.rto, Duration::from_millis(MIN_RTO)); //The minimum only kicks in once there are measurements
+        // Kept within the minimum and maximum
+        let timer = RetransmissionTimer::new(Duration::from_secs(1), Duration::from_millis(10), Duration::from_millis(500));
+        assert_eq!(timer.rto, Duration::from_millis(500));
+        let timer = RetransmissionTimer::new(Duration::from_secs(1), Duration::from_secs(2), Duration::from_secs(5));
+        assert_eq!(timer.rto, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_sack_scoreboard_retransmits_only_holes() {
+        let mut queue = RetransmissionQueue::new();
+        for i in 0..8 {
+            queue.add_segment(SeqNum::new(i * 100), vec![0; 100], 0, 0);
+        }
+        queue.remove_acked_segments(SeqNum::new(100));
+        // Segments 1 and 4 got lost
+        queue.sack(&[(SeqNum::new(600), SeqNum::new(800)), (SeqNum::new(200), SeqNum::new(400))]);
+        queue.sack(&[(SeqNum::new(500), SeqNum::new(800)), (SeqNum::new(200), SeqNum::new(400))]);
+        assert_eq!(queue.queue.iter().filter(|seg| seg.sacked).count(), 5);
+        // Both holes have at least 3 SACKed segments after them
+        assert_eq!(queue.retransmit_next_lost().unwrap().seq_num, SeqNum::new(100));
+        assert_eq!(queue.retransmit_next_lost().unwrap().seq_num, SeqNum::new(400));
+        assert!(queue.retransmit_next_lost().is_none());
+    }
+
+    #[test]
+    fn test_remove_acked_across_wraparound() {
+        let mut queue = RetransmissionQueue::new();
+        let isn = SeqNum::new(u32::MAX - 150);
+        for i in 0..4 {
+            queue.add_segment(isn + i * 100, vec![0; 100], 0, 0);
+        }
+        // The third segment ends past 2^32 - 1
+        queue.remove_acked_segments(isn + 300);
+        assert_eq!(queue.queue.len(), 1);
+        assert_eq!(queue.queue[0].seq_num.value(), 149);
+    }
+
+    #[test]
+    fn test_new_recovery_episode_forgets_old_retransmissions() {
+        let mut queue = RetransmissionQueue::new();
+        queue.add_segment(SeqNum::new(0), vec![0; 100], 0, 0);
+        queue.retransmit_front();
+        queue.remove_acked_segments(SeqNum::new(100));
+        // 2^31 bytes later, the old high-water mark compares as being ahead of everything
+        let base = SeqNum::new(0x8000_0100);
+        for i in 0..5 {
+            queue.add_segment(base + i * 100, vec![0; 100], 0, 0);
+        }
+        queue.sack(&[(base + 100, base + 500)]);
+        queue.enter_recovery();
+        assert_eq!(queue.retransmit_next_lost().unwrap().seq_num, base);
+    }
+
+    #[test]
+    fn test_timeout_clears_scoreboard() {
+        let mut queue = RetransmissionQueue::new();
+        for i in 0..5 {
+            queue.add_segment(SeqNum::new(i * 100), vec![0; 100], 0, 0);
+        }
+        queue.sack(&[(SeqNum::new(100), SeqNum::new(500))]);
+        assert_eq!(queue.get_next_timeout(Duration::ZERO).unwrap().seq_num, SeqNum::new(0));
+        assert!(queue.queue.iter().all(|seg| !seg.sacked));
+        assert!(queue.retransmit_next_lost().is_none());
     }
 }
@@ -40,7 +40,7 @@ pub trait RipDaemon: VnodeIpDaemon {
         }
     }
 
-    ///BROADCAST FUNCTIONS
+    //BROADCAST FUNCTIONS
 
     ///Sends a RIP response to all RIP neighbors
     fn rip_broadcast(&self) {
@@ -58,9 +58,9 @@ pub fn poison_routes(
         if route.cost == 0 {
             // Locallllll
             ret_routes.push(route);
-        } else if route.address == dst.into() {
-            ret_routes.push(RipRoute::new(INF, route.address, route.mask));
-        } else if neighbor_routes.contains_key(&Ipv4Addr::from(route.address)) {
+        } else if route.address == dst.into()
+            || neighbor_routes.contains_key(&Ipv4Addr::from(route.address))
+        {
             ret_routes.push(RipRoute::new(INF, route.address, route.mask));
         } else {
             ret_routes.push(route);
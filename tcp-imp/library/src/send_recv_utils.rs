@@ -1,13 +1,19 @@
+use crate::congestion::*;
+use crate::poll::Watchers;
+use crate::reassembly::Reassembly;
+use std::task::Waker;
 use crate::prelude::*;
 use crate::retransmission::*;
-
-const MAX_MSG_SIZE: usize = 1418; //+ 82 for headers = 1500 total max packet size
-const BUFFER_CAPACITY: usize = 65535;
+use crate::seq_num::SeqNum;
+use crate::tcp_utils::DEFAULT_MSS;
+use std::collections::VecDeque;
 
 #[derive(Debug)]
 pub struct SyncBuf<T: TcpBuffer> {
     ready: Condvar,
     buf: Mutex<T>,
+    aborted: Mutex<Option<(ErrorKind, &'static str)>>, //Set once the connection is aborted - nothing will ever be ready again
+    watchers: Watchers, //Woken along with the condvar, for poll() and anyone else not blocking on it
 }
 
 impl<T: TcpBuffer> SyncBuf<T> {
@@ -15,18 +21,67 @@ impl<T: TcpBuffer> SyncBuf<T> {
         SyncBuf {
             ready: Condvar::new(),
             buf: Mutex::new(buf),
+            aborted: Mutex::new(None),
+            watchers: Watchers::new(),
         }
     }
     pub fn alert_ready(&self) {
-        self.ready.notify_one();
+        self.ready.notify_all();
+        self.watchers.wake_all();
+    }
+    ///Like wait(), but gives back None instead of blocking if the buffer isn't ready
+    pub fn try_wait(&self) -> Option<std::sync::MutexGuard<'_, T>> {
+        let buf = self.buf.lock().unwrap();
+        match buf.ready() || self.aborted().is_some() {
+            true => Some(buf),
+            false => None,
+        }
+    }
+    pub fn watch(&self, key: u64, waker: &Waker) {
+        self.watchers.watch(key, waker);
+    }
+    pub fn unwatch(&self, key: u64) {
+        self.watchers.unwatch(key);
     }
     pub fn wait(&self) -> std::sync::MutexGuard<'_, T> {
         let mut buf = self.buf.lock().unwrap();
-        while !buf.ready() {
+        while !buf.ready() && self.aborted().is_none() {
             buf = self.ready.wait(buf).unwrap();
         }
         buf
     }
+    ///Waits until the input condition holds or the connection is aborted, giving up at the deadline if there is one
+    ///Returns whether the condition holds
+    pub fn wait_for(&self, done: impl Fn(&T) -> bool, deadline: Option<Instant>) -> bool {
+        let mut buf = self.buf.lock().unwrap();
+        while !done(&buf) && self.aborted().is_none() {
+            buf = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.ready.wait_timeout(buf, deadline - now).unwrap().0
+                }
+                None => self.ready.wait(buf).unwrap(),
+            };
+        }
+        done(&buf)
+    }
+    ///Wakes up every waiting thread for good, the connection is gone - they should fail with the input error
+    pub fn abort(&self, kind: ErrorKind, msg: &'static str) {
+        let _buf = self.buf.lock().unwrap(); //Waiters check for an abort while holding the lock, so no wakeup gets lost
+        *self.aborted.lock().unwrap() = Some((kind, msg));
+        self.ready.notify_all();
+        self.watchers.wake_all();
+    }
+    ///The error the connection was aborted with, if it was
+    pub fn aborted(&self) -> Option<Error> {
+        self.aborted
+            .lock()
+            .unwrap()
+            .map(|(kind, msg)| Error::new(kind, msg))
+    }
     pub fn get_buf(&self) -> std::sync::MutexGuard<'_, T> {
         self.buf.lock().unwrap()
     }
@@ -38,77 +93,90 @@ pub trait TcpBuffer {
 
 #[derive(Debug)]
 pub struct SendBuf {
-    pub circ_buffer: CircularBuffer<BUFFER_CAPACITY, u8>,
+    pub circ_buffer: VecDeque<u8>, //Only grows as far as it needs to, so an idle socket holds on to next to nothing
+    capacity: usize, //Most bytes circ_buffer may hold
     //una: usize, Don't need, b/c una will always be 0 technically
     pub nxt: usize, // Pointer to next byte to be sent ; NOTE, UPDATE AS BYTES DRAINED
     //lbw: usize Don't need b/c lbw will always be circ_buffer.len() technically
-    pub rem_window: u16,
-    num_acked: u32,
-    our_init_seq: u32,
+    pub rem_window: u32,
+    window_changed: bool, //Whether the last window update changed rem_window - a changed window means an ACK is not a duplicate
+    una: SeqNum, //Sequence number of the oldest unacknowledged byte
     pub probing: bool, //Identifies whether or not we are currently probing - a little kludgy
     stop_probing_sender: Sender<()>,
     pub retr_queue: RetransmissionQueue,
+    pub cc: Box<dyn CongestionControl>,
+    mss: usize, //Largest payload we put in a segment
+    nodelay: bool, //Turns off Nagle's algorithm
 }
 
 impl TcpBuffer for SendBuf {
     //Ready when buffer is not full
     fn ready(&self) -> bool {
-        self.circ_buffer.len() != self.circ_buffer.capacity()
+        self.circ_buffer.len() < self.capacity
     }
 }
 
 impl SendBuf {
-    pub fn new(our_init_seq: u32, stop_probing_sender: Sender<()>) -> SendBuf {
+    pub fn new(
+        our_init_seq: SeqNum,
+        stop_probing_sender: Sender<()>,
+        cc_algo: CongestionAlgorithm,
+        capacity: usize,
+    ) -> SendBuf {
         SendBuf {
-            circ_buffer: CircularBuffer::new(),
+            circ_buffer: VecDeque::new(),
+            capacity,
             nxt: 0,
             rem_window: 0,
-            num_acked: 0,
-            our_init_seq, //OURS
+            window_changed: false,
+            una: our_init_seq + 1, //Our SYN takes up the first sequence number
             probing: false,
             stop_probing_sender,
             retr_queue: RetransmissionQueue::new(),
+            cc: cc_algo.build(DEFAULT_MSS as usize),
+            mss: DEFAULT_MSS as usize, //Until the handshake tells us better
+            nodelay: false,
         }
     }
     ///Fills up the circular buffer with the data in filler until the buffer is full,
     ///then returns the original input filler vector drained of the values added to the circular buffer
     pub fn fill_with(&mut self, mut filler: Vec<u8>) -> Vec<u8> {
-        let available_spc = self.circ_buffer.capacity() - self.circ_buffer.len();
+        let available_spc = self.capacity.saturating_sub(self.circ_buffer.len());
         let to_add = filler
             .drain(..std::cmp::min(available_spc, filler.len()))
             .collect::<Vec<u8>>();
 
-        self.circ_buffer.extend_from_slice(&to_add[..]);
+        self.circ_buffer.extend(to_add);
         filler
     }
-    ///Returns a vector of data to be put in the next TcpPacket to send, taking into account the input window size of the receiver
-    ///This vector contains as many bytes as possible up to the maximum payload size (1500)
+    ///Returns a vector of data to be put in the next TcpPacket to send, taking into account the receiver's window
+    ///and the congestion window. This vector contains as many bytes as possible up to the maximum payload size
     pub fn next_data(&mut self) -> NextData {
+        if self.circ_buffer.len() <= self.nxt {
+            // Everything in the buffer has already been sent - rate samples taken now say nothing about the path
+            self.retr_queue.delivery.mark_app_limited(self.nxt);
+            return NextData::NoData;
+        }
         if self.rem_window == 0 {
-            // Zero window probing
-            self.probing = true;
-            let data = if self.circ_buffer.len() > self.nxt {
-                // Normal case: resend one byte from the buffer to probe
-                self.see_amount(1)
-            } else if !self.circ_buffer.is_empty() {
-                // If nxt points beyond len, but there is data, adjust nxt if needed:
-                let available = self.circ_buffer.len() - self.nxt;
-                self.see_amount(std::cmp::min(1, available))
-            } else {
-                // No data at all, insert a dummy byte for probing
+            // Zero window probing - only once everything in flight has been acknowledged
+            if self.nxt != 0 || self.probing {
                 return NextData::NoData;
-            };
-            NextData::ZeroWindow(data)
-        } else {
-            // Normal data
-            let greatest_constraint = std::cmp::min(self.rem_window as usize, MAX_MSG_SIZE);
-            let data = self.take_amount(greatest_constraint);
-            self.rem_window -= data.len() as u16;
-            if data.is_empty() {
-                NextData::NoData
-            } else {
-                NextData::Data(data)
             }
+            self.probing = true;
+            return NextData::ZeroWindow(self.take_amount(1));
+        }
+        // Nagle (RFC 896) - while data is unacknowledged, small writes wait until they add up to a full segment
+        if !self.nodelay && self.nxt != 0 && self.circ_buffer.len() - self.nxt < self.mss {
+            return NextData::NoData;
+        }
+        // Can't have more than min(rwnd, cwnd) bytes in flight
+        let window = cmp::min(self.rem_window as usize, self.cc.cwnd());
+        let usable = window.saturating_sub(self.nxt);
+        let data = self.take_amount(cmp::min(usable, self.mss));
+        if data.is_empty() {
+            NextData::NoData
+        } else {
+            NextData::Data(data)
         }
     }
 
@@ -128,52 +196,98 @@ impl SendBuf {
             .cloned()
             .collect()
     }
-    ///Acknowledges (drops) all sent bytes up to the one indicated by most_recent_ack
     /// Acknowledges (drops) all sent bytes up to the one indicated by most_recent_ack
-    pub fn ack_data(&mut self, most_recent_ack: u32) {
-        let expected_ack = self.num_acked + self.our_init_seq + 1;
-        if most_recent_ack < expected_ack {
-            return;
+    /// Returns the number of data bytes newly acknowledged
+    pub fn ack_data(&mut self, most_recent_ack: SeqNum) -> usize {
+        let expected_ack = self.una;
+        if most_recent_ack <= expected_ack {
+            return 0;
         }
+        // Anything acknowledged past nxt is our FIN, which isn't stored in the buffer
+        let acked_data = cmp::min((most_recent_ack - expected_ack) as usize, self.nxt);
 
-        let mut relative_ack = most_recent_ack - expected_ack;
-        let acked_bytes = relative_ack as usize;
+        // Drain out the acknowledged data
+        // This removes the acknowledged bytes from the front of circ_buffer
+        self.circ_buffer.drain(..acked_data);
+        self.nxt -= acked_data;
+        self.una += acked_data as u32;
 
-        // If we were probing and the ACK covers beyond nxt, it means our probe byte got acknowledged.
-        if self.probing && acked_bytes > self.nxt {
+        // If we were probing and everything up to nxt is acked, our probe byte got through
+        if self.probing && self.nxt == 0 {
             self.probing = false;
-            // The probe byte is considered sent from nxt, so increment nxt by one
-            // since that byte is now acknowledged.
             let _ = self.stop_probing_sender.send(()); // Ignore if fails
-                                                       // Adjust relative_ack since that one byte was a probe
-        }
-
-        // Handle FIN acknowledgment if needed
-        // (If you have a FIN at nxt+1, and it's acked, decrement relative_ack by 1.)
-        if acked_bytes == self.nxt + 1 {
-            relative_ack -= 1;
         }
-
-        let acked_data = relative_ack as usize;
-        let available_data = self.circ_buffer.len();
-        let actual_acked = std::cmp::min(acked_data, available_data);
-
-        // Never let nxt go negative
-        if actual_acked >= self.nxt {
-            // All currently "in-flight" data plus possibly the probe is acked
-            self.nxt = 0;
-        } else {
-            self.nxt -= actual_acked;
+        acked_data
+    }
+    ///Handles an ACK that acknowledges new data, informing the congestion controller
+    pub fn new_ack(&mut self, ack_num: SeqNum) -> CcAction {
+        let acked = self.ack_data(ack_num);
+        let ack = AckInfo {
+            acked,
+            ack_num,
+            snd_nxt: self.snd_nxt(),
+            in_flight: self.nxt,
+            rate: self.retr_queue.take_rate_sample(),
+        };
+        self.cc.on_ack(ack)
+    }
+    ///Handles a duplicate ACK, informing the congestion controller
+    pub fn dup_ack(&mut self, dup_count: u32) -> CcAction {
+        let snd_nxt = self.snd_nxt();
+        let was_recovering = self.cc.in_recovery();
+        let action = self.cc.on_dup_ack(dup_count, snd_nxt, self.nxt);
+        if !was_recovering && self.cc.in_recovery() {
+            self.retr_queue.enter_recovery();
         }
-
-        // Drain out the acknowledged data
-        // This removes the acknowledged bytes from the front of circ_buffer
-        self.circ_buffer.drain(..actual_acked);
-        self.num_acked += actual_acked as u32;
+        action
+    }
+    ///Informs the congestion controller that the retransmission timer expired
+    pub fn timed_out(&mut self) {
+        self.cc.on_rto(self.nxt);
+    }
+    ///Returns whether or not an ACK with an unchanged acknowledgement number counts as a duplicate ACK (RFC 5681)
+    pub fn is_dup_ack(&self) -> bool {
+        !self.window_changed && (self.nxt != 0 || !self.retr_queue.is_empty())
+    }
+    ///Sequence number of the next new byte to be sent
+    fn snd_nxt(&self) -> SeqNum {
+        self.una + self.nxt as u32
     }
 
+    ///How long to wait after sending a segment of the given length, if the congestion controller paces its sending
+    pub fn pacing_delay(&self, len: usize) -> Option<Duration> {
+        let rate = self.cc.pacing_rate()?;
+        Some(Duration::from_secs_f64(len as f64 / rate))
+    }
+    ///Swaps in a new congestion controller
+    pub fn set_congestion_control(&mut self, cc_algo: CongestionAlgorithm) {
+        self.cc = cc_algo.build(self.mss);
+    }
+    ///Sets the largest payload put in a single segment - the congestion controller needs swapping in again afterwards
+    pub fn set_mss(&mut self, mss: usize) {
+        self.mss = mss;
+    }
+    pub fn mss(&self) -> usize {
+        self.mss
+    }
+    ///Turns Nagle's algorithm off (true) or back on (false)
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.nodelay = nodelay;
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    ///Resizes the buffer - anything it already holds past the new size still gets sent, but nothing more is taken in until it's down below it
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
     ///Updates the SendBuf's internal tracker of how many more bytes can be sent before filling the reciever's window
-    pub fn update_window(&mut self, new_window: u16) {
+    ///First sequence number past what our partner's window lets us send
+    pub fn window_end(&self) -> SeqNum {
+        self.una + self.rem_window
+    }
+    pub fn update_window(&mut self, new_window: u32) {
+        self.window_changed = new_window != self.rem_window;
         self.rem_window = new_window;
     }
     // pub fn check_timeouts(&mut self, current_rto: Duration) -> Vec<RetrSegment> {
@@ -181,9 +295,6 @@ impl SendBuf {
     //         { self.retr_queue.get_timed_out_segments(current_rto) };
     //     timed_out_segments
     // }
-    pub fn still_sending(&self) -> bool {
-        self.circ_buffer.is_empty() && self.nxt == 0 && !self.probing && self.retr_queue.is_empty()
-    }
 }
 
 pub enum NextData {
@@ -194,35 +305,37 @@ pub enum NextData {
 
 #[derive(Debug)]
 pub struct RecvBuf {
-    circ_buffer: CircularBuffer<BUFFER_CAPACITY, u8>,
+    circ_buffer: VecDeque<u8>,
+    capacity: usize, //Most bytes circ_buffer may hold - early arrivals go in the window past it
     //lbr: usize Don't need, lbr will always be 0
     //nxt: usize Don't need, nxt will always be circ_buffer.len()
-    early_arrivals: PayloadMap,
-    bytes_read: u32,
-    rem_init_seq: u32,
-    final_seq: Option<u32>,
+    early_arrivals: Reassembly,
+    last_early_arrival: Option<SeqNum>, //Sequence number of the most recent out of order segment - its SACK block gets reported first
+    lbr: SeqNum, //Sequence number of the first byte in circ_buffer, the next one to be read
+    final_seq: Option<SeqNum>,
+    shut: bool, //Reading was shut down, so whatever comes in is dropped as soon as it's in order
+    window_floor: Option<SeqNum>, //Right edge we offered before the buffer shrank - the window doesn't pull back from it (RFC 9293 3.8.6)
 }
 
 impl TcpBuffer for RecvBuf {
     //Ready when buffer has some elements
     fn ready(&self) -> bool {
-        let received_fin = if self.final_seq.is_some() {
-            true
-        } else {
-            false
-        };
-        !self.circ_buffer.is_empty() || received_fin
+        let received_fin = self.final_seq.is_some();
+        !self.circ_buffer.is_empty() || received_fin || self.shut
     }
 }
 
 impl RecvBuf {
-    pub fn new() -> RecvBuf {
+    pub fn new(capacity: usize) -> RecvBuf {
         RecvBuf {
-            circ_buffer: CircularBuffer::new(),
-            early_arrivals: PayloadMap::new(),
-            bytes_read: 0,
-            rem_init_seq: 0, //We don't know yet *shrug* - gets set once and then is never edited
+            circ_buffer: VecDeque::new(),
+            capacity,
+            early_arrivals: Reassembly::new(),
+            last_early_arrival: None,
+            lbr: SeqNum::new(0), //We don't know yet *shrug* - set once the partner's SYN comes in
             final_seq: None,
+            shut: false,
+            window_floor: None,
         }
     }
 
@@ -232,87 +345,159 @@ impl RecvBuf {
         let constraints = [bytes as usize, self.circ_buffer.len()];
         let greatest_constraint = constraints.iter().min().unwrap();
         let data: Vec<u8> = self.circ_buffer.drain(..greatest_constraint).collect();
-        self.bytes_read += data.len() as u32;
+        self.lbr += data.len() as u32;
+        self.settle_window_floor();
         data
     }
 
-    ///Adds the input data segment to the buffer if its sequence number is the next expected one. If not, inserts the segment into the
-    ///early arrival hashmap. If data is ever added to the buffer, the early arrivals hashmap is checked to see if it contains the
-    ///following expected segment, and the cycle continues until there are no more segments to add to the buffer
+    ///Adds whatever part of the input segment is new and fits in the window - see reassembly.rs. Then moves everything that's now
+    ///in order into the circular buffer
     ///Returns the next expected sequence number (the new ack number)
-    pub fn add(&mut self, seq_num: u32, data: Vec<u8>) -> u32 {
-        //println!("sequence number: {}\nexpected sequence number: {}", seq_num, self.expected_seq());
-        match seq_num.cmp(&self.expected_seq()) {
-            cmp::Ordering::Equal => {
-                let data_slice = match data.len() > self.window() as usize {
-                    true => &data[..self.window() as usize],
-                    false => &data[..],
-                };
-                self.circ_buffer.extend_from_slice(data_slice);
-                if let Some(next_data) = self.early_arrivals.remove(&self.expected_seq()) {
-                    return self.add(self.expected_seq(), next_data);
-                }
-            }
-            cmp::Ordering::Less => {} //Drop packet, contains stale data
-            cmp::Ordering::Greater => {
-                if data.len() <= self.window() as usize {
-                    self.early_arrivals.insert(seq_num, data)
-                }  //Drop packet, we don't have space
-            } //Early arrival, add it to early arrival hashmap
+    pub fn add(&mut self, seq_num: SeqNum, data: Vec<u8>) -> SeqNum {
+        let expected_seq = self.expected_seq();
+        let window_end = expected_seq + self.window();
+        self.early_arrivals.insert(seq_num, &data, expected_seq, window_end);
+        if seq_num > expected_seq {
+            self.last_early_arrival = Some(seq_num);
+        }
+        while let Some(next_data) = self.early_arrivals.pop(self.expected_seq()) {
+            self.circ_buffer.extend(next_data);
+        }
+        if self.shut {
+            self.lbr += self.circ_buffer.len() as u32; //Nobody is going to read it
+            self.circ_buffer.clear();
         }
         self.expected_seq()
     }
     ///Returns the next expected sequence number - only used privately, self.add() returns next sequence number too for public use
-    fn expected_seq(&self) -> u32 {
-        self.rem_init_seq + self.bytes_read + ((self.circ_buffer.len() + 1) as u32)
+    fn expected_seq(&self) -> SeqNum {
+        self.lbr + self.circ_buffer.len() as u32
+    }
+    ///Returns up to max_blocks SACK blocks describing the out of order data we're holding on to (RFC 2018)
+    ///The block containing the most recently received segment comes first
+    pub fn sack_blocks(&self, max_blocks: usize) -> Vec<(SeqNum, SeqNum)> {
+        let expected_seq = self.expected_seq();
+        let mut blocks: Vec<(SeqNum, SeqNum)> = Vec::new();
+        for (start, end) in self.early_arrivals.ranges() {
+            match blocks.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => blocks.push((start, end)),
+            }
+        }
+        let mut blocks: Vec<(SeqNum, SeqNum)> = blocks
+            .into_iter()
+            .filter(|(_, end)| *end > expected_seq)
+            .map(|(start, end)| (start.max(expected_seq), end))
+            .collect();
+        if let Some(recent) = self.last_early_arrival {
+            if let Some(pos) = blocks.iter().position(|(start, end)| (*start..*end).contains(&recent)) {
+                let block = blocks.remove(pos);
+                blocks.insert(0, block);
+            }
+        }
+        blocks.truncate(max_blocks);
+        blocks
     }
     ///Returns the buffer's current window size
-    pub fn window(&self) -> u16 {
-        (self.circ_buffer.capacity() - self.circ_buffer.len() - self.early_arrivals.len()) as u16
+    pub fn window(&self) -> u32 {
+        //Early arrivals lie inside the window, so they don't shrink it. Shrinking the buffer can leave it holding more than it's meant to
+        let window = self.capacity.saturating_sub(self.circ_buffer.len()) as u32;
+        match self.window_floor {
+            Some(floor) if floor > self.expected_seq() => cmp::max(window, floor - self.expected_seq()),
+            _ => window,
+        }
+    }
+    ///Returns the total size of the buffer, i.e. the largest window we'll advertise once whatever we offered before a shrink is used up
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    ///Resizes the buffer - anything it already holds past the new size stays until it's read, and a smaller buffer only
+    ///shrinks the window as reading moves the buffer's own right edge past the one we've already offered
+    pub fn set_capacity(&mut self, capacity: usize) {
+        let right_edge = self.expected_seq() + self.window();
+        self.capacity = capacity;
+        self.window_floor = Some(right_edge);
+        self.settle_window_floor();
+    }
+    ///Forgets the window floor once the buffer's own right edge has caught up with it, so it can't come back around
+    fn settle_window_floor(&mut self) {
+        if let Some(floor) = self.window_floor {
+            if self.lbr + self.capacity as u32 >= floor {
+                self.window_floor = None;
+            }
+        }
+    }
+    ///Whether we're holding on to data that arrived ahead of a hole
+    pub fn has_out_of_order(&self) -> bool {
+        !self.early_arrivals.is_empty()
     }
     ///Returns a boolean representing whether or not there is data the buffer still expects to receive
     pub fn can_receive(&self) -> bool {
+        if self.shut {
+            return false;
+        }
         match self.final_seq {
             Some(fin_seq_num) => self.expected_seq() < fin_seq_num || !self.circ_buffer.is_empty(), //Can receive if there are still packets out there OR if we still have stuff in our buffer
             None => true,
         }
     }
 
-    pub fn set_init_seq(&mut self, seq_num: u32) {
-        self.rem_init_seq = seq_num;
+    pub fn set_init_seq(&mut self, seq_num: SeqNum) {
+        self.lbr = seq_num + 1; //Their SYN takes up the first sequence number
     }
-    pub fn set_final_seq(&mut self, fin_seq_num: u32) {
+    pub fn set_final_seq(&mut self, fin_seq_num: SeqNum) {
         self.final_seq = Some(fin_seq_num);
     }
+    ///Whether our partner's FIN has come in
+    pub fn fin_received(&self) -> bool {
+        self.final_seq.is_some()
+    }
+    ///Drops everything buffered, along with anything that comes in from here on - it still gets acknowledged
+    pub fn shut_down(&mut self) {
+        self.shut = true;
+        self.lbr += self.circ_buffer.len() as u32;
+        self.circ_buffer.clear();
+    }
 }
 
-//A wrapper for a HashMap<u32, Vec<u8>> that keeps track of the cumulative size of the data stored in the map
-//accessable via the len method like a vector
-#[derive(Debug)]
-struct PayloadMap {
-    hash_map: HashMap<u32, Vec<u8>>, //Could be made pub if we ever need to call methods on the hashmap that aren't insert() or remove()
-    size: usize,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl PayloadMap {
-    pub fn new() -> PayloadMap {
-        PayloadMap {
-            hash_map: HashMap::new(),
-            size: 0,
-        }
+    #[test]
+    fn test_nagle_holds_small_segments_back() {
+        let (stop_probing_sender, _stop_probing_recver) = channel();
+        let mut send_buf = SendBuf::new(SeqNum::new(0), stop_probing_sender, CongestionAlgorithm::default(), 65535);
+        send_buf.update_window(65535);
+        send_buf.set_mss(100);
+        send_buf.fill_with(vec![0; 100]);
+        assert!(matches!(send_buf.next_data(), NextData::Data(data) if data.len() == 100));
+        // Less than a segment's worth waits while the first one is still unacknowledged...
+        send_buf.fill_with(vec![0; 10]);
+        assert!(matches!(send_buf.next_data(), NextData::NoData));
+        // ...unless Nagle is off
+        send_buf.set_nodelay(true);
+        assert!(matches!(send_buf.next_data(), NextData::Data(data) if data.len() == 10));
     }
-    pub fn insert(&mut self, key: u32, val: Vec<u8>) {
-        self.size += val.len();
-        self.hash_map.insert(key, val);
-    }
-    pub fn remove(&mut self, key: &u32) -> Option<Vec<u8>> {
-        let payload = self.hash_map.remove(key);
-        if let Some(data) = &payload {
-            self.size -= data.len()
-        }
-        payload
-    }
-    pub fn len(&self) -> usize {
-        self.size
+
+    #[test]
+    fn test_shrinking_keeps_offered_window() {
+        let mut recv_buf = RecvBuf::new(1000);
+        recv_buf.set_init_seq(SeqNum::new(99));
+        let isn = SeqNum::new(100);
+        let ack = recv_buf.add(isn, vec![1; 400]);
+        let offered = ack + recv_buf.window();
+        assert_eq!(offered, isn + 1000);
+        recv_buf.set_capacity(200);
+        assert_eq!(ack + recv_buf.window(), offered); //Held at the edge we already offered...
+        let ack = recv_buf.add(isn + 400, vec![2; 600]); //...so what was already on its way still fits
+        assert_eq!(ack, offered);
+        assert_eq!(recv_buf.window(), 0);
+        assert_eq!(recv_buf.read(500).len(), 500);
+        assert_eq!(recv_buf.window(), 0); //Still holding more than the new size
+        assert_eq!(recv_buf.read(400).len(), 400);
+        assert_eq!(recv_buf.window(), 100); //Drained below it, so it's the new size from here on
+        assert_eq!(recv_buf.window_floor, None);
+        assert!(ack + recv_buf.window() > offered);
     }
 }
@@ -0,0 +1,140 @@
+use std::cmp;
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+/* Sequence number arithmetic (RFC 9293 3.4 + RFC 1982):
+
+Sequence numbers live in a space of 2^32 values that wraps back around to 0, so plain u32
+comparisons break as soon as a connection's sequence numbers pass 2^32 - 1. Instead:
+
+s1 + n = (s1 + n) mod 2^32
+s1 - s2 = (s1 - s2) mod 2^32, the distance from s2 forward to s1
+s1 < s2 iff s1 != s2 and (s2 - s1) mod 2^32 < 2^31
+
+Ordering only means something for sequence numbers less than 2^31 apart, which anything inside a
+connection's windows always is. It isn't transitive across the whole space, so SeqNum is only
+PartialOrd - sequence numbers exactly 2^31 apart don't compare at all - and never goes in sorted
+collections as is. Keep those keyed on distances from a base sequence number instead.
+
+*/
+
+/// A TCP sequence (or acknowledgement) number, compared and added modulo 2^32
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SeqNum(u32);
+
+impl SeqNum {
+    pub const fn new(seq_num: u32) -> SeqNum {
+        SeqNum(seq_num)
+    }
+    /// The raw value, as it goes in a TCP header
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+    /// The later of two sequence numbers
+    pub fn max(self, other: SeqNum) -> SeqNum {
+        if other > self {
+            other
+        } else {
+            self
+        }
+    }
+    /// The earlier of two sequence numbers
+    pub fn min(self, other: SeqNum) -> SeqNum {
+        if other < self {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl From<u32> for SeqNum {
+    fn from(seq_num: u32) -> SeqNum {
+        SeqNum(seq_num)
+    }
+}
+
+impl From<SeqNum> for u32 {
+    fn from(seq_num: SeqNum) -> u32 {
+        seq_num.0
+    }
+}
+
+impl fmt::Display for SeqNum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl PartialOrd for SeqNum {
+    fn partial_cmp(&self, other: &SeqNum) -> Option<cmp::Ordering> {
+        match self.0.wrapping_sub(other.0) {
+            0x8000_0000 => None, //Halfway around - neither one is ahead of the other
+            distance => Some((distance as i32).cmp(&0)),
+        }
+    }
+}
+
+impl Add<u32> for SeqNum {
+    type Output = SeqNum;
+    fn add(self, n: u32) -> SeqNum {
+        SeqNum(self.0.wrapping_add(n))
+    }
+}
+
+impl AddAssign<u32> for SeqNum {
+    fn add_assign(&mut self, n: u32) {
+        *self = *self + n;
+    }
+}
+
+impl Sub<u32> for SeqNum {
+    type Output = SeqNum;
+    fn sub(self, n: u32) -> SeqNum {
+        SeqNum(self.0.wrapping_sub(n))
+    }
+}
+
+impl SubAssign<u32> for SeqNum {
+    fn sub_assign(&mut self, n: u32) {
+        *self = *self - n;
+    }
+}
+
+/// Distance from the right hand side forward to the left hand side - only meaningful if lhs >= rhs
+impl Sub<SeqNum> for SeqNum {
+    type Output = u32;
+    fn sub(self, other: SeqNum) -> u32 {
+        self.0.wrapping_sub(other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ordering_across_wraparound() {
+        let top = SeqNum::new(u32::MAX - 10);
+        let wrapped = top + 100;
+        assert_eq!(wrapped.value(), 89);
+        assert!(top < wrapped);
+        assert!(wrapped > top);
+        assert_eq!(wrapped - top, 100);
+        assert_eq!(wrapped - 100, top);
+        assert_eq!(top.max(wrapped), wrapped);
+        assert_eq!(wrapped.min(top), top);
+        assert!(SeqNum::new(5) < SeqNum::new(6));
+        assert!(SeqNum::new(0) > SeqNum::new(u32::MAX));
+    }
+
+    #[test]
+    fn test_halfway_apart_is_unordered() {
+        let (a, b) = (SeqNum::new(7), SeqNum::new(7) + (1 << 31));
+        assert_eq!(a.partial_cmp(&b), None);
+        assert_ne!(a, b);
+        //Not transitive, so no total order - each step is less than 2^31, but it comes back around
+        let (c, d) = (a + (1 << 30), a + (3 << 30));
+        assert!(a < c && c < b && b < d && d < a);
+    }
+}
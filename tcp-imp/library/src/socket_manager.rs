@@ -1,31 +1,49 @@
 use crate::conn_socket::ConnectionSocket;
+use crate::isn::IsnGenerator;
+use crate::poll::Readiness;
+use crate::seq_num::SeqNum;
 use crate::prelude::*;
 use crate::sockman_utils::*;
+use crate::tcp_config::TcpConfig;
 use crate::tcp_utils::*;
 use crate::utils::*;
+use std::task::Waker;
 
 pub struct SocketManager {
+    interface_reps: Arc<RwLock<InterfaceTable>>, //Both tables are only read, to work out the MSS of new connections
+    forwarding_table: Arc<RwLock<ForwardingTable>>,
     socket_table: Arc<RwLock<SocketTable>>,
     listener_table: ListenerTable,
     closed_sender: Arc<Sender<SocketId>>,
     ip_sender: Arc<Sender<PacketBasis>>,
     sid_assigner: Arc<SidAssigner>,
+    isn_generator: Arc<IsnGenerator>,
+    tcp_config: TcpConfig,
 }
 
 impl SocketManager {
     /// Create a new SocketManager, listener table initially empty
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        interface_reps: Arc<RwLock<InterfaceTable>>,
+        forwarding_table: Arc<RwLock<ForwardingTable>>,
         socket_table: Arc<RwLock<SocketTable>>,
         closed_sender: Arc<Sender<SocketId>>,
         ip_sender: Arc<Sender<PacketBasis>>,
         sid_assigner: Arc<SidAssigner>,
+        isn_generator: Arc<IsnGenerator>,
+        tcp_config: TcpConfig,
     ) -> SocketManager {
         SocketManager {
+            interface_reps,
+            forwarding_table,
             socket_table,
             listener_table: HashMap::new(),
             closed_sender,
             ip_sender,
             sid_assigner,
+            isn_generator,
+            tcp_config,
         }
     }
     /// Initialize connection socket for incoming packet and either add it to pending connections for listener or add it to socket table
@@ -42,57 +60,99 @@ impl SocketManager {
         //Figure out if a socket exists to handle the packet, pass it to the socket if it does, or tell the proper listener to create a connection socket for it (or drop it if no listeners for it)
         self.listener_recv(port, head, tcp_pack);
     }
-    /// Adds a listener to the listener table and socket table
-    pub fn listen(&mut self, port: u16) -> SocketId {
+    /// Answers a segment that doesn't belong to any socket with a reset
+    pub fn send_reset(&self, ip_head: &Ipv4Header, tpack: &TcpPacket) {
+        if let Some(pbasis) = reset_reply(ip_head, tpack) {
+            let _ = self.ip_sender.send(pbasis); //Nothing to be done if IP is gone
+        }
+    }
+    /// Adds a listener to the listener table and socket table, failing with AddrInUse if the port already has one
+    /// Past backlog half open connections, new ones are answered with SYN cookies; past backlog finished ones waiting to be accepted, new ones are dropped
+    pub fn listen(&mut self, port: u16, backlog: usize) -> Result<SocketId> {
+        if self.listener_table.contains_key(&port) {
+            return Err(Error::new(ErrorKind::AddrInUse, "Already listening on the input port"));
+        }
         {
-            self.listener_table.insert(port, ListenerEntry::new());
+            self.listener_table.insert(port, ListenerEntry::new(backlog));
         }
         {
             let mut socket_table = self.socket_table.write().unwrap();
-            let sid = socket_table.len() as u16; //THIS TOO
+            let sid = self.sid_assigner.assign_sid();
             let sock_listen_ent = ListenEntry::new(port);
             let listen_ent = SocketEntry::Listener(sock_listen_ent);
             socket_table.insert(sid, listen_ent);
-            sid
+            Ok(sid)
         }
     }
     /// Opens a listener on <port> to accepting new connections
     pub fn accept(&mut self, port: u16) {
         let listener_table = &mut self.listener_table;
-        match listener_table.get_mut(&port) {
-            Some(listener) => {
-                listener.accepting = true;
-                let mut sock_table = self.socket_table.write().unwrap();
+        // Listener may have been closed before this function got called
+        if let Some(listener) = listener_table.get_mut(&port) {
+            listener.accepting = true;
+            let mut sock_table = self.socket_table.write().unwrap();
+            for pd_conn in listener.pending_connections.drain(..) {
                 let sid = self.sid_assigner.assign_sid();
-                listener.pending_connections.drain(..).for_each(|pd_conn| {
-                    pd_conn.start(&mut sock_table, sid);
-                });
+                let sock = pd_conn.start(&mut sock_table, sid);
+                listener.handshaking.push(Arc::clone(&sock.lock().unwrap().state));
             }
-            None => (), // Listener was closed in the before this function got c
         }
     }
-    pub fn accept1(&mut self, port: u16) -> Option<Receiver<Arc<Mutex<ConnectionSocket>>>> {
+    /// Starts the next pending connection on <port> and hands it over through the returned receiver, or hands over the next
+    /// one to come in if none are pending - unless the listener is nonblocking, in which case that fails with WouldBlock
+    pub fn accept1(&mut self, port: u16) -> Result<Receiver<Arc<Mutex<ConnectionSocket>>>> {
         let listener_table = &mut self.listener_table;
-        match listener_table.get_mut(&port) {
-            Some(listener) => {
-                let (sock_send, sock_recv) = channel::<Arc<Mutex<ConnectionSocket>>>();
-                if !listener.pending_connections.is_empty() {
-                    //Just take the first pending connection
-                    let pd_conn = listener.pending_connections.remove(0);
-                    let mut sock_table = self.socket_table.write().unwrap();
-                    let sid = self.sid_assigner.assign_sid();
-                    let sock = pd_conn.start(&mut sock_table, sid);
-                    sock_send
-                        .send(sock)
-                        .expect("Error sending arc of sock to receiver");
-                } else {
-                    //No pending connections, so just say we are open to them for now
-                    listener.accepting = true;
-                    listener.sock_send = Some(sock_send);
-                }
-                Some(sock_recv)
+        let listener = match listener_table.get_mut(&port) {
+            Some(listener) => listener,
+            None => {
+                // Listener was closed before this function got called
+                return Err(Error::new(ErrorKind::InvalidInput, "No listener on the input port"));
             }
-            None => None, // Listener was closed in the before this function got c
+        };
+        let (sock_send, sock_recv) = channel::<Arc<Mutex<ConnectionSocket>>>();
+        if let Some(pd_conn) = listener.next_pending() {
+            let mut sock_table = self.socket_table.write().unwrap();
+            let sid = self.sid_assigner.assign_sid();
+            let sock = pd_conn.start(&mut sock_table, sid);
+            listener.handshaking.push(Arc::clone(&sock.lock().unwrap().state));
+            sock_send
+                .send(sock)
+                .expect("Error sending arc of sock to receiver");
+        } else if listener.nonblocking {
+            return Err(Error::new(ErrorKind::WouldBlock, "No connection to accept yet"));
+        } else {
+            //No pending connections, so just say we are open to them for now
+            listener.accepting = true;
+            listener.sock_send = Some(sock_send);
+        }
+        Ok(sock_recv)
+    }
+    pub fn set_listener_nonblocking(&mut self, port: u16, nonblocking: bool) {
+        if let Some(listener) = self.listener_table.get_mut(&port) {
+            listener.nonblocking = nonblocking;
+        }
+    }
+    /// What the listener on <port> is ready for - a listener that's gone counts as hung up
+    pub fn listener_readiness(&self, port: u16) -> Readiness {
+        match self.listener_table.get(&port) {
+            Some(listener) => Readiness {
+                acceptable: !listener.pending_connections.is_empty(),
+                ..Readiness::default()
+            },
+            None => Readiness {
+                hung_up: true,
+                ..Readiness::default()
+            },
+        }
+    }
+    pub fn watch_listener(&self, port: u16, key: u64, waker: &Waker) {
+        if let Some(listener) = self.listener_table.get(&port) {
+            listener.watchers.watch(key, waker);
+        }
+    }
+    pub fn unwatch_listener(&self, port: u16, key: u64) {
+        if let Some(listener) = self.listener_table.get(&port) {
+            listener.watchers.unwatch(key);
         }
     }
     /// Upon receiving a 'listen' command from the REPL, creates a new listener socket and adds it to the listener table
@@ -101,31 +161,109 @@ impl SocketManager {
     ///
     /// NOTE: The socket table is locked during this operation
     fn listener_recv(&mut self, port: u16, ip_head: Ipv4Header, tcp_pack: TcpPacket) {
-        //Check that the packet is a SYN packet and drop if it isn't
-        if !has_only_flags(&tcp_pack.header, SYN) {
-            return println!("Listener socket received non SYN packet for some reason");
+        let src_addr = TcpAddress::new(
+            Ipv4Addr::from(ip_head.destination),
+            tcp_pack.header.destination_port,
+        );
+        let dst_addr = TcpAddress::new(Ipv4Addr::from(ip_head.source), tcp_pack.header.source_port);
+        if self.has_connection(&src_addr, &dst_addr) {
+            return; //Duplicate that got here before its connection made it into the socket table - the connection deals with the rest
         }
+        let local_mss = self.local_mss(&dst_addr);
         //Find data about appropriate listener socket in the listener table
         let listener = self
             .listener_table
             .get_mut(&port)
             .expect("Herm, listener table not synced up with socket table");
+        if listener
+            .pending_connections
+            .iter()
+            .any(|pd_conn| pd_conn.sock.dst_addr == dst_addr)
+        {
+            return; //Retransmission - the pending connection answers it once it's accepted
+        }
+        if !has_only_flags(&tcp_pack.header, SYN) {
+            //Could be the last ACK of a handshake we answered with a SYN cookie
+            return self.cookie_recv(port, src_addr, dst_addr, local_mss, ip_head, tcp_pack);
+        }
+        if listener.half_open(self.tcp_config.connect_timeout) >= listener.backlog {
+            //No room - answer without keeping any state, and only set the connection up if the ACK comes back
+            let peer_isn = SeqNum::new(tcp_pack.header.sequence_number);
+            let peer_mss = TcpOptions::parse(&tcp_pack.header).mss.unwrap_or(DEFAULT_MSS);
+            let mss = cmp::min(peer_mss, local_mss);
+            //Too small an MSS for a cookie to hold gets dropped - our partner will retransmit
+            if let Some(cookie) = self.isn_generator.cookie(&src_addr, &dst_addr, peer_isn, mss) {
+                if let Some(pbasis) = syn_cookie_reply(&ip_head, &tcp_pack, cookie, mss) {
+                    let _ = self.ip_sender.send(pbasis); //Nothing to be done if IP is gone
+                }
+            }
+            return;
+        }
         //Construct connection socket and pending connection for incoming client
-        let src_addr = TcpAddress::new(
-            Ipv4Addr::from(ip_head.destination),
-            tcp_pack.header.destination_port,
-        );
-        let dst_addr = TcpAddress::new(Ipv4Addr::from(ip_head.source), tcp_pack.header.source_port);
+        let init_seq = self.isn_generator.generate(&src_addr, &dst_addr);
+        let conn_sock = self.new_conn_sock(src_addr, dst_addr, init_seq, local_mss);
+        self.hand_to_listener(port, conn_sock, tcp_pack, ip_head);
+    }
+    /// Sets up the connection for an ACK carrying back one of our SYN cookies, or answers it with a reset if it doesn't
+    fn cookie_recv(
+        &mut self,
+        port: u16,
+        src_addr: TcpAddress,
+        dst_addr: TcpAddress,
+        local_mss: u16,
+        ip_head: Ipv4Header,
+        tcp_pack: TcpPacket,
+    ) {
+        let peer_isn = SeqNum::new(tcp_pack.header.sequence_number) - 1;
+        let cookie = SeqNum::new(tcp_pack.header.acknowledgment_number) - 1;
+        let mss = match self.isn_generator.check_cookie(&src_addr, &dst_addr, peer_isn, cookie) {
+            Some(mss) if is_cookie_ack(&tcp_pack.header) => mss,
+            _ => return self.send_reset(&ip_head, &tcp_pack),
+        };
+        let listener = self
+            .listener_table
+            .get_mut(&port)
+            .expect("Herm, listener table not synced up with socket table");
+        if listener.completed() >= listener.backlog {
+            return; //No room - our partner will retransmit
+        }
+        let mut conn_sock = self.new_conn_sock(src_addr, dst_addr, cookie, local_mss);
+        conn_sock.accept_cookie(&tcp_pack.header, mss);
+        self.hand_to_listener(port, conn_sock, tcp_pack, ip_head);
+    }
+    /// Builds a connection socket for an incoming connection
+    fn new_conn_sock(&self, src_addr: TcpAddress, dst_addr: TcpAddress, init_seq: SeqNum, local_mss: u16) -> ConnectionSocket {
         let state = Arc::new(RwLock::new(TcpState::Initialized)); //Always start in Initialize state when spawned by listener socket
-        let ip_send = self.ip_sender.clone();
-        let closed_send = self.closed_sender.clone();
-        let conn_sock = ConnectionSocket::new(
+        ConnectionSocket::new(
             state,
-            src_addr.clone(),
-            dst_addr.clone(),
-            closed_send,
-            ip_send,
-        );
+            src_addr,
+            dst_addr,
+            init_seq,
+            Arc::clone(&self.closed_sender),
+            Arc::clone(&self.ip_sender),
+            local_mss,
+            self.tcp_config.clone(),
+        )
+    }
+    /// Whether the socket table already holds a connection between the input addresses
+    fn has_connection(&self, src_addr: &TcpAddress, dst_addr: &TcpAddress) -> bool {
+        self.socket_table.read().unwrap().connection(src_addr, dst_addr).is_some()
+    }
+    /// MSS to advertise to a partner at the input address
+    fn local_mss(&self, dst_addr: &TcpAddress) -> u16 {
+        mss_for_mtu(egress_mtu(
+            &self.interface_reps.read().unwrap(),
+            &self.forwarding_table.read().unwrap(),
+            &dst_addr.ip,
+        ))
+    }
+    /// Starts the connection socket right away if the listener is accepting, passing it the packet that brought it about,
+    /// or stashes it for later if it isn't
+    fn hand_to_listener(&mut self, port: u16, conn_sock: ConnectionSocket, tcp_pack: TcpPacket, ip_head: Ipv4Header) {
+        let listener = self
+            .listener_table
+            .get_mut(&port)
+            .expect("Herm, listener table not synced up with socket table");
         let pending_conn = PendingConn::new(conn_sock);
         //Decide whether to immediately start connection or stash it for later depending on whether the listener is accepting
         match listener.accepting {
@@ -135,8 +273,9 @@ impl SocketManager {
                     let sid = self.sid_assigner.assign_sid();
                     pending_conn.start(&mut sock_table, sid)
                 };
+                listener.handshaking.push(Arc::clone(&sock.lock().unwrap().state));
                 let sock_clone = Arc::clone(&sock); //potentially needed later
-                ConnectionSocket::handle_packet(sock, tcp_pack, ip_head); //Sends SYN + ACK message
+                ConnectionSocket::handle_packet(sock, tcp_pack, ip_head); //Sends SYN + ACK message, or takes in data that came with a cookie
                 if let Some(sock_send) = &listener.sock_send {
                     //Accept1 was called earlier
                     sock_send
@@ -146,11 +285,119 @@ impl SocketManager {
                     listener.sock_send = None;
                 }
             }
-            false => listener.pending_connections.push(pending_conn),
+            false => {
+                listener.pending_connections.push(pending_conn);
+                listener.watchers.wake_all();
+            }
         }
     }
     pub fn listener_close(&mut self, listen_ent: ListenEntry) {
         let listen_port = listen_ent.port;
-        self.listener_table.remove(&listen_port);
+        if let Some(listener) = self.listener_table.remove(&listen_port) {
+            listener.watchers.wake_all(); //Lets pollers see it hung up
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTEN_PORT: u16 = 80;
+    const LOCAL_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const PEER_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    /// A socket manager on 10.0.0.1 with no routes, along with the packets it sends and the socket IDs it reports as closed
+    fn manager() -> (SocketManager, Receiver<PacketBasis>, Receiver<SocketId>) {
+        let (ip_sender, ip_recver) = channel();
+        let (closed_sender, closed_recver) = channel();
+        let sock_man = SocketManager::new(
+            Arc::new(RwLock::new(InterfaceTable::new())),
+            Arc::new(RwLock::new(ForwardingTable::new())),
+            Arc::new(RwLock::new(SocketTable::new())),
+            Arc::new(closed_sender),
+            Arc::new(ip_sender),
+            Arc::new(SidAssigner::new()),
+            Arc::new(IsnGenerator::new(Some(7))),
+            TcpConfig::default(),
+        );
+        (sock_man, ip_recver, closed_recver)
+    }
+
+    /// A segment to our listener from 10.0.0.2:<peer_port>
+    fn from_peer(peer_port: u16, seq_num: u32, ack_num: Option<u32>, syn: bool, payload: &[u8]) -> Packet {
+        let mut head = TcpHeader::new(peer_port, LISTEN_PORT, seq_num, u16::MAX);
+        head.syn = syn;
+        if let Some(ack_num) = ack_num {
+            head.ack = true;
+            head.acknowledgment_number = ack_num;
+        }
+        head.checksum = head
+            .calc_checksum_ipv4_raw(PEER_IP.octets(), LOCAL_IP.octets(), payload)
+            .unwrap();
+        let header = Ipv4Header {
+            source: PEER_IP.octets(),
+            destination: LOCAL_IP.octets(),
+            protocol: IpNumber::TCP,
+            ..Default::default()
+        };
+        Packet {
+            header,
+            data: serialize_tcp(TcpPacket::new(head, payload.to_vec())),
+        }
+    }
+
+    /// Everything sent to 10.0.0.2:<peer_port> so far
+    fn sent_to(ip_recver: &Receiver<PacketBasis>, peer_port: u16) -> Vec<TcpPacket> {
+        ip_recver
+            .try_iter()
+            .map(|pbasis| deserialize_tcp(pbasis.msg).unwrap())
+            .filter(|tpack| tpack.header.destination_port == peer_port)
+            .collect()
+    }
+
+    #[test]
+    fn test_syn_cookies_past_backlog() {
+        let (mut sock_man, ip_recver, _closed) = manager();
+        sock_man.listen(LISTEN_PORT, 2).unwrap();
+        sock_man.accept(LISTEN_PORT);
+        // Two handshakes nobody finishes fill the backlog
+        for peer_port in [1000, 1001] {
+            sock_man.handle_incoming(from_peer(peer_port, 100, None, true, &[]), LISTEN_PORT);
+            let syn_ack = &sent_to(&ip_recver, peer_port)[0].header;
+            assert!(syn_ack.syn && syn_ack.ack);
+        }
+        let expiry = sock_man.tcp_config.connect_timeout;
+        assert_eq!(sock_man.listener_table.get_mut(&LISTEN_PORT).unwrap().half_open(expiry), 2);
+        // The next SYN gets a cookie, and nothing is kept for it
+        sock_man.handle_incoming(from_peer(1002, 500, None, true, &[]), LISTEN_PORT);
+        let syn_ack = sent_to(&ip_recver, 1002).remove(0).header;
+        assert!(syn_ack.syn && syn_ack.ack);
+        assert_eq!(syn_ack.acknowledgment_number, 501);
+        let options = TcpOptions::parse(&syn_ack);
+        assert_eq!(options.mss, Some(DEFAULT_MSS)); //No MSS in the SYN
+        assert!(!options.sack_permitted && options.timestamp.is_none() && options.window_scale.is_none());
+        assert_eq!(sock_man.listener_table.get_mut(&LISTEN_PORT).unwrap().half_open(expiry), 2);
+        let (local_addr, peer_addr) = (TcpAddress::new(LOCAL_IP, LISTEN_PORT), TcpAddress::new(PEER_IP, 1002));
+        assert!(!sock_man.has_connection(&local_addr, &peer_addr));
+        // The ACK bringing it back sets up the connection, and the data it carries gets through
+        let cookie_ack = from_peer(1002, 501, Some(syn_ack.sequence_number.wrapping_add(1)), false, b"hello");
+        sock_man.handle_incoming(cookie_ack, LISTEN_PORT);
+        let sock = {
+            let socket_table = sock_man.socket_table.read().unwrap();
+            let sid = socket_table.connection(&local_addr, &peer_addr).unwrap();
+            match &socket_table[&sid] {
+                SocketEntry::Connection(ent) => Arc::clone(&ent.sock),
+                SocketEntry::Listener(_) => panic!("Expected a connection"),
+            }
+        };
+        assert!(matches!(*sock.lock().unwrap().state.read().unwrap(), TcpState::Established));
+        assert_eq!(ConnectionSocket::receive(sock, 5).unwrap(), b"hello");
+        // An ACK with a cookie we never handed out gets reset
+        sock_man.handle_incoming(from_peer(1003, 701, Some(12345), false, &[]), LISTEN_PORT);
+        let reset = sent_to(&ip_recver, 1003).remove(0).header;
+        assert!(reset.rst);
+        assert_eq!(reset.sequence_number, 12345);
+        assert!(!sock_man.has_connection(&local_addr, &TcpAddress::new(PEER_IP, 1003)));
     }
 }
@@ -1,11 +1,19 @@
+use crate::congestion::CcModel;
 use crate::conn_socket::ConnectionSocket;
+use crate::poll::Watchers;
 use crate::prelude::*;
 use crate::tcp_utils::*;
+use std::ops::{Deref, RangeInclusive};
+use std::str::FromStr;
 
 pub type SocketId = u16;
-pub type SocketTable = HashMap<SocketId, SocketEntry>;
 pub type ListenerTable = HashMap<u16, ListenerEntry>;
 
+/// How many connections a listener holds on to for accepting, unless told otherwise
+pub const DEFAULT_BACKLOG: usize = 128;
+/// Ports connect() picks its local port from (RFC 6335 6)
+pub const EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+
 pub struct SidAssigner {
     next_sid: AtomicU16,
 }
@@ -22,9 +30,90 @@ impl SidAssigner {
         }
     }
     pub fn assign_sid(&self) -> SocketId {
-        let sid = self.next_sid.load(Ordering::SeqCst);
-        self.next_sid.store(sid + 1, Ordering::SeqCst);
-        sid
+        self.next_sid.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+/// Every open socket by ID, with connections also findable by their addresses and listeners by their port
+#[derive(Debug, Default)]
+pub struct SocketTable {
+    entries: HashMap<SocketId, SocketEntry>,
+    connections: HashMap<(TcpAddress, TcpAddress), SocketId>, //Keyed on (src_addr, dst_addr)
+    listeners: HashMap<u16, SocketId>,
+    local_ports: HashMap<u16, usize>, //Sockets using each local port - accepted connections share their listener's
+}
+
+impl SocketTable {
+    pub fn new() -> SocketTable {
+        SocketTable::default()
+    }
+    pub fn insert(&mut self, sid: SocketId, ent: SocketEntry) -> Option<SocketEntry> {
+        let old = self.remove(&sid);
+        let port = match &ent {
+            SocketEntry::Connection(conn) => {
+                self.connections.insert((conn.src_addr.clone(), conn.dst_addr.clone()), sid);
+                conn.src_addr.port
+            }
+            SocketEntry::Listener(listener) => {
+                self.listeners.insert(listener.port, sid);
+                listener.port
+            }
+        };
+        *self.local_ports.entry(port).or_insert(0) += 1;
+        self.entries.insert(sid, ent);
+        old
+    }
+    pub fn remove(&mut self, sid: &SocketId) -> Option<SocketEntry> {
+        let ent = self.entries.remove(sid)?;
+        self.unindex(&ent);
+        Some(ent)
+    }
+    /// ID of the connection between the input addresses, if there is one
+    pub fn connection(&self, src_addr: &TcpAddress, dst_addr: &TcpAddress) -> Option<SocketId> {
+        self.connections.get(&(src_addr.clone(), dst_addr.clone())).copied()
+    }
+    /// ID of the listener on the input port, if there is one
+    pub fn listener(&self, port: u16) -> Option<SocketId> {
+        self.listeners.get(&port).copied()
+    }
+    /// Whether any connection or listener is using the input local port
+    pub fn port_in_use(&self, port: u16) -> bool {
+        self.local_ports.contains_key(&port)
+    }
+    fn unindex(&mut self, ent: &SocketEntry) {
+        let port = match ent {
+            SocketEntry::Connection(conn) => {
+                self.connections.remove(&(conn.src_addr.clone(), conn.dst_addr.clone()));
+                conn.src_addr.port
+            }
+            SocketEntry::Listener(listener) => {
+                self.listeners.remove(&listener.port);
+                listener.port
+            }
+        };
+        if let Some(count) = self.local_ports.get_mut(&port) {
+            *count -= 1;
+            if *count == 0 {
+                self.local_ports.remove(&port);
+            }
+        }
+    }
+}
+
+impl Deref for SocketTable {
+    type Target = HashMap<SocketId, SocketEntry>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl<'a> IntoIterator for &'a SocketTable {
+    type Item = (&'a SocketId, &'a SocketEntry);
+    type IntoIter = std::collections::hash_map::Iter<'a, SocketId, SocketEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
     }
 }
 
@@ -43,6 +132,48 @@ pub struct ConnectionEntry {
     pub sock: Arc<Mutex<ConnectionSocket>>,
 }
 
+/// Which directions of a connection shutdown() shuts down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum How {
+    Read,  //Drop anything our partner sends from here on
+    Write, //Send our FIN once everything buffered is out - we can still receive until our partner's FIN
+    Both,
+}
+
+impl FromStr for How {
+    type Err = Error;
+
+    fn from_str(how: &str) -> Result<Self> {
+        match how {
+            "read" | "r" => Ok(How::Read),
+            "write" | "w" => Ok(How::Write),
+            "both" | "rw" => Ok(How::Both),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Expected read, write or both, got \"{how}\""),
+            )),
+        }
+    }
+}
+
+/// Snapshot of a connection socket's transmission state, as shown by the ss command
+#[derive(Debug, Clone)]
+pub struct SocketStats {
+    pub state: TcpState,
+    pub cc_name: &'static str,
+    pub cwnd: usize,
+    pub ssthresh: usize,
+    pub in_recovery: bool,
+    pub srtt: Option<Duration>,
+    pub rto: Duration,
+    pub rem_window: u32,
+    pub in_flight: usize,
+    pub mss: usize,
+    pub model: Option<CcModel>, // Only for model-based congestion control (BBR)
+    pub send_buffer: usize,
+    pub recv_buffer: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct ListenEntry {
     pub port: u16,
@@ -62,31 +193,74 @@ pub struct ListenerEntry {
     pub accepting: bool,
     pub pending_connections: Vec<PendingConn>,
     pub sock_send: Option<Sender<Arc<Mutex<ConnectionSocket>>>>, //This is so cursed wtf
+    pub backlog: usize, //Most half open connections held on to before SYN cookies take over, and most finished ones waiting to be accepted
+    pub handshaking: Vec<Arc<RwLock<TcpState>>>, //States of connections that were started before their handshake was done
+    pub nonblocking: bool, //accept1() fails with WouldBlock instead of waiting when nothing is pending
+    pub watchers: Watchers, //Woken whenever a connection gets stashed for accepting, or the listener closes
 }
 impl Default for ListenerEntry {
     fn default() -> Self {
-        Self::new()
+        Self::new(DEFAULT_BACKLOG)
     }
 }
 
 impl ListenerEntry {
-    pub fn new() -> ListenerEntry {
+    pub fn new(backlog: usize) -> ListenerEntry {
         ListenerEntry {
             accepting: false,
             pending_connections: Vec::new(),
             sock_send: None, //Initially None - will become Some(<sender>) when accept1() gets called
+            backlog,
+            handshaking: Vec::new(),
+            nonblocking: false,
+            watchers: Watchers::new(),
         }
     }
+    /// Number of connections whose handshake isn't done yet
+    /// Pending ones nobody has asked about for longer than the input expiry are dropped - whoever sent the SYN has given up
+    pub fn half_open(&mut self, expiry: Duration) -> usize {
+        //Once a handshake is over (one way or another), it's no longer the listener's business
+        self.handshaking.retain(|state| {
+            matches!(*state.read().unwrap(), TcpState::Initialized | TcpState::SynRecvd)
+        });
+        self.pending_connections
+            .retain(|pd_conn| pd_conn.established() || pd_conn.since.elapsed() < expiry);
+        self.handshaking.len() + self.pending_connections.iter().filter(|pd_conn| !pd_conn.established()).count()
+    }
+    /// Number of connections that finished their handshake and are waiting to be accepted
+    pub fn completed(&self) -> usize {
+        self.pending_connections.iter().filter(|pd_conn| pd_conn.established()).count()
+    }
+    /// Takes the pending connection accept1() should hand out next - finished handshakes go first
+    pub fn next_pending(&mut self) -> Option<PendingConn> {
+        if self.pending_connections.is_empty() {
+            return None;
+        }
+        let pos = self
+            .pending_connections
+            .iter()
+            .position(|pd_conn| pd_conn.established())
+            .unwrap_or(0);
+        Some(self.pending_connections.remove(pos))
+    }
 }
 
 #[derive(Debug)]
 pub struct PendingConn {
     pub sock: ConnectionSocket, //Connection socket that has not been run yet - run it when the associated listener socket starts accepting connections
+    pub since: Instant,
 }
 
 impl PendingConn {
     pub fn new(sock: ConnectionSocket) -> PendingConn {
-        PendingConn { sock }
+        PendingConn {
+            sock,
+            since: Instant::now(),
+        }
+    }
+    /// Whether the connection came in with a SYN cookie, so its handshake is already done
+    pub fn established(&self) -> bool {
+        matches!(*self.sock.state.read().unwrap(), TcpState::Established)
     }
     /// Takes in a pending connection and adds it to the SocketTable before returning a pointer to that socket
     pub fn start(
@@ -107,6 +281,16 @@ impl PendingConn {
         thread::spawn(move || {
             ConnectionSocket::time_check(time_clone);
         });
+        // Spawn thread for sending buffered data
+        let send_clone = Arc::clone(&sock);
+        thread::spawn(move || {
+            ConnectionSocket::send_onwards(send_clone);
+        });
+        // Spawn thread for delayed acknowledgements
+        let ack_clone = Arc::clone(&sock);
+        thread::spawn(move || {
+            ConnectionSocket::delayed_acker(ack_clone);
+        });
         let ent = ConnectionEntry {
             src_addr,
             dst_addr,
@@ -0,0 +1,189 @@
+use crate::congestion::CongestionAlgorithm;
+use crate::prelude::*;
+use crate::retransmission::{INITIAL_RTO, MAX_RTO, MIN_RTO, R1};
+use crate::tcp_utils::MAX_WINDOW_SHIFT;
+
+const TIME_WAIT: Duration = Duration::from_millis(2 * MAX_RTO); //Stands in for 2 MSL
+const ZWP_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_BUFFER_SIZE: usize = 65535;
+const MAX_BUFFER_SIZE: usize = (u16::MAX as usize) << MAX_WINDOW_SHIFT; //Largest window that can be advertised (RFC 7323 2.3)
+
+/// Node wide TCP settings, built from the tcp directives of the lnx file
+/// Every socket on the node starts out with these settings
+#[derive(Debug, Clone)]
+pub struct TcpConfig {
+    pub congestion_control: CongestionAlgorithm,
+    pub rto_initial: Duration, //RTO before the first RTT measurement, kept between rto_min and rto_max
+    pub rto_min: Duration,
+    pub rto_max: Duration,
+    pub ack_delay: Duration, //How long an acknowledgement may be held back - zero ACKs every segment right away
+    pub connect_timeout: Duration, //How long connect() keeps retransmitting its SYN before giving up
+    pub user_timeout: Duration, //How long sent data may go unacknowledged before the connection is aborted (R2)
+    pub warn_retransmissions: u32, //Retransmissions of a segment before we warn that the connection might be failing (R1)
+    pub max_retransmissions: Option<u32>, //Retransmissions of a segment before the connection is aborted - None leaves it to the user timeout
+    pub time_wait: Duration, //How long a connection sits in TIME_WAIT before it's gone for good
+    pub zwp_interval: Duration, //Time between zero window probes
+    pub keepalive: bool, //Whether idle connections get probed - off by default (RFC 1122 4.2.3.6)
+    pub keepalive_params: Keepalive,
+    pub linger: Option<Duration>, //How long close() waits on unacknowledged data before resetting the connection - None waits as long as it takes
+    pub isn_seed: Option<u64>, //Makes initial sequence numbers the same on every run - None picks a random key
+    pub send_buffer: usize, //Bytes a socket buffers for sending, counting what's sent but unacknowledged
+    pub recv_buffer: usize, //Bytes a socket buffers for receiving, which bounds the window it advertises
+}
+
+/// How an idle connection gets probed to check that our partner is still there
+#[derive(Debug, Clone, Copy)]
+pub struct Keepalive {
+    pub idle: Duration,     //How long the connection has to be quiet before the first probe
+    pub interval: Duration, //Time between unanswered probes
+    pub probes: u32,        //Unanswered probes before the connection is aborted
+}
+
+impl Default for Keepalive {
+    fn default() -> Keepalive {
+        Keepalive {
+            idle: Duration::from_secs(2 * 60 * 60), //RFC 1122 4.2.3.6 - no less than two hours
+            interval: Duration::from_secs(75),
+            probes: 9,
+        }
+    }
+}
+
+impl Default for TcpConfig {
+    fn default() -> TcpConfig {
+        TcpConfig {
+            congestion_control: CongestionAlgorithm::default(),
+            rto_initial: Duration::from_millis(INITIAL_RTO),
+            rto_min: Duration::from_millis(MIN_RTO),
+            rto_max: Duration::from_millis(MAX_RTO),
+            ack_delay: Duration::from_millis(200),
+            connect_timeout: Duration::from_secs(30),
+            user_timeout: Duration::from_secs(100), //RFC 9293 3.8.3 - R2 should be at least 100 seconds
+            warn_retransmissions: R1,
+            max_retransmissions: None,
+            time_wait: TIME_WAIT,
+            zwp_interval: ZWP_INTERVAL,
+            keepalive: false,
+            keepalive_params: Keepalive::default(),
+            linger: None,
+            isn_seed: None,
+            send_buffer: DEFAULT_BUFFER_SIZE,
+            recv_buffer: DEFAULT_BUFFER_SIZE,
+        }
+    }
+}
+
+impl TryFrom<&IPConfig> for TcpConfig {
+    type Error = Error;
+
+    fn try_from(config_info: &IPConfig) -> Result<TcpConfig> {
+        let mut tcp_config = TcpConfig::default();
+        if let Some(name) = &config_info.tcp_congestion_control {
+            tcp_config.congestion_control = name.parse()?;
+        }
+        //Every timer but ack-delay and linger, where zero means something, has to be above zero
+        let timer = |key: &str, millis: Option<u64>, default: Duration| match millis {
+            Some(millis) => check_timer(&format!("tcp {key}"), Duration::from_millis(millis)),
+            None => Ok(default),
+        };
+        tcp_config.rto_initial = timer("rto-initial", config_info.tcp_rto_initial, tcp_config.rto_initial)?;
+        tcp_config.rto_min = timer("rto-min", config_info.tcp_rto_min, tcp_config.rto_min)?;
+        tcp_config.rto_max = timer("rto-max", config_info.tcp_rto_max, tcp_config.rto_max)?;
+        tcp_config.connect_timeout = timer("connect-timeout", config_info.tcp_connect_timeout, tcp_config.connect_timeout)?;
+        tcp_config.user_timeout = timer("user-timeout", config_info.tcp_user_timeout, tcp_config.user_timeout)?;
+        tcp_config.time_wait = timer("time-wait", config_info.tcp_time_wait, tcp_config.time_wait)?;
+        tcp_config.zwp_interval = timer("zwp-interval", config_info.tcp_zwp_interval, tcp_config.zwp_interval)?;
+        //Shorter idle times than RFC 1122's two hours are allowed, as long as keepalive stays off by default
+        let keepalive_params = &mut tcp_config.keepalive_params;
+        keepalive_params.idle = timer("keepalive-idle", config_info.tcp_keepalive_idle, keepalive_params.idle)?;
+        keepalive_params.interval = timer("keepalive-interval", config_info.tcp_keepalive_interval, keepalive_params.interval)?;
+        if tcp_config.rto_min > tcp_config.rto_max {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "tcp rto-min must be no more than rto-max",
+            ));
+        }
+        if let Some(ack_delay) = config_info.tcp_ack_delay {
+            //RFC 9293 3.8.6.3 - the delay must be less than 0.5 seconds
+            if ack_delay >= 500 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "tcp ack-delay must be under 500 milliseconds",
+                ));
+            }
+            tcp_config.ack_delay = Duration::from_millis(ack_delay);
+        }
+        if let Some(warn_retransmissions) = config_info.tcp_warn_retransmissions {
+            tcp_config.warn_retransmissions = warn_retransmissions;
+        }
+        tcp_config.max_retransmissions = config_info.tcp_max_retransmissions;
+        if let Some(keepalive) = config_info.tcp_keepalive {
+            tcp_config.keepalive = keepalive;
+        }
+        if let Some(probes) = config_info.tcp_keepalive_probes {
+            tcp_config.keepalive_params.probes = probes;
+        }
+        tcp_config.linger = config_info.tcp_linger.map(Duration::from_millis);
+        tcp_config.isn_seed = config_info.tcp_isn_seed;
+        if let Some(send_buffer) = config_info.tcp_send_buffer {
+            tcp_config.send_buffer = check_buffer_size(send_buffer)?;
+        }
+        if let Some(recv_buffer) = config_info.tcp_recv_buffer {
+            tcp_config.recv_buffer = check_buffer_size(recv_buffer)?;
+        }
+        Ok(tcp_config)
+    }
+}
+
+/// Passes a send or receive buffer size through if a socket can use it
+pub fn check_buffer_size(size: usize) -> Result<usize> {
+    if size == 0 || size > MAX_BUFFER_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("TCP buffer sizes must be between 1 and {MAX_BUFFER_SIZE} bytes"),
+        ));
+    }
+    Ok(size)
+}
+
+/// Passes a timer through if it's above zero - a zero timer would have a socket spin on it, or give up right away
+pub fn check_timer(name: &str, timer: Duration) -> Result<Duration> {
+    if timer.is_zero() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{name} must be above zero")));
+    }
+    Ok(timer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(lnx: &str) -> Result<TcpConfig> {
+        let mut config_info = IPConfig::default();
+        config_info.parse(lnx).unwrap();
+        TcpConfig::try_from(&config_info)
+    }
+
+    #[test]
+    fn test_zero_timers_rejected() {
+        let timers = [
+            "rto-initial",
+            "rto-min",
+            "rto-max",
+            "connect-timeout",
+            "user-timeout",
+            "time-wait",
+            "zwp-interval",
+            "keepalive-idle",
+            "keepalive-interval",
+        ];
+        for key in timers {
+            let err = config(&format!("tcp {key} 0")).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{key}");
+            assert!(config(&format!("tcp {key} 1000")).is_ok(), "{key}");
+        }
+        // Zero means something for these
+        let tcp_config = config("tcp ack-delay 0\ntcp linger 0").unwrap();
+        assert_eq!(tcp_config.linger, Some(Duration::ZERO));
+    }
+}
@@ -1,4 +1,6 @@
 use crate::prelude::*;
+use crate::seq_num::SeqNum;
+use crate::utils::PacketBasis;
 
 #[derive(Debug, Clone)]
 pub enum TcpState {
@@ -17,7 +19,7 @@ pub enum TcpState {
     Closed,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TcpAddress {
     pub ip: Ipv4Addr,
     pub port: u16,
@@ -68,6 +70,106 @@ pub fn deserialize_tcp(raw_packet: Vec<u8>) -> Result<TcpPacket> {
     }
 }
 
+/// Builds the reset answering a segment that no connection is there to take (RFC 9293 3.10.7.1)
+/// Resets never get answered, so there's nothing to build for those
+pub fn reset_reply(ip_head: &Ipv4Header, tpack: &TcpPacket) -> Option<PacketBasis> {
+    let head = &tpack.header;
+    if head.rst {
+        return None;
+    }
+    let mut reply = TcpHeader::new(head.destination_port, head.source_port, 0, 0);
+    reply.rst = true;
+    if head.ack {
+        //Looks like it's meant for an old connection of ours - use the sequence number it expects
+        reply.sequence_number = head.acknowledgment_number;
+    } else {
+        let seg_len = tpack.payload.len() as u32 + head.syn as u32 + head.fin as u32;
+        reply.ack = true;
+        reply.acknowledgment_number = head.sequence_number.wrapping_add(seg_len);
+    }
+    reply.checksum = reply
+        .calc_checksum_ipv4_raw(ip_head.destination, ip_head.source, &[])
+        .ok()?;
+    Some(PacketBasis {
+        dst_ip: Ipv4Addr::from(ip_head.source),
+        prot_num: 6,
+        msg: serialize_tcp(TcpPacket::new(reply, Vec::new())),
+    })
+}
+
+/// Builds the SYN-ACK answering a SYN with a SYN cookie in place of our ISN - no connection gets set up for it
+pub fn syn_cookie_reply(ip_head: &Ipv4Header, tpack: &TcpPacket, cookie: SeqNum, mss: u16) -> Option<PacketBasis> {
+    let head = &tpack.header;
+    let mut reply = TcpHeader::new(head.destination_port, head.source_port, cookie.value(), u16::MAX);
+    reply.syn = true;
+    reply.ack = true;
+    reply.acknowledgment_number = head.sequence_number.wrapping_add(1);
+    reply
+        .set_options(&[TcpOptionElement::MaximumSegmentSize(mss)])
+        .ok()?;
+    reply.checksum = reply
+        .calc_checksum_ipv4_raw(ip_head.destination, ip_head.source, &[])
+        .ok()?;
+    Some(PacketBasis {
+        dst_ip: Ipv4Addr::from(ip_head.source),
+        prot_num: 6,
+        msg: serialize_tcp(TcpPacket::new(reply, Vec::new())),
+    })
+}
+
+/// The TCP options we understand, parsed out of a header
+#[derive(Debug, Clone, Default)]
+pub struct TcpOptions {
+    pub sack_permitted: bool,
+    pub sack_blocks: Vec<(SeqNum, SeqNum)>, // (left edge, right edge) of each SACK block
+    pub window_scale: Option<u8>,
+    pub timestamp: Option<(u32, u32)>, // (TSval, TSecr)
+    pub mss: Option<u16>,
+}
+
+impl TcpOptions {
+    /// Parses the options of a TCP header, ignoring any that are malformed or unknown
+    pub fn parse(head: &TcpHeader) -> TcpOptions {
+        let mut options = TcpOptions::default();
+        for option in head.options_iterator().flatten() {
+            match option {
+                TcpOptionElement::SelectiveAcknowledgementPermitted => options.sack_permitted = true,
+                TcpOptionElement::MaximumSegmentSize(mss) => options.mss = Some(mss),
+                //Shifts past the largest allowed one get treated as it (RFC 7323 2.3)
+                TcpOptionElement::WindowScale(shift) => options.window_scale = Some(cmp::min(shift, MAX_WINDOW_SHIFT)),
+                TcpOptionElement::Timestamp(val, ecr) => options.timestamp = Some((val, ecr)),
+                TcpOptionElement::SelectiveAcknowledgement(first, rest) => {
+                    let blocks = std::iter::once(first).chain(rest.into_iter().flatten());
+                    options.sack_blocks.extend(blocks.map(|(left, right)| (left.into(), right.into())));
+                }
+                _ => {}
+            }
+        }
+        options
+    }
+}
+
+/// MSS to assume when our partner doesn't send the option (RFC 9293 3.7.1)
+pub const DEFAULT_MSS: u16 = 536;
+const IP_TCP_HEADERS_LEN: usize = 40; //Both headers without options
+
+/// MSS to advertise for an interface with the given MTU
+pub fn mss_for_mtu(mtu: usize) -> u16 {
+    cmp::min(mtu.saturating_sub(IP_TCP_HEADERS_LEN), u16::MAX as usize) as u16
+}
+
+/// Largest window scale shift allowed by RFC 7323 (2.3)
+pub const MAX_WINDOW_SHIFT: u8 = 14;
+
+/// Smallest window scale shift that lets a window of the input size fit in the 16 bit window field
+pub fn window_shift_for(window: usize) -> u8 {
+    let mut shift = 0;
+    while shift < MAX_WINDOW_SHIFT && (window >> shift) > u16::MAX as usize {
+        shift += 1;
+    }
+    shift
+}
+
 // TCP FLAGS
 pub const URG: u8 = 1;
 pub const ACK: u8 = 2;
@@ -102,6 +204,11 @@ pub fn has_flags(head: &TcpHeader, flags: u8) -> bool {
     (head_flags & flags) == flags
 }
 
+/// Whether a packet could be the last ACK of a handshake we answered with a SYN cookie - an ACK, maybe with data, and nothing more
+pub fn is_cookie_ack(head: &TcpHeader) -> bool {
+    has_only_flags(head, ACK) || has_only_flags(head, ACK | PSH)
+}
+
 /// Horrible terrible function to determine if a packet is SYN and ONLY SYN
 pub fn is_syn(head: &TcpHeader) -> bool {
     if head.ns | head.fin | head.rst | head.psh | head.ack | head.urg | head.ece | head.cwr {
@@ -128,3 +235,77 @@ pub enum SocketCmd {
     //More perhaps
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_shift_for() {
+        assert_eq!(window_shift_for(0), 0);
+        assert_eq!(window_shift_for(65535), 0);
+        assert_eq!(window_shift_for(65536), 1);
+        assert_eq!(window_shift_for(65535 << 3), 3);
+        assert_eq!(window_shift_for(65536 << 3), 4);
+        assert_eq!(window_shift_for(65535 << MAX_WINDOW_SHIFT), MAX_WINDOW_SHIFT);
+        // Windows too big to advertise get the largest shift there is
+        assert_eq!(window_shift_for(usize::MAX), MAX_WINDOW_SHIFT);
+    }
+
+    #[test]
+    fn test_mss_for_mtu() {
+        assert_eq!(mss_for_mtu(1500), 1460);
+        assert_eq!(mss_for_mtu(576), 536);
+        assert_eq!(mss_for_mtu(20), 0);
+        assert_eq!(mss_for_mtu(100_000), u16::MAX);
+    }
+
+    #[test]
+    fn test_parse_clamps_window_scale() {
+        for (sent, parsed) in [(7, 7), (14, 14), (15, 14), (255, 14)] {
+            let mut head = TcpHeader::new(1, 2, 0, 0);
+            head.syn = true;
+            head.set_options(&[TcpOptionElement::WindowScale(sent)]).unwrap();
+            assert_eq!(TcpOptions::parse(&head).window_scale, Some(parsed));
+        }
+    }
+
+    /// The reset we'd answer a segment from 10.0.0.2:2000 to 10.0.0.1:1000 with
+    fn reset_for(head: TcpHeader, payload: Vec<u8>) -> TcpHeader {
+        let ip_head = Ipv4Header {
+            source: [10, 0, 0, 2],
+            destination: [10, 0, 0, 1],
+            protocol: IpNumber::TCP,
+            ..Default::default()
+        };
+        let reply = reset_reply(&ip_head, &TcpPacket::new(head, payload)).unwrap();
+        assert_eq!(reply.dst_ip, Ipv4Addr::new(10, 0, 0, 2));
+        deserialize_tcp(reply.msg).unwrap().header
+    }
+
+    #[test]
+    fn test_reset_reply() {
+        // A SYN gets a reset acknowledging it, with no sequence number of its own
+        let mut syn = TcpHeader::new(2000, 1000, 700, 0);
+        syn.syn = true;
+        let reply = reset_for(syn, Vec::new());
+        assert!(reply.rst && reply.ack);
+        assert_eq!((reply.source_port, reply.destination_port), (1000, 2000));
+        assert_eq!((reply.sequence_number, reply.acknowledgment_number), (0, 701));
+        // So does data, across the wrap
+        let data = TcpHeader::new(2000, 1000, u32::MAX - 1, 0);
+        let reply = reset_for(data, vec![0; 10]);
+        assert_eq!(reply.acknowledgment_number, 8);
+        // A segment acknowledging something gets a reset carrying the sequence number it expects
+        let mut ack = TcpHeader::new(2000, 1000, 700, 0);
+        ack.ack = true;
+        ack.acknowledgment_number = 4242;
+        let reply = reset_for(ack, vec![0; 10]);
+        assert!(reply.rst && !reply.ack);
+        assert_eq!(reply.sequence_number, 4242);
+        // Resets never get answered
+        let mut rst = TcpHeader::new(2000, 1000, 700, 0);
+        rst.rst = true;
+        assert!(reset_reply(&Ipv4Header::default(), &TcpPacket::new(rst, Vec::new())).is_none());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::backends::{Backend, HostBackend};
+use crate::config::initialize;
+use crate::ip_handler::IpHandler;
+use crate::prelude::*;
+use crate::sockman_utils::{SocketId, DEFAULT_BACKLOG};
+use std::sync::OnceLock;
+
+/* Test networks:
+
+Tests that need a whole connection run two hosts on one little network, 10.0.0.1 and 10.0.0.2,
+their links carried over loopback UDP like any vnode's. Hosts never shut down, and their IP daemons
+keep polling for as long as they're up, so tests starting out with the same tcp directives share a
+pair instead of each leaving one more running. Tests sharing a pair listen on ports of their own,
+so they can run side by side.
+
+*/
+
+pub const HOST_A: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+pub const HOST_B: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+type HostPairs = HashMap<(Option<u16>, String), (HostBackend, HostBackend)>; //Keyed on (mtu, tcp_lines)
+
+static NEXT_LISTEN_PORT: AtomicU16 = AtomicU16::new(5000);
+
+/// A port no other test listens on
+pub fn listen_port() -> u16 {
+    NEXT_LISTEN_PORT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// A UDP port nothing on the loopback interface is using right now
+fn free_port() -> u16 {
+    let sock = UdpSocket::bind("127.0.0.1:0").expect("No free UDP ports");
+    sock.local_addr().unwrap().port()
+}
+
+fn host(ip: Ipv4Addr, port: u16, neighbor_ip: Ipv4Addr, neighbor_port: u16, mtu: Option<u16>, tcp_lines: &str) -> HostBackend {
+    let mtu = mtu.map_or(String::new(), |mtu| format!(" mtu {mtu}"));
+    let lnx = format!(
+        "interface if0 {ip}/24 127.0.0.1:{port}{mtu}\nneighbor {neighbor_ip} at 127.0.0.1:{neighbor_port} via if0\nrouting static\n{tcp_lines}\n"
+    );
+    let mut config_info = IPConfig::default();
+    config_info.parse(&lnx).expect("Bad test lnx");
+    let (backend, ip_recver) = initialize(config_info).expect("Couldn't start test host");
+    let Backend::Host(backend) = backend else {
+        unreachable!("Static routing makes hosts")
+    };
+    let ip_handler = IpHandler::new(Arc::clone(&backend.socket_table), Arc::clone(&backend.socket_manager));
+    thread::spawn(move || ip_handler.run(ip_recver));
+    backend
+}
+
+/// Two hosts linked to each other, HOST_A and HOST_B - tcp_lines are lnx tcp directives both start out with
+pub fn host_pair(tcp_lines: &str) -> (HostBackend, HostBackend) {
+    host_pair_with_mtu(None, tcp_lines)
+}
+
+/// Same as host_pair(), with both ends of the link set to the input MTU
+pub fn host_pair_with_mtu(mtu: Option<u16>, tcp_lines: &str) -> (HostBackend, HostBackend) {
+    static PAIRS: OnceLock<Mutex<HostPairs>> = OnceLock::new();
+    let mut pairs = PAIRS.get_or_init(Default::default).lock().unwrap();
+    let pair = pairs.entry((mtu, tcp_lines.to_string())).or_insert_with(|| {
+        let (port_a, port_b) = (free_port(), free_port());
+        (
+            host(HOST_A, port_a, HOST_B, port_b, mtu, tcp_lines),
+            host(HOST_B, port_b, HOST_A, port_a, mtu, tcp_lines),
+        )
+    });
+    pair.clone()
+}
+
+/// A connection from HOST_A to a listener on HOST_B, as (host_a, HOST_A's end, host_b, HOST_B's end)
+pub fn connected_pair(tcp_lines: &str) -> (HostBackend, SocketId, HostBackend, SocketId) {
+    let (host_a, host_b) = host_pair(tcp_lines);
+    let (sid_a, sid_b) = connect_hosts(&host_a, &host_b);
+    (host_a, sid_a, host_b, sid_b)
+}
+
+/// Connects HOST_A to a new listener on HOST_B, returning (HOST_A's end, HOST_B's end)
+pub fn connect_hosts(host_a: &HostBackend, host_b: &HostBackend) -> (SocketId, SocketId) {
+    let port = listen_port();
+    host_b.listen(port, DEFAULT_BACKLOG).unwrap();
+    let connector = host_a.clone();
+    let connecting = thread::spawn(move || connector.connect(HOST_B, port));
+    let sid_b = host_b.accept1(port).expect("Test listener went away");
+    let sid_a = connecting.join().unwrap().expect("Test connection failed");
+    (sid_a, sid_b)
+}
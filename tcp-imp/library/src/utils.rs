@@ -4,6 +4,7 @@ pub type ForwardingTable = HashMap<Ipv4Net, Route>;
 pub type InterfaceTable = HashMap<String, InterfaceRep>; //Is shared via Arc<RwLock<>>
 pub type InterfaceRecvers = HashMap<String, Receiver<Packet>>; //NEVER shared - only IPDaemon has this
 pub type RipNeighbors = HashMap<Ipv4Addr, Vec<Route>>;
+pub const DEFAULT_MTU: usize = 1500; //Used for interfaces that don't set an mtu in their lnx file
 //type SocketTable = ...?
 
 //Used as values of the forwarding table hashmap held by nodes
@@ -77,6 +78,7 @@ pub struct InterfaceRep {
     pub v_ip: Ipv4Addr,
     pub status: InterfaceStatus,         //Interface status
     pub neighbors: Vec<(Ipv4Addr, u16)>, //List of the interface's neighbors in (ipaddr, udpport) form
+    pub mtu: usize,                      //Largest IP packet the interface can send
     pub sender: Sender<InterCmd>, //Channel to send messages from associated interface (sends InterCmd and receives Packet)
 }
 
@@ -86,6 +88,7 @@ impl InterfaceRep {
         v_net: Ipv4Net,
         v_ip: Ipv4Addr,
         neighbors: Vec<(Ipv4Addr, u16)>,
+        mtu: usize,
         sender: Sender<InterCmd>,
     ) -> InterfaceRep {
         InterfaceRep {
@@ -94,6 +97,7 @@ impl InterfaceRep {
             v_ip,
             status: InterfaceStatus::Up, //Status always starts as Up
             neighbors,
+            mtu,
             sender,
         }
     }
@@ -103,6 +107,43 @@ impl InterfaceRep {
     }
 }
 
+/// Finds the interface a packet to dst_ip would leave through
+/// Ok(None) means the packet would stay on this node, Err that there's no route for it
+pub fn egress_interface<'a>(
+    interface_reps: &'a InterfaceTable,
+    forwarding_table: &ForwardingTable,
+    dst_ip: &Ipv4Addr,
+) -> Result<Option<&'a InterfaceRep>> {
+    let mut dst_ip = *dst_ip;
+    //Follow routes until one bottoms out at an interface - each hop matches a strictly shorter prefix, so this ends
+    for _ in 0..forwarding_table.len() {
+        let route = forwarding_table
+            .iter()
+            .filter(|(prefix, _)| prefix.contains(&dst_ip))
+            .max_by_key(|(prefix, _)| prefix.prefix_len())
+            .map(|(_, route)| route);
+        match route.map(|route| &route.next_hop) {
+            Some(ForwardingOption::Inter(name)) => return Ok(interface_reps.get(name)),
+            Some(ForwardingOption::Ip(ip)) => dst_ip = *ip,
+            Some(ForwardingOption::ToSelf) => return Ok(None),
+            None => break,
+        }
+    }
+    Err(Error::new(ErrorKind::HostUnreachable, "No route to host"))
+}
+
+/// Finds the MTU of the interface a packet to dst_ip would leave through, DEFAULT_MTU if it doesn't leave
+pub fn egress_mtu(
+    interface_reps: &InterfaceTable,
+    forwarding_table: &ForwardingTable,
+    dst_ip: &Ipv4Addr,
+) -> usize {
+    match egress_interface(interface_reps, forwarding_table, dst_ip) {
+        Ok(Some(inter_rep)) => inter_rep.mtu,
+        _ => DEFAULT_MTU,
+    }
+}
+
 //Used to indicate if an Interface is down or up
 #[derive(Debug)]
 pub enum InterfaceStatus {
@@ -0,0 +1,192 @@
+use crate::congestion::*;
+use crate::seq_num::SeqNum;
+use std::cmp;
+use std::time::Duration;
+
+/* TCP Vegas (Brakmo & Peterson, 1995):
+
+Vegas watches the RTT instead of waiting for loss. BaseRTT is the smallest RTT ever seen and
+stands in for the RTT of an empty path, the smallest RTT seen during the last round trip
+stands in for the current one.
+
+Once per round trip:
+expected = cwnd / BaseRTT
+actual = cwnd / RTT
+diff = (expected - actual) * BaseRTT (= segments we're keeping queued in the network)
+
+CONSTANTS (in segments):
+- ALPHA = 2, BETA = 4, GAMMA = 1
+
+SLOW START:
+- Grows like Reno, until diff > GAMMA -> ssthresh = cwnd, cwnd = min(cwnd, expected * RTT + 1)
+
+CONGESTION AVOIDANCE:
+- diff < ALPHA -> cwnd += 1 segment
+- diff > BETA -> cwnd -= 1 segment
+- Otherwise cwnd stays put
+A round without any valid RTT samples falls back to Reno's additive increase
+
+ON LOSS:
+Same as NewReno
+
+*/
+
+const ALPHA: f64 = 2.0;
+const BETA: f64 = 4.0;
+const GAMMA: f64 = 1.0;
+
+#[derive(Debug)]
+pub struct Vegas {
+    mss: usize,
+    cwnd: usize,
+    ssthresh: usize,
+    base_rtt: Option<Duration>,
+    round_min_rtt: Option<Duration>, // Smallest RTT sampled during the current round
+    round_end: Option<SeqNum>,       // The current round is over once this gets acknowledged
+    recover: Option<SeqNum>,         // Some(snd.nxt at time of loss) while in fast recovery
+}
+
+impl Vegas {
+    pub fn new(mss: usize) -> Vegas {
+        Vegas {
+            mss,
+            cwnd: initial_window(mss),
+            ssthresh: usize::MAX,
+            base_rtt: None,
+            round_min_rtt: None,
+            round_end: None,
+            recover: None,
+        }
+    }
+    /// Adjusts cwnd at the end of a round trip in which we got RTT samples
+    fn end_round(&mut self, base_rtt: Duration, rtt: Duration) {
+        let cwnd = self.cwnd as f64 / self.mss as f64;
+        let target = cwnd * base_rtt.as_secs_f64() / rtt.as_secs_f64();
+        let diff = cwnd - target;
+        if self.cwnd < self.ssthresh {
+            if diff > GAMMA {
+                // Queue is starting to build - leave slow start
+                self.cwnd = cmp::min(self.cwnd, ((target + 1.0) * self.mss as f64) as usize);
+                self.ssthresh = self.cwnd;
+            }
+        } else if diff > BETA {
+            self.cwnd -= self.mss;
+        } else if diff < ALPHA {
+            self.cwnd += self.mss;
+        }
+        self.cwnd = cmp::max(self.cwnd, 2 * self.mss);
+    }
+}
+
+impl CongestionControl for Vegas {
+    fn name(&self) -> &'static str {
+        "vegas"
+    }
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+    fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+    fn in_recovery(&self) -> bool {
+        self.recover.is_some()
+    }
+    fn on_ack(&mut self, ack: AckInfo) -> CcAction {
+        if let Some(recover) = self.recover {
+            if ack.ack_num >= recover {
+                // Full ACK - deflate the window and leave fast recovery
+                self.cwnd = cmp::min(self.ssthresh, cmp::max(ack.in_flight, self.mss) + self.mss);
+                self.recover = None;
+                return CcAction::Nothing;
+            }
+            // Partial ACK - the next hole needs retransmitting right away
+            self.cwnd = self.cwnd.saturating_sub(ack.acked);
+            if ack.acked >= self.mss {
+                self.cwnd += self.mss;
+            }
+            self.cwnd = cmp::max(self.cwnd, self.mss);
+            return CcAction::Retransmit;
+        }
+        if self.cwnd < self.ssthresh {
+            // Slow start
+            self.cwnd += cmp::min(ack.acked, self.mss);
+        }
+        if self.round_end.is_some_and(|round_end| ack.ack_num < round_end) {
+            return CcAction::Nothing;
+        }
+        // A round trip has passed
+        self.round_end = Some(ack.snd_nxt);
+        match (self.base_rtt, self.round_min_rtt.take()) {
+            (Some(base_rtt), Some(rtt)) => self.end_round(base_rtt, rtt),
+            // No samples to go off of - act like Reno, one segment per round trip
+            _ if self.cwnd >= self.ssthresh => self.cwnd += self.mss,
+            _ => {}
+        }
+        CcAction::Nothing
+    }
+    fn on_dup_ack(&mut self, dup_count: u32, snd_nxt: SeqNum, in_flight: usize) -> CcAction {
+        if self.in_recovery() {
+            self.cwnd += self.mss;
+            return CcAction::Nothing;
+        }
+        if dup_count == DUP_ACK_THRESHOLD {
+            self.ssthresh = loss_ssthresh(in_flight, self.mss);
+            self.cwnd = self.ssthresh + 3 * self.mss;
+            self.recover = Some(snd_nxt);
+            return CcAction::Retransmit;
+        }
+        CcAction::Nothing
+    }
+    fn on_rto(&mut self, in_flight: usize) {
+        self.ssthresh = loss_ssthresh(in_flight, self.mss);
+        self.cwnd = self.mss;
+        self.recover = None;
+    }
+    fn on_rtt_sample(&mut self, rtt: Duration) {
+        self.base_rtt = Some(self.base_rtt.map_or(rtt, |base_rtt| cmp::min(base_rtt, rtt)));
+        self.round_min_rtt = Some(self.round_min_rtt.map_or(rtt, |min_rtt| cmp::min(min_rtt, rtt)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 1000;
+
+    /// Runs one round trip in which every RTT sample equals the input RTT
+    fn round(cc: &mut Vegas, snd_nxt: &mut SeqNum, rtt: Duration) {
+        cc.on_rtt_sample(rtt);
+        *snd_nxt += cc.cwnd() as u32;
+        cc.on_ack(AckInfo {
+            acked: MSS,
+            ack_num: cc.round_end.unwrap_or_default(),
+            snd_nxt: *snd_nxt,
+            in_flight: 0,
+            rate: None,
+        });
+    }
+
+    #[test]
+    fn test_backs_off_as_the_queue_grows() {
+        let mut cc = Vegas::new(MSS);
+        let mut snd_nxt = SeqNum::new(0);
+        let base = Duration::from_millis(10);
+        round(&mut cc, &mut snd_nxt, base);
+        assert!(cc.cwnd() < cc.ssthresh());
+        // RTT doubling means half the window is sitting in a queue - slow start is over
+        cc.cwnd = 9 * MSS; // The round's ACK grows this to 10 segments
+        round(&mut cc, &mut snd_nxt, 2 * base);
+        assert_eq!(cc.ssthresh(), 6 * MSS);
+        assert_eq!(cc.cwnd(), 6 * MSS);
+        // Fewer than ALPHA segments queued - grow
+        round(&mut cc, &mut snd_nxt, base);
+        assert_eq!(cc.cwnd(), 7 * MSS);
+        // More than BETA segments queued - shrink
+        round(&mut cc, &mut snd_nxt, 4 * base);
+        assert_eq!(cc.cwnd(), 6 * MSS);
+        // In between - hold
+        round(&mut cc, &mut snd_nxt, base.mul_f64(1.5));
+        assert_eq!(cc.cwnd(), 6 * MSS);
+    }
+}
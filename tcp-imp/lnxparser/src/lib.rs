@@ -67,19 +67,14 @@ fn str_to_udp(input: &str) -> (Ipv4Addr, u16) {
     )
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, PartialEq)]
 pub enum RoutingType {
+    #[default]
     None,
     Static,
     Rip,
 }
 
-impl Default for RoutingType {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 impl TryFrom<&str> for RoutingType {
     type Error = ParserError;
 
@@ -101,21 +96,28 @@ pub struct InterfaceConfig {
     pub assigned_ip: Ipv4Addr,
     pub udp_addr: Ipv4Addr,
     pub udp_port: u16,
+    // Largest IP packet the link can carry, if not the default
+    pub mtu: Option<u16>,
 }
 
 impl TryFrom<Vec<&str>> for InterfaceConfig {
     type Error = ParserError;
 
     /// Create an `InterfaceConfig` from a vector of tokens
-    /// Format: interface <name> <virtual IP address>/<prefix> <UDP address>:<UDP port>
+    /// Format: interface <name> <virtual IP address>/<prefix> <UDP address>:<UDP port> [mtu <bytes>]
     fn try_from(tokens: Vec<&str>) -> Result<Self, ParserError> {
-        if tokens.len() != 4 {
+        if tokens.len() != 4 && tokens.len() != 6 {
             return Err(ParserError::BadFormat);
         }
 
         let name = String::from(tokens[1]);
         let assigned_prefix: Ipv4Net = tokens[2].parse()?;
         let (udp_addr, udp_port) = str_to_udp(tokens[3]);
+        let mtu = match tokens.get(4) {
+            Some(&"mtu") => Some(tokens[5].parse()?),
+            Some(_) => return Err(ParserError::MissingToken(String::from("mtu"))),
+            None => None,
+        };
 
         Ok(Self {
             name,
@@ -123,6 +125,7 @@ impl TryFrom<Vec<&str>> for InterfaceConfig {
             assigned_ip: assigned_prefix.addr(),
             udp_addr,
             udp_port,
+            mtu,
         })
     }
 }
@@ -181,8 +184,35 @@ pub struct IPConfig {
     pub rip_timeout_threshold: Option<u64>,
 
     // HOSTS ONLY: Timing parmeters for TCP (in milliseconds)
+    pub tcp_rto_initial: Option<u64>,
     pub tcp_rto_min: Option<u64>,
     pub tcp_rto_max: Option<u64>,
+    pub tcp_ack_delay: Option<u64>,
+    pub tcp_connect_timeout: Option<u64>,
+    pub tcp_user_timeout: Option<u64>,
+    pub tcp_keepalive_idle: Option<u64>,
+    pub tcp_keepalive_interval: Option<u64>,
+    pub tcp_linger: Option<u64>,
+    pub tcp_time_wait: Option<u64>,
+    pub tcp_zwp_interval: Option<u64>,
+
+    // HOSTS ONLY: Keepalive switch and probe count for TCP sockets
+    pub tcp_keepalive: Option<bool>,
+    pub tcp_keepalive_probes: Option<u32>,
+
+    // HOSTS ONLY: Retransmissions of a TCP segment before warning (R1) and giving up
+    pub tcp_warn_retransmissions: Option<u32>,
+    pub tcp_max_retransmissions: Option<u32>,
+
+    // HOSTS ONLY: Seed for TCP initial sequence numbers, for reproducible runs
+    pub tcp_isn_seed: Option<u64>,
+
+    // HOSTS ONLY: Default congestion control algorithm for TCP sockets
+    pub tcp_congestion_control: Option<String>,
+
+    // HOSTS ONLY: Default send and receive buffer sizes for TCP sockets (in bytes)
+    pub tcp_send_buffer: Option<usize>,
+    pub tcp_recv_buffer: Option<usize>,
 }
 
 impl IPConfig {
@@ -223,7 +253,7 @@ impl IPConfig {
             match self.parse_line(line) {
                 Ok(()) | Err(ParserError::InvalidLine(_, _)) => {}
                 Err(e) => return Err(ParserError::InvalidLine(String::from(line), Box::new(e))),
-            };
+            }
         }
         Ok(())
     }
@@ -344,11 +374,38 @@ impl IPConfig {
         }
 
         let property = tokens[1];
-        let value: u64 = tokens[2].parse()?;
+        let value = tokens[2];
 
         match property {
-            "rto-min" => self.tcp_rto_min = Some(value),
-            "rto-max" => self.tcp_rto_max = Some(value),
+            "rto-initial" => self.tcp_rto_initial = Some(value.parse()?),
+            "rto-min" => self.tcp_rto_min = Some(value.parse()?),
+            "rto-max" => self.tcp_rto_max = Some(value.parse()?),
+            "ack-delay" => self.tcp_ack_delay = Some(value.parse()?),
+            "connect-timeout" => self.tcp_connect_timeout = Some(value.parse()?),
+            "user-timeout" => self.tcp_user_timeout = Some(value.parse()?),
+            "keepalive" => {
+                self.tcp_keepalive = Some(match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => {
+                        return Err(ParserError::Other(format!(
+                            "Invalid keepalive setting: {value}"
+                        )));
+                    }
+                });
+            }
+            "keepalive-idle" => self.tcp_keepalive_idle = Some(value.parse()?),
+            "keepalive-interval" => self.tcp_keepalive_interval = Some(value.parse()?),
+            "keepalive-probes" => self.tcp_keepalive_probes = Some(value.parse()?),
+            "linger" => self.tcp_linger = Some(value.parse()?),
+            "time-wait" => self.tcp_time_wait = Some(value.parse()?),
+            "zwp-interval" => self.tcp_zwp_interval = Some(value.parse()?),
+            "warn-retransmissions" => self.tcp_warn_retransmissions = Some(value.parse()?),
+            "max-retransmissions" => self.tcp_max_retransmissions = Some(value.parse()?),
+            "isn-seed" => self.tcp_isn_seed = Some(value.parse()?),
+            "congestion-control" => self.tcp_congestion_control = Some(String::from(value)),
+            "send-buffer" => self.tcp_send_buffer = Some(value.parse()?),
+            "recv-buffer" => self.tcp_recv_buffer = Some(value.parse()?),
             _ => {
                 return Err(ParserError::Other(format!(
                     "Invalid TCP property: {property}"
@@ -398,6 +455,49 @@ tcp rto-max 3000
         assert_eq!(ip_config.tcp_rto_max, Some(3000));
     }
 
+    #[test]
+    fn test_parse_tcp() {
+        let mut config = IPConfig::default();
+        config
+            .parse_tcp(&["tcp", "congestion-control", "cubic"])
+            .unwrap();
+        assert_eq!(config.tcp_congestion_control, Some(String::from("cubic")));
+        config.parse_tcp(&["tcp", "rto-initial", "3000"]).unwrap();
+        assert_eq!(config.tcp_rto_initial, Some(3000));
+        config.parse_tcp(&["tcp", "ack-delay", "40"]).unwrap();
+        assert_eq!(config.tcp_ack_delay, Some(40));
+        config.parse_tcp(&["tcp", "connect-timeout", "5000"]).unwrap();
+        assert_eq!(config.tcp_connect_timeout, Some(5000));
+        config.parse_tcp(&["tcp", "keepalive", "on"]).unwrap();
+        assert_eq!(config.tcp_keepalive, Some(true));
+        config.parse_tcp(&["tcp", "keepalive-probes", "3"]).unwrap();
+        assert_eq!(config.tcp_keepalive_probes, Some(3));
+        assert!(config.parse_tcp(&["tcp", "keepalive", "yes"]).is_err());
+        config.parse_tcp(&["tcp", "time-wait", "1000"]).unwrap();
+        assert_eq!(config.tcp_time_wait, Some(1000));
+        config.parse_tcp(&["tcp", "max-retransmissions", "8"]).unwrap();
+        assert_eq!(config.tcp_max_retransmissions, Some(8));
+        config.parse_tcp(&["tcp", "linger", "0"]).unwrap();
+        assert_eq!(config.tcp_linger, Some(0));
+        config.parse_tcp(&["tcp", "isn-seed", "42"]).unwrap();
+        assert_eq!(config.tcp_isn_seed, Some(42));
+        config.parse_tcp(&["tcp", "recv-buffer", "1048576"]).unwrap();
+        assert_eq!(config.tcp_recv_buffer, Some(1_048_576));
+        assert!(config.parse_tcp(&["tcp", "rto-min", "cubic"]).is_err());
+        assert!(config.parse_tcp(&["tcp", "no-such-thing", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_interface_mtu() {
+        let mut config = IPConfig::default();
+        config
+            .parse("interface if0 10.0.0.1/24 127.0.0.1:5000 mtu 576\ninterface if1 10.1.0.1/24 127.0.0.1:5001\n")
+            .unwrap();
+        assert_eq!(config.interfaces[0].mtu, Some(576));
+        assert_eq!(config.interfaces[1].mtu, None);
+        assert!(InterfaceConfig::try_from(vec!["interface", "if0", "10.0.0.1/24", "127.0.0.1:5000", "mut", "576"]).is_err());
+    }
+
     #[test]
     fn test_str_to_udp() {
         let (ip, port) = str_to_udp("192.168.1.1:8080");
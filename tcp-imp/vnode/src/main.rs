@@ -15,7 +15,7 @@ fn main() {
     if &args[1] != "--config" {
         panic!("Config was not supplied or incorrectly supplied");
     }
-    let file_path = (&args[2]).clone();
+    let file_path = args[2].clone();
     //Initialize the IPDaemon
     let config_info: IPConfig = IPConfig::new(file_path);
     let (backend, ip_recver) = initialize(config_info).expect("Error initializing backend");
@@ -12,6 +12,7 @@ pub type CommandHandler = Box<dyn for<'a> Fn(&'a dyn VnodeBackend, Vec<String>)>
 
 pub enum NumArgs {
     Exactly(usize),
+    Between(usize, usize), //Inclusive on both ends - for commands with optional arguments
     Any
 }
 
@@ -31,7 +32,7 @@ pub trait VnodeRepl<Backend: VnodeBackend + 'static> where Self: 'static {
     fn get_all_commands(&self) -> Vec<(String, CommandData)>;
     //fn ip_listen(ip_recver: Receiver<Packet>) -> ();
     //Methods
-    fn init_command_table(&mut self) -> () {
+    fn init_command_table(&mut self) {
         let mut commands = self.get_all_commands();
         let command_table = self.command_table_mut();
         commands.drain(..).for_each(|(name, cd)| { command_table.insert(name, cd); });
@@ -41,7 +42,7 @@ pub trait VnodeRepl<Backend: VnodeBackend + 'static> where Self: 'static {
         let cd = CommandData { handler, num_args };
         self.command_table_mut().insert(name, cd);
     }*/
-    fn run_repl(&self) -> () {
+    fn run_repl(&self) {
         let mut ed = Editor::<(), DefaultHistory>::new().unwrap();
         loop {
             let cmd = ed.readline("> ");
@@ -56,7 +57,7 @@ pub trait VnodeRepl<Backend: VnodeBackend + 'static> where Self: 'static {
     }
     fn execute_command(&self, cmd: String) -> result::Result<(), String> {
         //Parse input into a command and list of arguments
-        let mut split_cmd: Vec<&str> = cmd.trim().split_whitespace().collect();
+        let mut split_cmd: Vec<&str> = cmd.split_whitespace().collect();
         if split_cmd.is_empty() { return Err(String::from("No command input")); }
         let cmd = split_cmd.remove(0).into();
         let args = split_cmd.iter().map(|&s| s.to_string()).collect();
@@ -71,7 +72,8 @@ pub trait VnodeRepl<Backend: VnodeBackend + 'static> where Self: 'static {
         };
         match cmd_data.num_args {
             NumArgs::Exactly(num) if num == args.len() => {},
-            NumArgs::Any if args.len() > 0 => {},
+            NumArgs::Between(min, max) if (min..=max).contains(&args.len()) => {},
+            NumArgs::Any if !args.is_empty() => {},
             _ => return Err(format!("Improper number of arguments for {cmd:?}"))
         }
         let backend = self.backend();
@@ -88,11 +90,11 @@ pub trait VnodeRepl<Backend: VnodeBackend + 'static> where Self: 'static {
                 return (addr, rest);
             }
             if !rest.is_empty() {
-                rest.push_str(" ");
+                rest.push(' ');
             }
             rest.push_str(s);
         }
-        return (addr, rest);
+        (addr, rest)
     }
     fn get_base_commands(&self) -> Vec<(String, CommandData)> {
         let mut base_commands = vec![
@@ -4,14 +4,17 @@ use crate::repl_trait::*;
 use library::backends::{HostBackend, RouterBackend};
 //use library::vnode_traits::VnodeBackend;
 use library::sockman_utils::*;
+use library::congestion::CongestionAlgorithm;
 use library::ip_handler::*;
+use library::net::{VTcpListener, VTcpStream};
 use library::utils::*;
 use library::vnode_traits::VnodeBackend; //Hopefully this can be removed in the future because this stuff shoud be private
 use std::thread;
 use std::sync::mpsc::Receiver;
 use std::sync::Arc;
+use std::time::Duration;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io;
 use std::path::{Path, PathBuf};
 use std::io::ErrorKind;
 
@@ -24,8 +27,6 @@ use std::io::ErrorKind;
 //Test retransmissions and ZWP together and with closing (test send/receive file)
 //Run performance test
 
-const READ_CHUNK: usize = 1380;
-
 pub struct HostRepl {
     pub backend: HostBackend,
     command_table: CommandTable
@@ -37,14 +38,22 @@ impl VnodeRepl<HostBackend> for HostRepl {
     fn command_table_mut(&mut self) -> &mut CommandTable { &mut self.command_table }
     fn get_all_commands(&self) -> Vec<(String, CommandData)> {
         let mut custom_commands = vec![
-            ("a".to_string(), CommandData { handler: Self::wrap_host_handler(Self::a_handler), num_args: NumArgs::Exactly(1) }), 
+            ("a".to_string(), CommandData { handler: Self::wrap_host_handler(Self::a_handler), num_args: NumArgs::Between(1, 2) }), 
             ("c".to_string(), CommandData { handler: Self::wrap_host_handler(Self::c_handler), num_args: NumArgs::Exactly(2) }), 
             ("ls".to_string(), CommandData { handler: Self::wrap_host_handler(Self::ls_handler), num_args: NumArgs::Exactly(0) }),
             ("s".to_string(), CommandData { handler: Self::wrap_host_handler(Self::s_handler), num_args: NumArgs::Exactly(2) }), 
             ("r".to_string(), CommandData { handler: Self::wrap_host_handler(Self::r_handler), num_args: NumArgs::Exactly(2) }),
-            ("sf".to_string(), CommandData { handler: Self::wrap_host_handler(Self::sf_handler), num_args: NumArgs::Exactly(3) }),
+            ("sf".to_string(), CommandData { handler: Self::wrap_host_handler(Self::sf_handler), num_args: NumArgs::Between(3, 4) }),
             ("rf".to_string(), CommandData { handler: Self::wrap_host_handler(Self::rf_handler), num_args: NumArgs::Exactly(2) }),
-            ("cl".to_string(), CommandData { handler: Self::wrap_host_handler(Self::cl_handler), num_args: NumArgs::Exactly(1) })
+            ("cl".to_string(), CommandData { handler: Self::wrap_host_handler(Self::cl_handler), num_args: NumArgs::Exactly(1) }),
+            ("cc".to_string(), CommandData { handler: Self::wrap_host_handler(Self::cc_handler), num_args: NumArgs::Exactly(2) }),
+            ("ss".to_string(), CommandData { handler: Self::wrap_host_handler(Self::ss_handler), num_args: NumArgs::Exactly(1) }),
+            ("nd".to_string(), CommandData { handler: Self::wrap_host_handler(Self::nd_handler), num_args: NumArgs::Exactly(2) }),
+            ("ka".to_string(), CommandData { handler: Self::wrap_host_handler(Self::ka_handler), num_args: NumArgs::Exactly(2) }),
+            ("sd".to_string(), CommandData { handler: Self::wrap_host_handler(Self::sd_handler), num_args: NumArgs::Exactly(2) }),
+            ("ab".to_string(), CommandData { handler: Self::wrap_host_handler(Self::ab_handler), num_args: NumArgs::Exactly(1) }),
+            ("lg".to_string(), CommandData { handler: Self::wrap_host_handler(Self::lg_handler), num_args: NumArgs::Exactly(2) }),
+            ("bs".to_string(), CommandData { handler: Self::wrap_host_handler(Self::bs_handler), num_args: NumArgs::Exactly(3) })
         ];
         let mut all_commands = self.get_base_commands();
         all_commands.append(&mut custom_commands);
@@ -56,7 +65,7 @@ impl HostRepl {
     pub fn new(backend: HostBackend) -> HostRepl {
         HostRepl { backend, command_table: HashMap::new() }
     }
-    pub fn run(mut self, ip_recver: Receiver<Packet>) -> () { //Can't be put in trait because of weird size issue
+    pub fn run(mut self, ip_recver: Receiver<Packet>) { //Can't be put in trait because of weird size issue
         self.init_command_table();
         let backend = &self.backend;
         let socket_table = Arc::clone(&backend.socket_table);
@@ -66,22 +75,27 @@ impl HostRepl {
         self.run_repl();
     }
     //Additional command handlers
-    pub fn a_handler(backend: &HostBackend, args: Vec<String>) -> () {
+    pub fn a_handler(backend: &HostBackend, args: Vec<String>) {
         //Sanititize input
         let port = if let Ok(port) = args[0].parse::<u16>() { port } else { return println!("Input port \"{}\" invalid", args[0]) };
+        let backlog = match args.get(1) {
+            Some(backlog) => if let Ok(backlog) = backlog.parse::<usize>() { backlog } else { return println!("Input backlog \"{backlog}\" invalid") },
+            None => DEFAULT_BACKLOG,
+        };
         //Listen on a port and then immediately accept on that port
-        backend.listen(port.clone());
+        if let Err(e) = backend.listen(port, backlog) { return println!("{e}") };
         backend.accept(port);
     }
 
-    pub fn c_handler(backend: &HostBackend, args: Vec<String>) -> () {
+    pub fn c_handler(backend: &HostBackend, args: Vec<String>) {
         //Sanitize input
         let ip_addr = if let Ok(ip_addr) = args[0].parse::<Ipv4Addr>() { ip_addr } else { return println!("Input IP address \"{}\" invalid", args[0]) };
         let port = if let Ok(port) = args[1].parse::<u16>() { port } else { return println!("Input IP address \"{}\" invalid", args[1]) };
-        //Connect on an ip and port
-        backend.connect(ip_addr, port);
+        //Connect on an ip and port - in the background, as it can take a while to give up
+        let backend_clone = backend.clone();
+        thread::spawn(move || if let Err(e) = backend_clone.connect(ip_addr, port) { println!("Unable to connect to {ip_addr}:{port}: {e}") });
     }
-    pub fn ls_handler(backend: &HostBackend, _args: Vec<String>) -> () {
+    pub fn ls_handler(backend: &HostBackend, _args: Vec<String>) {
         let socket_table = backend.socket_table();
         println!("SID\tLAddr\t\tLPort\tRAddr\t\tRPort\tState");
         for (sid, ent) in &*socket_table {
@@ -92,17 +106,17 @@ impl HostRepl {
             println!("{}", to_print);
         }
     }
-    pub fn s_handler(backend: &HostBackend, args: Vec<String>) -> () {
+    pub fn s_handler(backend: &HostBackend, args: Vec<String>) {
         //Sanitize input
         let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
         let data = <String as Clone>::clone(&args[1]).into_bytes();
         //Send data and print result
         match backend.tcp_send(sid, data) {
             Ok(bytes_sent) => println!("Sent {bytes_sent} bytes"),
-            Err(e) => println!("{}", e.to_string())
+            Err(e) => println!("{}", e)
         }
     }
-    pub fn r_handler(backend: &HostBackend, args: Vec<String>) -> () {
+    pub fn r_handler(backend: &HostBackend, args: Vec<String>) {
         //Sanitize input
         let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
         let bytes = if let Ok(bytes) = args[1].parse::<u16>() { bytes } else { return println!("Input number of bytes to read {} cannot parse to u16 - make sure it's less than 2^16", args[1]) };
@@ -115,10 +129,10 @@ impl HostRepl {
                 };
                 println!("Received {} bytes. As a string, they are:\n{}", msg.len(), msg)
             },
-            Err(e) => println!("{}", e.to_string())
+            Err(e) => println!("{}", e)
         }
     }
-    pub fn sf_handler(backend: &HostBackend, args: Vec<String>) -> () {
+    pub fn sf_handler(backend: &HostBackend, args: Vec<String>) {
         //Sanitize input 
         let filepath: PathBuf = {
             if let Ok(path) = Path::new(&args[0]).canonicalize() {
@@ -134,9 +148,11 @@ impl HostRepl {
         };
         let ip_addr = if let Ok(ip_addr) = args[1].parse::<Ipv4Addr>() { ip_addr } else { return eprintln!("Input IP address \"{}\" invalid", args[1]) };
         let port = if let Ok(port) = args[2].parse::<u16>() { port } else { return eprintln!("Input port \"{}\" invalid", args[2]) };
+        //Optionally send with a different congestion control algorithm (i.e. ledbat for background transfers)
+        let algo = match args.get(3).map(|algo| algo.parse::<CongestionAlgorithm>()) { Some(Ok(algo)) => Some(algo), Some(Err(e)) => return eprintln!("{e}"), None => None };
         //Spawn thread to do the actual sending
         let backend_clone = backend.clone(); //Again, kinda kludgy, but best we can do at the moment
-        thread::spawn(move || Self::send_file(backend_clone, filepath, ip_addr, port));
+        thread::spawn(move || Self::send_file(backend_clone, filepath, ip_addr, port, algo));
         //Spawn a thread that...
         //First calls backend.connect() on ip address and port number
         //Then loops through... 
@@ -145,33 +161,29 @@ impl HostRepl {
         //Continues to do this until we've read the entire file
         //Closes the connection 
     }
-    fn send_file(backend: HostBackend, filepath: PathBuf, ip_addr: Ipv4Addr, port: u16) {
+    fn send_file(backend: HostBackend, filepath: PathBuf, ip_addr: Ipv4Addr, port: u16, algo: Option<CongestionAlgorithm>) {
         // Open the file
         let mut file = match File::open(filepath) {
             Ok(file) => file,
             Err(e) => return eprintln!("Unable to open file: {}", e)
         };
-        // 1 kb buffer for reading into send
-        let mut buf: Vec<u8> = vec![0u8; READ_CHUNK];
         // Call connect and establish a connection on the inputted ip and port
-        let sid = backend.connect(ip_addr, port);
-        //Let the sending begin!
-        let mut total_bytes_sent: u32 = 0;  
-        loop { 
-            let bytes_read = file.read(&mut buf).unwrap();
-            if bytes_read == 0 { break; } //We have reached EOF
-            total_bytes_sent += match backend.tcp_send(sid, buf[..bytes_read].to_vec()) {
-                Ok(bytes_sent) => bytes_sent,
-                Err(e) => panic!("{}", e.to_string())
-            };
-            //  println!("Current total bytes sent: {}", total_bytes_sent);
+        let mut stream = match VTcpStream::connect(&backend, ip_addr, port) {
+            Ok(stream) => stream,
+            Err(e) => return eprintln!("Unable to connect to {ip_addr}:{port}: {e}")
+        };
+        if let Some(algo) = algo {
+            if let Err(e) = backend.set_congestion_control(stream.sid(), algo) { return eprintln!("{e}") }
         }
-        backend.close(sid).expect("Somehow connection socket already got removed from socket table...");
-        // println!("Iter count: {iter_cnt}");
+        //Let the sending begin!
+        let total_bytes_sent = match io::copy(&mut file, &mut stream) {
+            Ok(bytes_sent) => bytes_sent,
+            Err(e) => return eprintln!("Sending file failed: {e}") //Connection got aborted
+        };
+        if let Err(e) = stream.close() { return eprintln!("Closing after sending {total_bytes_sent} bytes failed: {e}") } //Linger ran out
         println!("Sent {total_bytes_sent} bytes");
-
     }
-    pub fn rf_handler(backend: &HostBackend, args: Vec<String>) -> () {
+    pub fn rf_handler(backend: &HostBackend, args: Vec<String>) {
         //Sanitize input
         let path = Path::new(&args[0]);
         let file = match File::create(path) {
@@ -185,28 +197,90 @@ impl HostRepl {
         thread::spawn(move || Self::receive_file(backend_clone, file, port));
     }
     fn receive_file(backend: HostBackend, mut file: File, port: u16) {
-        backend.listen(port);
-        let sid =  backend.accept1(port).expect("No listener socket with input port found...");
-        let mut total_bytes_read = 0;
-        loop {
-            let data = match backend.tcp_recieve(sid, READ_CHUNK as u16) {
-                Ok(data) => data,
-                Err(ref e) if e.kind() == ErrorKind::InvalidInput => panic!("Socket at sid we were given by accept1() doesn't exist..."),
-                Err(ref e) if e.kind() == ErrorKind::Unsupported => break, //We can't receive anymore - this is expected behavior for when there is nothing left to receive
-                Err(e) => panic!("Received error: {e:?}")
-            };
-            file.write_all(&data).unwrap();
-            total_bytes_read += data.len();
+        let listener = match VTcpListener::bind(&backend, port) {
+            Ok(listener) => listener,
+            Err(e) => return eprintln!("Unable to listen on port {port}: {e}")
+        };
+        let (mut stream, _) = match listener.accept() {
+            Ok(conn) => conn,
+            Err(e) => return eprintln!("Unable to accept on port {port}: {e}")
+        };
+        listener.into_sid(); //The listener stays open, the way rf's always has - cl closes it
+        match io::copy(&mut stream, &mut file) {
+            Ok(total_bytes_read) => println!("Read {total_bytes_read} bytes"),
+            Err(e) => eprintln!("Receiving file failed: {e}") //Connection got aborted
         }
-        println!("We're getting here...");
-        backend.close(sid).expect("No socket at the sid we just got back from accept1()...");
-        println!("Read {total_bytes_read} bytes");
     }
-    pub fn cl_handler(backend: &HostBackend, args: Vec<String>) -> () {
+    pub fn cl_handler(backend: &HostBackend, args: Vec<String>) {
         //Sanitize input
         let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
         //Make the backend close that socket
-        if let Err(e) = backend.close(sid) { println!("{}", e.to_string())};
+        if let Err(e) = backend.close(sid) { println!("{}", e)};
+    }
+    pub fn sd_handler(backend: &HostBackend, args: Vec<String>) {
+        //Sanitize input
+        let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
+        let how = match args[1].parse::<How>() { Ok(how) => how, Err(e) => return println!("{e}") };
+        if let Err(e) = backend.shutdown(sid, how) { println!("{e}") };
+    }
+    pub fn ab_handler(backend: &HostBackend, args: Vec<String>) {
+        //Sanitize input
+        let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
+        if let Err(e) = backend.abort(sid) { println!("{e}") };
+    }
+    pub fn lg_handler(backend: &HostBackend, args: Vec<String>) {
+        //Sanitize input
+        let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
+        let linger = match args[1].as_str() {
+            "off" => None,
+            millis => if let Ok(millis) = millis.parse::<u64>() { Some(Duration::from_millis(millis)) } else { return println!("Expected milliseconds or off, got \"{millis}\"") },
+        };
+        if let Err(e) = backend.set_linger(sid, linger) { println!("{e}") };
+    }
+    pub fn cc_handler(backend: &HostBackend, args: Vec<String>) {
+        //Sanitize input
+        let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
+        let algo = match args[1].parse::<CongestionAlgorithm>() { Ok(algo) => algo, Err(e) => return println!("{e}") };
+        //Swap out the socket's congestion control algorithm
+        if let Err(e) = backend.set_congestion_control(sid, algo) { println!("{e}") };
+    }
+    pub fn nd_handler(backend: &HostBackend, args: Vec<String>) {
+        //Sanitize input
+        let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
+        let nodelay = match args[1].as_str() { "on" => true, "off" => false, other => return println!("Expected on or off, got \"{other}\"") };
+        if let Err(e) = backend.set_nodelay(sid, nodelay) { println!("{e}") };
+    }
+    pub fn ka_handler(backend: &HostBackend, args: Vec<String>) {
+        //Sanitize input
+        let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
+        let keepalive = match args[1].as_str() { "on" => true, "off" => false, other => return println!("Expected on or off, got \"{other}\"") };
+        if let Err(e) = backend.set_keepalive(sid, keepalive) { println!("{e}") };
+    }
+    pub fn bs_handler(backend: &HostBackend, args: Vec<String>) {
+        //Sanitize input
+        let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
+        let size = if let Ok(size) = args[2].parse::<usize>() { size } else { return println!("Input buffer size {} invalid", args[2]) };
+        let outcome = match args[1].as_str() {
+            "send" => backend.set_send_buffer(sid, size),
+            "recv" => backend.set_recv_buffer(sid, size),
+            other => return println!("Expected send or recv, got \"{other}\""),
+        };
+        if let Err(e) = outcome { println!("{e}") };
+    }
+    pub fn ss_handler(backend: &HostBackend, args: Vec<String>) {
+        //Sanitize input
+        let sid = if let Ok(sid) = args[0].parse::<SocketId>() { sid } else { return println!("Input socket ID {} invalid", args[0]) };
+        let stats = match backend.socket_stats(sid) { Ok(stats) => stats, Err(e) => return println!("{e}") };
+        let ssthresh = if stats.ssthresh == usize::MAX { "-".to_string() } else { stats.ssthresh.to_string() };
+        let srtt = stats.srtt.map_or("-".to_string(), |srtt| format!("{srtt:?}"));
+        println!("State: {:?}\tCC: {}{}", stats.state, stats.cc_name, if stats.in_recovery { " (recovering)" } else { "" });
+        println!("cwnd: {}\tssthresh: {}\tin flight: {}\trwnd: {}", stats.cwnd, ssthresh, stats.in_flight, stats.rem_window);
+        println!("srtt: {}\trto: {:?}\tmss: {}", srtt, stats.rto, stats.mss);
+        println!("send buffer: {}\trecv buffer: {}", stats.send_buffer, stats.recv_buffer);
+        if let Some(model) = stats.model {
+            let min_rtt = model.min_rtt.map_or("-".to_string(), |min_rtt| format!("{min_rtt:?}"));
+            println!("Mode: {}\tbtl_bw: {:.0} B/s\tmin_rtt: {}\tpacing rate: {:.0} B/s\tpacing gain: {:.2}\tcwnd gain: {:.2}", model.mode, model.btl_bw, min_rtt, model.pacing_rate, model.pacing_gain, model.cwnd_gain);
+        }
     }
     fn wrap_host_handler<F>(f: F) -> CommandHandler
     where
@@ -238,12 +312,12 @@ impl RouterRepl {
     pub fn new(backend: RouterBackend) -> RouterRepl {
         RouterRepl { backend, command_table: HashMap::new() }
     }
-    pub fn run(mut self, ip_recver: Receiver<Packet>) -> () {
+    pub fn run(mut self, ip_recver: Receiver<Packet>) {
         self.init_command_table();
         thread::spawn(move || Self::ip_listen(ip_recver));
         self.run_repl();
     }
-    fn ip_listen(ip_recver: Receiver<Packet>) -> () {
+    fn ip_listen(ip_recver: Receiver<Packet>) {
         loop {
             let pack = ip_recver.recv().expect("Error receiving packet from IP Daemon");
             let src = Self::string_ip(pack.header.source);